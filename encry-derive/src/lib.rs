@@ -0,0 +1,216 @@
+//! Derive macro for field-level encryption with encry's `KryptorService`.
+//!
+//! `#[derive(EncryptFields)]` on a struct with named fields generates a
+//! companion `<Name>Encrypted` struct and an `EncryptFields` impl. Field
+//! attributes select how each field is stored:
+//!
+//! - `#[encrypt]` randomized AEAD encryption
+//! - `#[encrypt(deterministic)]` deterministic AES-SIV encryption
+//! - `#[blind_index]` / `#[blind_index(name = "...", normalize = "...")]` adds a
+//!   `<field>_index` column holding a blind index of the plaintext
+//!
+//! Fields without attributes are copied through in plaintext.
+//!
+//! Generated code names runtime items through `::encry`, so the deriving
+//! crate must depend on `encry`.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{Data, DeriveInput, Fields, LitStr, parse_macro_input};
+
+const NORMALIZERS: &[&str] = &["trim", "lowercase", "email", "e164"];
+
+#[proc_macro_derive(EncryptFields, attributes(encrypt, blind_index))]
+pub fn derive_encrypt_fields(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input).unwrap_or_else(|e| e.to_compile_error()).into()
+}
+
+enum Mode {
+    Plain,
+    Randomized,
+    Deterministic,
+}
+
+struct BlindIndexAttr {
+    name: String,
+    normalize: Option<String>,
+}
+
+struct FieldSpec {
+    ident: syn::Ident,
+    ty: syn::Type,
+    mode: Mode,
+    blind_index: Option<BlindIndexAttr>,
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "EncryptFields does not support generic structs",
+        ));
+    }
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(named) => &named.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "EncryptFields requires a struct with named fields",
+                ));
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "EncryptFields can only be derived for structs",
+            ));
+        }
+    };
+
+    let specs = fields.iter().map(parse_field).collect::<syn::Result<Vec<_>>>()?;
+
+    let name = &input.ident;
+    let vis = &input.vis;
+    let encrypted_name = format_ident!("{}Encrypted", name);
+    let fields_path = quote!(::encry::kryptor::fields);
+    let error_path = quote!(::encry::kryptor::errors::EncryptionError);
+
+    let mut struct_fields = Vec::new();
+    let mut encrypt_inits = Vec::new();
+    let mut decrypt_inits = Vec::new();
+
+    for spec in &specs {
+        let ident = &spec.ident;
+        let ty = &spec.ty;
+        let label = format!("{}.{}", name, ident);
+
+        match spec.mode {
+            Mode::Plain => {
+                struct_fields.push(quote!(pub #ident: #ty));
+                encrypt_inits.push(quote!(#ident: ::std::clone::Clone::clone(&self.#ident)));
+                decrypt_inits.push(quote!(#ident: ::std::clone::Clone::clone(&encrypted.#ident)));
+            }
+            Mode::Randomized => {
+                struct_fields.push(quote!(pub #ident: ::std::string::String));
+                encrypt_inits.push(quote!(#ident: encryptor.encrypt_field(#label, &self.#ident)?));
+                decrypt_inits.push(quote!(#ident: encryptor.decrypt_field(#label, &encrypted.#ident)?));
+            }
+            Mode::Deterministic => {
+                struct_fields.push(quote!(pub #ident: ::std::string::String));
+                encrypt_inits.push(quote!(#ident: encryptor.encrypt_field_deterministic(#label, &self.#ident)?));
+                decrypt_inits.push(quote!(#ident: encryptor.decrypt_field_deterministic(#label, &encrypted.#ident)?));
+            }
+        }
+
+        if let Some(index) = &spec.blind_index {
+            let index_ident = format_ident!("{}_index", ident);
+            let index_name = &index.name;
+            let normalize = match &index.normalize {
+                Some(normalize) => quote!(::std::option::Option::Some(#normalize)),
+                None => quote!(::std::option::Option::None),
+            };
+            struct_fields.push(quote!(pub #index_ident: <#ty as #fields_path::BlindIndexValue>::Index));
+            encrypt_inits.push(quote!(#index_ident: encryptor.blind_index(#index_name, #normalize, &self.#ident)?));
+        }
+    }
+
+    let doc = format!(
+        "Storage form of [`{}`] produced by `#[derive(EncryptFields)]`",
+        name
+    );
+
+    Ok(quote! {
+        #[doc = #doc]
+        #[derive(Debug, Clone, ::serde::Serialize, ::serde::Deserialize)]
+        #vis struct #encrypted_name {
+            #(#struct_fields,)*
+        }
+
+        impl #fields_path::EncryptFields for #name {
+            type Encrypted = #encrypted_name;
+
+            fn encrypt_fields(
+                &self,
+                encryptor: &mut #fields_path::FieldEncryptor,
+            ) -> ::std::result::Result<Self::Encrypted, #error_path> {
+                ::std::result::Result::Ok(#encrypted_name {
+                    #(#encrypt_inits,)*
+                })
+            }
+
+            fn decrypt_fields(
+                encrypted: &Self::Encrypted,
+                encryptor: &mut #fields_path::FieldEncryptor,
+            ) -> ::std::result::Result<Self, #error_path> {
+                ::std::result::Result::Ok(Self {
+                    #(#decrypt_inits,)*
+                })
+            }
+        }
+    })
+}
+
+fn parse_field(field: &syn::Field) -> syn::Result<FieldSpec> {
+    let ident = field.ident.clone().expect("named field");
+    let mut mode = Mode::Plain;
+    let mut blind_index = None;
+
+    for attr in &field.attrs {
+        if attr.path().is_ident("encrypt") {
+            if !matches!(mode, Mode::Plain) {
+                return Err(syn::Error::new_spanned(attr, "duplicate #[encrypt] attribute"));
+            }
+            mode = Mode::Randomized;
+            if matches!(attr.meta, syn::Meta::List(_)) {
+                attr.parse_nested_meta(|meta| {
+                    if meta.path.is_ident("deterministic") {
+                        mode = Mode::Deterministic;
+                        Ok(())
+                    } else {
+                        Err(meta.error("expected `deterministic`"))
+                    }
+                })?;
+            }
+        } else if attr.path().is_ident("blind_index") {
+            if blind_index.is_some() {
+                return Err(syn::Error::new_spanned(attr, "duplicate #[blind_index] attribute"));
+            }
+            let mut index = BlindIndexAttr {
+                name: ident.to_string(),
+                normalize: None,
+            };
+            if matches!(attr.meta, syn::Meta::List(_)) {
+                attr.parse_nested_meta(|meta| {
+                    if meta.path.is_ident("name") {
+                        index.name = meta.value()?.parse::<LitStr>()?.value();
+                        Ok(())
+                    } else if meta.path.is_ident("normalize") {
+                        let value = meta.value()?.parse::<LitStr>()?;
+                        if !NORMALIZERS.contains(&value.value().as_str()) {
+                            return Err(syn::Error::new_spanned(
+                                &value,
+                                format!("unknown normalizer; expected one of {}", NORMALIZERS.join(", ")),
+                            ));
+                        }
+                        index.normalize = Some(value.value());
+                        Ok(())
+                    } else {
+                        Err(meta.error("expected `name` or `normalize`"))
+                    }
+                })?;
+            }
+            blind_index = Some(index);
+        }
+    }
+
+    Ok(FieldSpec {
+        ident,
+        ty: field.ty.clone(),
+        mode,
+        blind_index,
+    })
+}
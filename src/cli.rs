@@ -0,0 +1,780 @@
+use std::cell::RefCell;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use base64::{Engine as _, engine::general_purpose};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use encry::kryptor::algorithm::Algorithm;
+use encry::kryptor::compression::{Compression, CompressionPolicy, DEFAULT_COMPRESSION_THRESHOLD};
+use encry::kryptor::config::{ConfigLoader, ENV_IKM};
+use encry::kryptor::encoding::TextEncoding;
+use encry::kryptor::envelope::Envelope;
+use encry::kryptor::errors::EncryptionError;
+use encry::kryptor::files;
+use encry::kryptor::keyring::{KeyEntry, KeyStatus, Keyring};
+use encry::kryptor::passphrase::{Argon2Params, KeyDescriptor};
+use encry::kryptor::rewrap::{RewrapItem, RewrapOutcome, RewrapProgress, Rewrapper};
+use encry::kryptor::secret::{SecretKey, SecretString};
+use encry::kryptor::stream::{self, STREAM_MAGIC, STREAM_VERSION, StreamHeader};
+use encry::kryptor::utilities::{EncryptedData, KryptorService};
+use encry::models::EncryptionContext;
+
+pub type Result<T> = std::result::Result<T, EncryptionError>;
+
+/// Encrypt, decrypt and inspect encry ciphertexts
+#[derive(Debug, Parser)]
+#[command(name = "encry", version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Encrypt a file or stdin
+    Encrypt(EncryptArgs),
+    /// Decrypt a ciphertext, package or stream
+    Decrypt(DecryptArgs),
+    /// Generate a new random IKM
+    Keygen(KeygenArgs),
+    /// Show the envelope or stream header of a ciphertext without decrypting it
+    Inspect(InspectArgs),
+    /// Re-encrypt ciphertexts (one per line) under the active key
+    Rewrap(RewrapArgs),
+    /// Run the built-in encryption demos
+    Demo(DemoArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct IoArgs {
+    /// Input file; reads stdin when omitted
+    #[arg(long = "in", short = 'i')]
+    pub input: Option<PathBuf>,
+    /// Output file; writes stdout when omitted
+    #[arg(long = "out", short = 'o')]
+    pub output: Option<PathBuf>,
+}
+
+/// Key sources, in order of precedence: `--key-file`, `ENCRY_KEY_FILE`, the
+/// variable named by `--key-env`, then the config file
+#[derive(Debug, Args)]
+pub struct KeyArgs {
+    /// File holding a base64 IKM, a JSON keyring or a passphrase key descriptor [env: ENCRY_KEY_FILE]
+    #[arg(long)]
+    pub key_file: Option<PathBuf>,
+    /// File whose first line is the passphrase for a key descriptor; ENCRY_PASSPHRASE is used otherwise
+    #[arg(long)]
+    pub passphrase_file: Option<PathBuf>,
+    /// Environment variable holding a base64 IKM
+    #[arg(long, default_value = ENV_IKM)]
+    pub key_env: String,
+    /// TOML or JSON config file [env: ENCRY_CONFIG]
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+    /// Key id recorded for a bare base64 IKM [env: ENCRY_KEY_ID, default: default]
+    #[arg(long)]
+    pub key_id: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct DemoArgs {
+    /// Use the built-in development key instead of loading one
+    #[arg(long)]
+    pub dev: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ContextArgs {
+    /// Encryption context string, as used by `EncryptionContext::new` (e.g. the aggregate key)
+    #[arg(long, short = 'c', conflicts_with = "context_json")]
+    pub context: Option<String>,
+    /// Encryption context as compact JSON, exactly as the application serializes it
+    #[arg(long)]
+    pub context_json: Option<String>,
+    /// Associated data the ciphertext is bound to, e.g. `["<aggregate>","Profile",0]`
+    #[arg(long)]
+    pub aad: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct EncryptArgs {
+    #[command(flatten)]
+    pub io: IoArgs,
+    #[command(flatten)]
+    pub keys: KeyArgs,
+    #[command(flatten)]
+    pub context: ContextArgs,
+    #[arg(long, value_enum, default_value_t = AlgorithmArg::Aes256Gcm)]
+    pub algorithm: AlgorithmArg,
+    /// Text encoding of the envelope
+    #[arg(long, value_enum, default_value_t = EncodingArg::Base64)]
+    pub encoding: EncodingArg,
+    /// Write the raw envelope bytes instead of text
+    #[arg(long, conflicts_with_all = ["encoding", "stream"])]
+    pub binary: bool,
+    /// Compress inputs of at least `--compress-threshold` bytes before encryption.
+    /// Only for data an attacker cannot partly choose; see the README.
+    #[arg(long, value_enum, conflicts_with = "stream")]
+    pub compress: Option<CompressionArg>,
+    #[arg(long, requires = "compress", default_value_t = DEFAULT_COMPRESSION_THRESHOLD)]
+    pub compress_threshold: usize,
+    /// Write a binary chunked stream instead of a base64 envelope; for large files
+    #[arg(long, conflicts_with = "aad")]
+    pub stream: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct DecryptArgs {
+    #[command(flatten)]
+    pub io: IoArgs,
+    #[command(flatten)]
+    pub keys: KeyArgs,
+    #[command(flatten)]
+    pub context: ContextArgs,
+    /// Decrypt a bare pre-envelope `[IV | Ciphertext | Tag]` blob
+    #[arg(long)]
+    pub legacy: bool,
+    /// Treat the plaintext as `encrypt_json` output and pretty-print it
+    #[arg(long)]
+    pub json: bool,
+    /// Text encoding of the envelope; raw envelopes and streams are detected
+    #[arg(long, value_enum, default_value_t = EncodingArg::Base64)]
+    pub encoding: EncodingArg,
+}
+
+#[derive(Debug, Args)]
+pub struct InspectArgs {
+    #[command(flatten)]
+    pub io: IoArgs,
+    /// Text encoding of the envelope; raw envelopes, packages and streams are detected
+    #[arg(long, value_enum, default_value_t = EncodingArg::Base64)]
+    pub encoding: EncodingArg,
+}
+
+#[derive(Debug, Args)]
+pub struct KeygenArgs {
+    /// Print a keyring entry with this key id instead of a bare base64 IKM
+    #[arg(long, conflicts_with = "descriptor")]
+    pub entry: Option<String>,
+    /// Print an Argon2id key descriptor with this key id; the IKM is later
+    /// derived from a passphrase, so no key material is written
+    #[arg(long)]
+    pub descriptor: Option<String>,
+    /// Argon2id memory cost in KiB
+    #[arg(long, requires = "descriptor", default_value_t = Argon2Params::default().memory_kib)]
+    pub memory_kib: u32,
+    /// Argon2id iterations
+    #[arg(long, requires = "descriptor", default_value_t = Argon2Params::default().iterations)]
+    pub iterations: u32,
+    /// Argon2id lanes
+    #[arg(long, requires = "descriptor", default_value_t = Argon2Params::default().parallelism)]
+    pub parallelism: u32,
+    /// Output file; writes stdout when omitted
+    #[arg(long = "out", short = 'o')]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct RewrapArgs {
+    #[command(flatten)]
+    pub io: IoArgs,
+    #[command(flatten)]
+    pub keys: KeyArgs,
+    #[command(flatten)]
+    pub context: ContextArgs,
+    /// Text encoding of bare ciphertext lines; packages are always base64
+    #[arg(long, value_enum, default_value_t = EncodingArg::Base64)]
+    pub encoding: EncodingArg,
+    /// Checkpoint file; an interrupted job resumes from it and appends to `--out`
+    #[arg(long)]
+    pub progress: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum AlgorithmArg {
+    Aes256Gcm,
+    Chacha20Poly1305,
+    Xchacha20Poly1305,
+    Aes256GcmSiv,
+}
+
+impl From<AlgorithmArg> for Algorithm {
+    fn from(arg: AlgorithmArg) -> Self {
+        match arg {
+            AlgorithmArg::Aes256Gcm => Algorithm::Aes256Gcm,
+            AlgorithmArg::Chacha20Poly1305 => Algorithm::ChaCha20Poly1305,
+            AlgorithmArg::Xchacha20Poly1305 => Algorithm::XChaCha20Poly1305,
+            AlgorithmArg::Aes256GcmSiv => Algorithm::Aes256GcmSiv,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum EncodingArg {
+    Base64,
+    Base64url,
+    Hex,
+}
+
+impl From<EncodingArg> for TextEncoding {
+    fn from(arg: EncodingArg) -> Self {
+        match arg {
+            EncodingArg::Base64 => TextEncoding::Base64,
+            EncodingArg::Base64url => TextEncoding::Base64UrlNoPad,
+            EncodingArg::Hex => TextEncoding::Hex,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum CompressionArg {
+    Zstd,
+    Deflate,
+}
+
+impl From<CompressionArg> for Compression {
+    fn from(arg: CompressionArg) -> Self {
+        match arg {
+            CompressionArg::Zstd => Compression::Zstd,
+            CompressionArg::Deflate => Compression::Deflate,
+        }
+    }
+}
+
+/// Process exit codes; clap itself exits with 2 on usage errors
+pub mod exit {
+    pub const SUCCESS: u8 = 0;
+    /// I/O failure or malformed input
+    pub const FAILURE: u8 = 1;
+    /// Missing, unknown, retired or malformed keys
+    pub const KEY_ERROR: u8 = 3;
+    /// Authentication failed: wrong key, context or associated data, or tampered data
+    pub const DECRYPTION_FAILED: u8 = 4;
+    /// Rewrap finished but some items could not be rewrapped
+    pub const PARTIAL_FAILURE: u8 = 5;
+}
+
+/// Maps an error to the exit code reported for it
+pub fn exit_code(err: &EncryptionError) -> u8 {
+    match err {
+        EncryptionError::UnknownKey(_)
+        | EncryptionError::KeyRetired(_)
+        | EncryptionError::KeyShredded(_)
+        | EncryptionError::InvalidKeyring(_)
+        | EncryptionError::InvalidConfig(_)
+        | EncryptionError::HkdfError(_) => exit::KEY_ERROR,
+        EncryptionError::DecryptionError(_) | EncryptionError::AesGcmError(_) => exit::DECRYPTION_FAILED,
+        // Stream readers report crypto failures through `io::Error`
+        EncryptionError::IoError(e) => match e.get_ref().and_then(|inner| inner.downcast_ref::<EncryptionError>()) {
+            Some(inner) => exit_code(inner),
+            None => exit::FAILURE,
+        },
+        _ => exit::FAILURE,
+    }
+}
+
+/// Runs a non-demo subcommand and reports errors on stderr
+pub fn execute(command: Command) -> ExitCode {
+    let result = match command {
+        Command::Encrypt(args) => encrypt(args).map(|_| exit::SUCCESS),
+        Command::Decrypt(args) => decrypt(args).map(|_| exit::SUCCESS),
+        Command::Keygen(args) => keygen(args).map(|_| exit::SUCCESS),
+        Command::Inspect(args) => inspect(args).map(|_| exit::SUCCESS),
+        Command::Rewrap(args) => rewrap(args),
+        Command::Demo(_) => unreachable!("demo is dispatched by main"),
+    };
+
+    match result {
+        Ok(code) => ExitCode::from(code),
+        Err(e) => {
+            eprintln!("encry: {}", e);
+            ExitCode::from(exit_code(&e))
+        }
+    }
+}
+
+fn encrypt(args: EncryptArgs) -> Result<()> {
+    let mut service = build_service(&args.keys, &args.context)?
+        .with_algorithm(args.algorithm.into())
+        .with_encoding(args.encoding.into())
+        .with_compression(match args.compress {
+            Some(compression) => CompressionPolicy::new(compression.into()).with_threshold(args.compress_threshold),
+            None => CompressionPolicy::disabled(),
+        });
+    let mut input = open_input(args.io.input.as_deref())?;
+    let mut output = open_output(args.io.output.as_deref(), false)?;
+
+    if args.stream {
+        stream::encrypt_stream(&mut service, &mut input, output)?;
+        return Ok(());
+    }
+
+    let mut plaintext = Vec::new();
+    input.read_to_end(&mut plaintext)?;
+    if args.binary {
+        output.write_all(&service.encrypt_bytes_to_vec_with_aad(&plaintext, aad_bytes(&args.context))?)?;
+        output.flush()?;
+        return Ok(());
+    }
+    let encrypted = service.encrypt_bytes_with_aad(&plaintext, aad_bytes(&args.context))?;
+    write_line(output, &encrypted)
+}
+
+fn decrypt(args: DecryptArgs) -> Result<()> {
+    let mut service = build_service(&args.keys, &args.context)?.with_encoding(args.encoding.into());
+    let mut input = BufReader::new(open_input(args.io.input.as_deref())?);
+    let mut output = open_output(args.io.output.as_deref(), false)?;
+
+    // Streams are decrypted straight from the input, in constant memory
+    if input.fill_buf()?.starts_with(&STREAM_MAGIC) {
+        stream::decrypt_stream(&mut service, input, &mut output)?;
+        return Ok(());
+    }
+
+    let mut data = Vec::new();
+    input.read_to_end(&mut data)?;
+
+    let aad = aad_bytes(&args.context);
+    if Envelope::is_envelope(&data) {
+        if args.json {
+            let value: serde_json::Value = service.decrypt_json_from_slice_with_aad(&data, aad)?;
+            return write_line(output, &serde_json::to_string_pretty(&value)?);
+        }
+        output.write_all(&service.decrypt_bytes_from_slice_with_aad(&data, aad)?)?;
+        output.flush()?;
+        return Ok(());
+    }
+
+    let text = String::from_utf8(data)?;
+    let text = text.trim();
+
+    // A JSON `EncryptedData` package carries its own context
+    if text.starts_with('{') {
+        let package: EncryptedData = serde_json::from_str(text)?;
+        let value: serde_json::Value = service.decrypt_package_with_aad(&package, aad)?;
+        return write_line(output, &serde_json::to_string_pretty(&value)?);
+    }
+
+    if args.json {
+        let value: serde_json::Value = if args.legacy {
+            service.decrypt_legacy_json(text)?
+        } else {
+            service.decrypt_json_with_aad(text, aad)?
+        };
+        return write_line(output, &serde_json::to_string_pretty(&value)?);
+    }
+
+    let plaintext = if args.legacy {
+        service.decrypt_legacy_bytes(text)?
+    } else {
+        service.decrypt_bytes_with_aad(text, aad)?
+    };
+    output.write_all(&plaintext)?;
+    output.flush()?;
+    Ok(())
+}
+
+fn keygen(args: KeygenArgs) -> Result<()> {
+    if let Some(key_id) = args.descriptor {
+        let params = Argon2Params {
+            memory_kib: args.memory_kib,
+            iterations: args.iterations,
+            parallelism: args.parallelism,
+        };
+        let descriptor = KeyDescriptor::generate(key_id, params)?;
+        return write_line(open_output(args.output.as_deref(), false)?, &serde_json::to_string_pretty(&descriptor)?);
+    }
+
+    let mut ikm = SecretKey::<32>::zeroed();
+    rand::rng().fill_bytes(ikm.expose_secret_mut());
+    let ikm_base64 = SecretString::from(general_purpose::STANDARD.encode(ikm.expose_secret()));
+
+    let text = match args.entry {
+        Some(key_id) => SecretString::from(serde_json::to_string_pretty(&KeyEntry::new(
+            key_id,
+            ikm_base64,
+            KeyStatus::Active,
+        ))?),
+        None => ikm_base64,
+    };
+    write_line(open_output(args.output.as_deref(), false)?, text.expose_secret())
+}
+
+fn inspect(args: InspectArgs) -> Result<()> {
+    let mut data = Vec::new();
+    open_input(args.io.input.as_deref())?.read_to_end(&mut data)?;
+    let mut output = open_output(args.io.output.as_deref(), false)?;
+
+    if data.starts_with(&STREAM_MAGIC) {
+        let header = StreamHeader::read_from(&mut data.as_slice())?;
+        writeln!(output, "format:     stream v{}", STREAM_VERSION)?;
+        writeln!(output, "algorithm:  {}", header.algorithm.name())?;
+        writeln!(output, "key id:     {}", header.key_id)?;
+        writeln!(output, "chunk size: {} bytes", header.chunk_size)?;
+        return Ok(());
+    }
+
+    let (raw, context) = if Envelope::is_envelope(&data) {
+        (data, None)
+    } else {
+        let text = String::from_utf8(data)?;
+        let text = text.trim();
+        if text.starts_with('{') {
+            let package: EncryptedData = serde_json::from_str(text)?;
+            (general_purpose::STANDARD.decode(&package.data)?, Some(package.context))
+        } else {
+            (TextEncoding::from(args.encoding).decode(text)?, None)
+        }
+    };
+
+    if !Envelope::is_envelope(&raw) {
+        writeln!(output, "format:     legacy (no envelope header); decrypt with --legacy")?;
+        return Ok(());
+    }
+
+    let envelope = Envelope::parse(&raw)?;
+    writeln!(output, "format:     envelope v{}", envelope.header.version)?;
+    writeln!(output, "algorithm:  {}", envelope.header.algorithm.name())?;
+    writeln!(output, "key id:     {}", envelope.header.key_id)?;
+    if let Some(content_type) = envelope.header.content_type {
+        writeln!(output, "content:    {}", content_type.name())?;
+        writeln!(output, "compressed: {}", envelope.header.compression()?.name())?;
+    }
+    if envelope.header.algorithm.is_public_key() {
+        writeln!(output, "encap key:  {} bytes", envelope.header.nonce.len())?;
+    } else {
+        writeln!(output, "nonce:      {} bytes", envelope.header.nonce.len())?;
+    }
+    writeln!(output, "ciphertext: {} bytes", envelope.ciphertext.len())?;
+    if let Some(context) = context {
+        let decoded = general_purpose::STANDARD.decode(&context)?;
+        writeln!(output, "context:    {}", String::from_utf8_lossy(&decoded))?;
+    }
+    Ok(())
+}
+
+fn rewrap(args: RewrapArgs) -> Result<u8> {
+    let keyring = load_keyring(&args.keys)?;
+    let mut rewrapper = Rewrapper::new(keyring, context_base64(&args.context)?).with_encoding(args.encoding.into());
+    let aad = args.context.aad.clone().map(String::into_bytes);
+
+    let checkpoint = match &args.progress {
+        Some(path) if path.exists() => serde_json::from_str(&fs::read_to_string(path)?)?,
+        _ => RewrapCheckpoint::default(),
+    };
+    let mut progress = checkpoint.progress;
+    let resuming = progress.next_index > 0;
+
+    // Lines written after the last checkpoint are cut off and written again
+    let mut output_len = checkpoint.output_len;
+    if resuming && let Some(path) = args.io.output.as_deref() {
+        OpenOptions::new().write(true).open(path)?.set_len(output_len)?;
+    }
+
+    // Lines are read as the job consumes them; the current one is kept for
+    // items that are copied through unchanged
+    let current = RefCell::new(String::new());
+    let read_error = RefCell::new(None);
+    let items = BufReader::new(open_input(args.io.input.as_deref())?)
+        .lines()
+        .map_while(|line| line.map_err(|e| *read_error.borrow_mut() = Some(e)).ok())
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let item = parse_rewrap_item(&line, aad.as_deref());
+            *current.borrow_mut() = line;
+            item
+        });
+
+    let mut output = open_output(args.io.output.as_deref(), resuming)?;
+    rewrapper.run(items, &mut progress, |report, next| {
+        let line = match &report.outcome {
+            RewrapOutcome::Rewrapped(item) => format_rewrap_item(item)?,
+            RewrapOutcome::AlreadyCurrent => current.borrow().clone(),
+            RewrapOutcome::Failed(e) => {
+                eprintln!("encry: item {} not rewrapped: {}", report.index, e);
+                current.borrow().clone()
+            }
+        };
+        writeln!(output, "{}", line)?;
+        output_len += line.len() as u64 + 1;
+        if let Some(path) = &args.progress {
+            output.flush()?;
+            let checkpoint = RewrapCheckpoint {
+                progress: next.clone(),
+                output_len,
+            };
+            files::write_private(path, serde_json::to_string(&checkpoint)?.as_bytes(), false)?;
+        }
+        Ok(())
+    })?;
+    output.flush()?;
+    if let Some(e) = read_error.into_inner() {
+        return Err(e.into());
+    }
+
+    eprintln!(
+        "encry: rewrapped {}, already current {}, failed {}",
+        progress.rewrapped, progress.already_current, progress.failed
+    );
+    Ok(if progress.failed > 0 { exit::PARTIAL_FAILURE } else { exit::SUCCESS })
+}
+
+/// Contents of the `--progress` file
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RewrapCheckpoint {
+    #[serde(flatten)]
+    progress: RewrapProgress,
+    /// Bytes of `--out` covered by `progress`
+    output_len: u64,
+}
+
+fn parse_rewrap_item(line: &str, aad: Option<&[u8]>) -> RewrapItem {
+    let line = line.trim();
+    if line.starts_with('{')
+        && let Ok(package) = serde_json::from_str::<EncryptedData>(line)
+    {
+        return match aad {
+            Some(aad) => RewrapItem::BoundPackage {
+                package,
+                aad: aad.to_vec(),
+            },
+            None => RewrapItem::Package(package),
+        };
+    }
+    match aad {
+        Some(aad) => RewrapItem::Bound {
+            data: line.to_string(),
+            aad: aad.to_vec(),
+        },
+        None => RewrapItem::Ciphertext(line.to_string()),
+    }
+}
+
+fn format_rewrap_item(item: &RewrapItem) -> Result<String> {
+    match item {
+        RewrapItem::Ciphertext(data) | RewrapItem::Bound { data, .. } => Ok(data.clone()),
+        RewrapItem::Package(package) | RewrapItem::BoundPackage { package, .. } => Ok(serde_json::to_string(package)?),
+    }
+}
+
+/// Loads the keyring through `ConfigLoader`, with the command-line flags
+/// taking precedence over the environment
+pub fn load_keyring(args: &KeyArgs) -> Result<Arc<Keyring>> {
+    let mut loader = ConfigLoader::from_env().ikm_var(&args.key_env);
+    if let Some(path) = &args.key_file {
+        loader = loader.key_file(path);
+    }
+    if let Some(path) = &args.config {
+        loader = loader.config_file(path);
+    }
+    if let Some(key_id) = &args.key_id {
+        loader = loader.key_id(key_id);
+    }
+    if let Some(path) = &args.passphrase_file {
+        let contents = SecretString::from(fs::read_to_string(path)?);
+        let passphrase = contents.expose_secret().lines().next().unwrap_or_default();
+        loader = loader.passphrase(passphrase);
+    }
+    Ok(loader.load()?.keyring)
+}
+
+fn build_service(keys: &KeyArgs, context: &ContextArgs) -> Result<KryptorService> {
+    Ok(KryptorService::from_keyring(load_keyring(keys)?, context_base64(context)?))
+}
+
+fn context_base64(args: &ContextArgs) -> Result<String> {
+    let context_json = match (&args.context, &args.context_json) {
+        (_, Some(json)) => {
+            serde_json::from_str::<serde_json::Value>(json)?;
+            json.trim().to_string()
+        }
+        (Some(keygen), None) => serde_json::to_string(&EncryptionContext::new(keygen.clone()))?,
+        (None, None) => {
+            return Err(EncryptionError::Other(
+                "an encryption context is required; pass --context or --context-json".to_string(),
+            ));
+        }
+    };
+    Ok(general_purpose::STANDARD.encode(context_json))
+}
+
+fn aad_bytes(args: &ContextArgs) -> &[u8] {
+    args.aad.as_deref().map(str::as_bytes).unwrap_or_default()
+}
+
+fn open_input(path: Option<&Path>) -> Result<Box<dyn Read>> {
+    Ok(match path {
+        Some(path) => Box::new(File::open(path)?),
+        None => Box::new(io::stdin().lock()),
+    })
+}
+
+fn open_output(path: Option<&Path>, append: bool) -> Result<Box<dyn Write>> {
+    Ok(match path {
+        Some(path) => Box::new(
+            OpenOptions::new()
+                .create(true)
+                .write(true)
+                .append(append)
+                .truncate(!append)
+                .open(path)?,
+        ),
+        None => Box::new(io::stdout().lock()),
+    })
+}
+
+fn write_line(mut output: Box<dyn Write>, text: &str) -> Result<()> {
+    writeln!(output, "{}", text)?;
+    output.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_IKM: &str = "rph2pwTQCx+TD/lk+7o9igzQw5A7FU3+S+Z24Cf9Duk=";
+
+    fn context(context: Option<&str>, context_json: Option<&str>) -> ContextArgs {
+        ContextArgs {
+            context: context.map(str::to_string),
+            context_json: context_json.map(str::to_string),
+            aad: None,
+        }
+    }
+
+    #[test]
+    fn test_context_string_matches_application_context() -> Result<()> {
+        let from_string = context_base64(&context(Some("agg-1"), None))?;
+        let from_json = context_base64(&context(None, Some(r#"{"keygen":"agg-1"}"#)))?;
+        assert_eq!(from_string, from_json);
+
+        let service = KryptorService::with_keyring(Keyring::single("k1", "a2V5")?, &EncryptionContext::new("agg-1".to_string()))?;
+        assert_eq!(service.context_base64(), from_string);
+
+        assert!(context_base64(&context(None, None)).is_err());
+        assert!(context_base64(&context(None, Some("{not json"))).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_exit_codes() {
+        assert_eq!(exit_code(&EncryptionError::UnknownKey("k9".to_string())), exit::KEY_ERROR);
+        assert_eq!(exit_code(&EncryptionError::DecryptionError(aes_gcm::Error)), exit::DECRYPTION_FAILED);
+        assert_eq!(exit_code(&EncryptionError::InvalidEnvelope("short".to_string())), exit::FAILURE);
+    }
+
+    #[test]
+    fn test_tampered_stream_exits_as_decryption_failure() -> Result<()> {
+        let mut service = KryptorService::with_keyring(Keyring::single("k1", TEST_IKM)?, &"ctx")?;
+        let mut sealed = Vec::new();
+        stream::encrypt_stream(&mut service, &mut [7u8; 1000].as_slice(), &mut sealed)?;
+
+        let mut tampered = sealed.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 0x01;
+        let err = stream::decrypt_stream(&mut service, tampered.as_slice(), &mut Vec::new()).unwrap_err();
+        assert!(matches!(err, EncryptionError::DecryptionError(_) | EncryptionError::AesGcmError(_)));
+        assert_eq!(exit_code(&err), exit::DECRYPTION_FAILED);
+
+        let wrapped = EncryptionError::IoError(io::Error::new(io::ErrorKind::InvalidData, err));
+        assert_eq!(exit_code(&wrapped), exit::DECRYPTION_FAILED);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rewrap_resume_cuts_output_back_to_the_checkpoint() -> Result<()> {
+        const NEW_IKM: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+        let dir = std::env::temp_dir();
+        let name = |suffix: &str| dir.join(format!("encry-rewrap-{}.{}", std::process::id(), suffix));
+        let (key_file, input, output, progress) = (name("keys"), name("in"), name("out"), name("progress"));
+
+        let mut keyring = Keyring::single("old", TEST_IKM)?;
+        let mut old_service = KryptorService::with_keyring(keyring.duplicate(), &EncryptionContext::new("a".to_string()))?;
+        let lines = [old_service.encrypt_json(&1)?, old_service.encrypt_json(&2)?, old_service.encrypt_json(&3)?];
+        fs::write(&input, lines.join("\n"))?;
+        keyring.rotate(KeyEntry::new("new", NEW_IKM, KeyStatus::Active))?;
+        fs::write(&key_file, serde_json::to_string(keyring.entries())?)?;
+
+        // A crash after the first item was checkpointed, halfway through writing the second
+        let first = "rewritten-first-line\n";
+        fs::write(&output, format!("{}half-written", first))?;
+        let checkpoint = RewrapCheckpoint {
+            progress: RewrapProgress {
+                next_index: 1,
+                rewrapped: 1,
+                ..RewrapProgress::default()
+            },
+            output_len: first.len() as u64,
+        };
+        fs::write(&progress, serde_json::to_string(&checkpoint)?)?;
+
+        let exit = rewrap(RewrapArgs {
+            io: IoArgs {
+                input: Some(input.clone()),
+                output: Some(output.clone()),
+            },
+            keys: KeyArgs {
+                key_file: Some(key_file.clone()),
+                passphrase_file: None,
+                key_env: "ENCRY_TEST_UNSET_IKM".to_string(),
+                config: None,
+                key_id: None,
+            },
+            context: context(Some("a"), None),
+            encoding: EncodingArg::Base64,
+            progress: Some(progress.clone()),
+        })?;
+        assert_eq!(exit, exit::SUCCESS);
+
+        let rewritten = fs::read_to_string(&output)?;
+        let rewritten: Vec<&str> = rewritten.lines().collect();
+        assert_eq!(rewritten.len(), 3);
+        assert_eq!(rewritten[0], "rewritten-first-line");
+        let mut new_service = KryptorService::with_keyring(keyring, &EncryptionContext::new("a".to_string()))?;
+        assert_eq!(new_service.decrypt_json::<i32>(rewritten[2])?, 3);
+
+        let checkpoint: RewrapCheckpoint = serde_json::from_str(&fs::read_to_string(&progress)?)?;
+        assert_eq!(checkpoint.progress.next_index, 3);
+        assert_eq!(checkpoint.output_len, fs::metadata(&output)?.len());
+
+        // A checkpoint that does not say how much output it covers is refused
+        let without_len = r#"{"next_index":1,"rewrapped":1,"already_current":0,"failed":0}"#;
+        assert!(serde_json::from_str::<RewrapCheckpoint>(without_len).is_err());
+
+        for path in [key_file, input, output, progress] {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_inspect_decodes_the_chosen_encoding() -> Result<()> {
+        let mut service = KryptorService::with_keyring(Keyring::single("k1", TEST_IKM)?, &"ctx")?
+            .with_encoding(TextEncoding::Hex);
+        let input = std::env::temp_dir().join(format!("encry-inspect-{}.txt", std::process::id()));
+        let output = input.with_extension("out");
+        fs::write(&input, service.encrypt_bytes(b"hello")?)?;
+
+        inspect(InspectArgs {
+            io: IoArgs {
+                input: Some(input.clone()),
+                output: Some(output.clone()),
+            },
+            encoding: EncodingArg::Hex,
+        })?;
+        let report = fs::read_to_string(&output)?;
+        assert!(report.contains("envelope v2"));
+        assert!(report.contains("key id:     k1"));
+
+        fs::remove_file(input)?;
+        fs::remove_file(output)?;
+        Ok(())
+    }
+}
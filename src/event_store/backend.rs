@@ -0,0 +1,102 @@
+use serde::{Deserialize, Serialize};
+
+use crate::event_store::errors::EventStoreError;
+use crate::kryptor::blind_index::{BlindIndex, ProfileIndexes};
+
+pub type Result<T> = std::result::Result<T, EventStoreError>;
+
+/// Version of the first event of every stream
+pub const FIRST_VERSION: i32 = 0;
+
+/// An event as handed to a backend: metadata in the clear, payload encrypted
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EncryptedEvent {
+    pub aggregated_key: String,
+    pub aggregate_type: String,
+    pub version: i32,
+    /// Key id the payload was encrypted under, for rotation and rewrap jobs
+    pub key_id: String,
+    /// Base64 payload ciphertext bound to `EventStore::associated_data`
+    pub payload: String,
+    /// Blind indexes to look the event up by; backends store them verbatim
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub blind_indexes: Vec<BlindIndexEntry>,
+}
+
+/// One blind index value of an event, e.g. `("email", "3f1a…")`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlindIndexEntry {
+    pub name: String,
+    pub value: String,
+}
+
+/// An event as persisted by a backend
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredEvent {
+    /// Global position across all streams, starting at 1
+    pub position: u64,
+    /// Append time in seconds since the Unix epoch
+    pub recorded_at: u64,
+    #[serde(flatten)]
+    pub event: EncryptedEvent,
+}
+
+/// Storage for encrypted events. Backends never see plaintext payloads.
+pub trait EventBackend {
+    /// Appends `events` to one stream atomically. The first event must carry
+    /// the version after the stream's current one; the rest must follow it.
+    fn append(&mut self, events: Vec<EncryptedEvent>) -> Result<Vec<StoredEvent>>;
+
+    /// All events of a stream, in version order
+    fn read_stream(&self, aggregated_key: &str) -> Result<Vec<StoredEvent>>;
+
+    /// Up to `limit` events of all streams with a position after `after_position`
+    fn read_all(&self, after_position: u64, limit: usize) -> Result<Vec<StoredEvent>>;
+
+    /// Version of the last event of a stream, or `None` for an empty stream
+    fn stream_version(&self, aggregated_key: &str) -> Result<Option<i32>>;
+
+    /// Events carrying the blind index `name` = `value`, in position order
+    fn find_by_blind_index(&self, name: &str, value: &str) -> Result<Vec<StoredEvent>>;
+}
+
+impl BlindIndexEntry {
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            value: value.into(),
+        }
+    }
+
+    /// Entries for the searchable fields of a `Profile`, named after their `BlindIndex`
+    pub fn from_profile(indexes: &ProfileIndexes) -> Vec<Self> {
+        let phone = BlindIndex::phone(None);
+        std::iter::once(Self::new(BlindIndex::email().name(), &indexes.email))
+            .chain(indexes.phones.iter().map(|value| Self::new(phone.name(), value)))
+            .collect()
+    }
+}
+
+/// Checks that `events` target one stream and continue it from `current`
+pub(crate) fn check_append(current: Option<i32>, events: &[EncryptedEvent]) -> Result<()> {
+    let Some(first) = events.first() else {
+        return Ok(());
+    };
+
+    let next = current.map_or(FIRST_VERSION, |version| version + 1);
+    for (expected, event) in (next..).zip(events) {
+        if event.aggregated_key != first.aggregated_key {
+            return Err(EventStoreError::Other(
+                "an append must target a single aggregate".to_string(),
+            ));
+        }
+        if event.version != expected {
+            return Err(EventStoreError::VersionConflict {
+                aggregated_key: event.aggregated_key.clone(),
+                expected,
+                actual: event.version,
+            });
+        }
+    }
+    Ok(())
+}
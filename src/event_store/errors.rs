@@ -0,0 +1,69 @@
+use std::fmt;
+
+use crate::kryptor::errors::EncryptionError;
+
+#[derive(Debug)]
+pub enum EventStoreError {
+    /// Encrypting or decrypting a payload failed
+    EncryptionError(EncryptionError),
+    /// An append did not continue the stream at its current version
+    VersionConflict {
+        aggregated_key: String,
+        expected: i32,
+        actual: i32,
+    },
+    SerializationError(serde_json::Error),
+    IoError(std::io::Error),
+    #[cfg(feature = "sqlite")]
+    DatabaseError(rusqlite::Error),
+    Other(String),
+}
+
+impl fmt::Display for EventStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EventStoreError::EncryptionError(e) => write!(f, "{}", e),
+            EventStoreError::VersionConflict {
+                aggregated_key,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Version conflict on aggregate {}: expected version {}, got {}",
+                aggregated_key, expected, actual
+            ),
+            EventStoreError::SerializationError(e) => write!(f, "Serialization error: {}", e),
+            EventStoreError::IoError(e) => write!(f, "I/O error: {}", e),
+            #[cfg(feature = "sqlite")]
+            EventStoreError::DatabaseError(e) => write!(f, "Database error: {}", e),
+            EventStoreError::Other(s) => write!(f, "Other error: {}", s),
+        }
+    }
+}
+
+impl std::error::Error for EventStoreError {}
+
+impl From<EncryptionError> for EventStoreError {
+    fn from(err: EncryptionError) -> Self {
+        EventStoreError::EncryptionError(err)
+    }
+}
+
+impl From<serde_json::Error> for EventStoreError {
+    fn from(err: serde_json::Error) -> Self {
+        EventStoreError::SerializationError(err)
+    }
+}
+
+impl From<std::io::Error> for EventStoreError {
+    fn from(err: std::io::Error) -> Self {
+        EventStoreError::IoError(err)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for EventStoreError {
+    fn from(err: rusqlite::Error) -> Self {
+        EventStoreError::DatabaseError(err)
+    }
+}
@@ -0,0 +1,234 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::event_store::backend::{EncryptedEvent, EventBackend, Result, StoredEvent};
+use crate::event_store::errors::EventStoreError;
+use crate::event_store::memory::InMemoryBackend;
+
+/// Backend persisted as an append-only log of JSON lines, one `StoredEvent`
+/// per line. The log is replayed into memory on open.
+///
+/// Each append is written with a single write and synced before it is
+/// acknowledged. A torn final line left by a crash is dropped on the next
+/// open. Only one process may write to a log at a time. If a failed append
+/// cannot be rolled back, the backend refuses further appends until the log
+/// is reopened.
+#[derive(Debug)]
+pub struct FileBackend {
+    path: PathBuf,
+    file: File,
+    /// Length of the log up to the last complete append
+    len: u64,
+    /// Set when a failed append left bytes past `len` in the log
+    poisoned: bool,
+    index: InMemoryBackend,
+}
+
+impl FileBackend {
+    /// Opens the log at `path`, creating it when missing
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let mut file = OpenOptions::new().read(true).append(true).create(true).open(&path)?;
+
+        let mut log = Vec::new();
+        file.read_to_end(&mut log)?;
+
+        // Everything after the last newline is a torn write, which may end
+        // inside a multibyte character, so only the complete prefix is UTF-8
+        let complete = log.iter().rposition(|&byte| byte == b'\n').map_or(0, |newline| newline + 1);
+        if complete < log.len() {
+            file.set_len(complete as u64)?;
+        }
+        let log = std::str::from_utf8(&log[..complete]).map_err(|e| {
+            EventStoreError::Other(format!("event log {} is not valid UTF-8: {}", path.display(), e))
+        })?;
+
+        let mut index = InMemoryBackend::new();
+        for (line_number, line) in log.lines().enumerate() {
+            let stored: StoredEvent = serde_json::from_str(line).map_err(|e| corrupt(&path, line_number, e))?;
+            let expected = index.prepare(vec![stored.event.clone()]).map_err(|e| corrupt(&path, line_number, e))?;
+            if expected[0].position != stored.position {
+                return Err(corrupt(&path, line_number, "positions are not consecutive"));
+            }
+            index.commit(&[stored]);
+        }
+
+        Ok(Self {
+            path,
+            file,
+            len: complete as u64,
+            poisoned: false,
+            index,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl EventBackend for FileBackend {
+    fn append(&mut self, events: Vec<EncryptedEvent>) -> Result<Vec<StoredEvent>> {
+        if self.poisoned {
+            return Err(EventStoreError::Other(format!(
+                "event log {} has a partial append that could not be rolled back; reopen it",
+                self.path.display()
+            )));
+        }
+
+        let stored = self.index.prepare(events)?;
+        if stored.is_empty() {
+            return Ok(stored);
+        }
+
+        let mut lines = Vec::new();
+        for event in &stored {
+            serde_json::to_writer(&mut lines, event)?;
+            lines.push(b'\n');
+        }
+
+        let written = self.file.write_all(&lines).and_then(|_| self.file.sync_data());
+        if let Err(e) = written {
+            // Drop a partial append so the log stays replayable. If that
+            // fails too, later appends would land after the partial bytes
+            if self.file.set_len(self.len).is_err() {
+                self.poisoned = true;
+            }
+            return Err(e.into());
+        }
+
+        self.len += lines.len() as u64;
+        self.index.commit(&stored);
+        Ok(stored)
+    }
+
+    fn read_stream(&self, aggregated_key: &str) -> Result<Vec<StoredEvent>> {
+        self.index.read_stream(aggregated_key)
+    }
+
+    fn read_all(&self, after_position: u64, limit: usize) -> Result<Vec<StoredEvent>> {
+        self.index.read_all(after_position, limit)
+    }
+
+    fn stream_version(&self, aggregated_key: &str) -> Result<Option<i32>> {
+        self.index.stream_version(aggregated_key)
+    }
+
+    fn find_by_blind_index(&self, name: &str, value: &str) -> Result<Vec<StoredEvent>> {
+        self.index.find_by_blind_index(name, value)
+    }
+}
+
+fn corrupt(path: &Path, line_number: usize, reason: impl std::fmt::Display) -> EventStoreError {
+    EventStoreError::Other(format!(
+        "event log {} is corrupt at line {}: {}",
+        path.display(),
+        line_number + 1,
+        reason
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_store::store::EncryptedEventStore;
+    use crate::kryptor::keyring::Keyring;
+    use crate::models::EventStore;
+
+    const TEST_IKM: &str = "rph2pwTQCx+TD/lk+7o9igzQw5A7FU3+S+Z24Cf9Duk=";
+
+    #[test]
+    fn test_log_replays_and_drops_torn_writes() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("encry-events-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let keyring = Keyring::single("k1", TEST_IKM)?;
+        let event = |version| {
+            EventStore::new(
+                "a".to_string(),
+                "Profile".to_string(),
+                version,
+                serde_json::json!({ "name": "Alice" }),
+            )
+        };
+
+        let mut store = EncryptedEventStore::new(FileBackend::open(&path)?, keyring.duplicate());
+        store.append(&[event(0), event(1)])?;
+
+        // Simulate a crash in the middle of the next append
+        OpenOptions::new()
+            .append(true)
+            .open(&path)?
+            .write_all(b"{\"position\":3,\"recorded")?;
+
+        let mut store = EncryptedEventStore::new(FileBackend::open(&path)?, keyring);
+        assert_eq!(store.stream_version("a")?, Some(1));
+        assert_eq!(store.append(&[event(2)])?, vec![3]);
+        assert_eq!(store.read_stream("a")?.len(), 3);
+        assert!(!std::fs::read_to_string(&path)?.contains("Alice"));
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_log_reopens_after_write_torn_inside_a_multibyte_character() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("encry-events-utf8-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let keyring = Keyring::single("k1", TEST_IKM)?;
+        let event = |version| {
+            EventStore::new(
+                "ä".to_string(),
+                "Profil".to_string(),
+                version,
+                serde_json::json!({ "name": "Zoë" }),
+            )
+        };
+
+        let mut store = EncryptedEventStore::new(FileBackend::open(&path)?, keyring.duplicate());
+        store.append(&[event(0)])?;
+
+        // Crash after the first byte of the two-byte "ä"
+        let torn = "{\"position\":2,\"event\":{\"aggregated_key\":\"ä".as_bytes();
+        OpenOptions::new()
+            .append(true)
+            .open(&path)?
+            .write_all(&torn[..torn.len() - 1])?;
+
+        let mut store = EncryptedEventStore::new(FileBackend::open(&path)?, keyring);
+        assert_eq!(store.stream_version("ä")?, Some(0));
+        assert_eq!(store.append(&[event(1)])?, vec![2]);
+        assert_eq!(store.read_stream("ä")?.len(), 2);
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_poisoned_log_refuses_appends_until_reopened() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("encry-events-poisoned-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let event = |version| EncryptedEvent {
+            aggregated_key: "a".to_string(),
+            aggregate_type: "Profile".to_string(),
+            version,
+            key_id: "k1".to_string(),
+            payload: "AAAA".to_string(),
+            blind_indexes: Vec::new(),
+        };
+
+        let mut backend = FileBackend::open(&path)?;
+        backend.append(vec![event(0)])?;
+
+        // As if a failed append could not be truncated away
+        backend.poisoned = true;
+        assert!(matches!(backend.append(vec![event(1)]), Err(EventStoreError::Other(_))));
+        assert_eq!(backend.stream_version("a")?, Some(0));
+
+        let mut backend = FileBackend::open(&path)?;
+        assert_eq!(backend.append(vec![event(1)])?.len(), 1);
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+}
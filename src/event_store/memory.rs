@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+
+use crate::event_store::backend::{EncryptedEvent, EventBackend, Result, StoredEvent, check_append};
+use crate::kryptor::keyring::unix_now;
+
+/// Backend kept in memory, for tests
+#[derive(Debug, Default)]
+pub struct InMemoryBackend {
+    events: Vec<StoredEvent>,
+    /// Indexes into `events` per aggregate, in version order
+    streams: HashMap<String, Vec<usize>>,
+}
+
+impl InMemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Validates an append and assigns positions without storing anything
+    pub(crate) fn prepare(&self, events: Vec<EncryptedEvent>) -> Result<Vec<StoredEvent>> {
+        if let Some(first) = events.first() {
+            check_append(self.stream_version(&first.aggregated_key)?, &events)?;
+        }
+
+        let recorded_at = unix_now();
+        let next_position = self.events.len() as u64 + 1;
+        Ok(events
+            .into_iter()
+            .enumerate()
+            .map(|(offset, event)| StoredEvent {
+                position: next_position + offset as u64,
+                recorded_at,
+                event,
+            })
+            .collect())
+    }
+
+    /// Stores events returned by `prepare`
+    pub(crate) fn commit(&mut self, stored: &[StoredEvent]) {
+        for event in stored {
+            self.streams
+                .entry(event.event.aggregated_key.clone())
+                .or_default()
+                .push(self.events.len());
+            self.events.push(event.clone());
+        }
+    }
+}
+
+impl EventBackend for InMemoryBackend {
+    fn append(&mut self, events: Vec<EncryptedEvent>) -> Result<Vec<StoredEvent>> {
+        let stored = self.prepare(events)?;
+        self.commit(&stored);
+        Ok(stored)
+    }
+
+    fn read_stream(&self, aggregated_key: &str) -> Result<Vec<StoredEvent>> {
+        Ok(self
+            .streams
+            .get(aggregated_key)
+            .map(|indexes| indexes.iter().map(|&index| self.events[index].clone()).collect())
+            .unwrap_or_default())
+    }
+
+    fn read_all(&self, after_position: u64, limit: usize) -> Result<Vec<StoredEvent>> {
+        // Positions are dense, so position n lives at index n - 1
+        let start = (after_position as usize).min(self.events.len());
+        Ok(self.events[start..].iter().take(limit).cloned().collect())
+    }
+
+    fn stream_version(&self, aggregated_key: &str) -> Result<Option<i32>> {
+        Ok(self
+            .streams
+            .get(aggregated_key)
+            .and_then(|indexes| indexes.last())
+            .map(|&index| self.events[index].event.version))
+    }
+
+    fn find_by_blind_index(&self, name: &str, value: &str) -> Result<Vec<StoredEvent>> {
+        Ok(self
+            .events
+            .iter()
+            .filter(|stored| {
+                stored
+                    .event
+                    .blind_indexes
+                    .iter()
+                    .any(|entry| entry.name == name && entry.value == value)
+            })
+            .cloned()
+            .collect())
+    }
+}
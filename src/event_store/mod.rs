@@ -0,0 +1,7 @@
+pub mod backend;
+pub mod errors;
+pub mod memory;
+pub mod file;
+pub mod store;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
\ No newline at end of file
@@ -0,0 +1,355 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use rusqlite::{Connection, ErrorCode, OptionalExtension, Row, TransactionBehavior, params, params_from_iter};
+
+use crate::event_store::backend::{
+    BlindIndexEntry, EncryptedEvent, EventBackend, FIRST_VERSION, Result, StoredEvent, check_append,
+};
+use crate::event_store::errors::EventStoreError;
+use crate::kryptor::keyring::unix_now;
+
+/// Schema migrations, applied in order. `PRAGMA user_version` records how
+/// many have run; append new migrations and never edit shipped ones.
+const MIGRATIONS: &[&str] = &[
+    // 1: events; UNIQUE(aggregated_key, version) backs optimistic concurrency
+    "CREATE TABLE events (
+        position INTEGER PRIMARY KEY AUTOINCREMENT,
+        aggregated_key TEXT NOT NULL,
+        aggregate_type TEXT NOT NULL,
+        version INTEGER NOT NULL,
+        key_id TEXT NOT NULL,
+        payload TEXT NOT NULL,
+        recorded_at INTEGER NOT NULL,
+        UNIQUE (aggregated_key, version)
+    );
+    CREATE INDEX events_key_id ON events (key_id);",
+    // 2: optional blind indexes, one row per indexed value
+    "CREATE TABLE event_blind_indexes (
+        position INTEGER NOT NULL REFERENCES events (position),
+        name TEXT NOT NULL,
+        value TEXT NOT NULL,
+        PRIMARY KEY (position, name, value)
+    ) WITHOUT ROWID;
+    CREATE INDEX event_blind_indexes_lookup ON event_blind_indexes (name, value);",
+];
+
+/// Events whose blind indexes are fetched per query, well below SQLite's
+/// bound-parameter limit
+const INDEX_BATCH_SIZE: usize = 500;
+
+const SELECT_EVENTS: &str =
+    "SELECT position, recorded_at, aggregated_key, aggregate_type, version, key_id, payload FROM events";
+
+/// Backend persisted in a SQLite database. Only ciphertexts, key ids and
+/// blind indexes are written; payloads stay encrypted at rest.
+#[derive(Debug)]
+pub struct SqliteBackend {
+    conn: Connection,
+}
+
+impl SqliteBackend {
+    /// Opens or creates the database at `path` and runs pending migrations
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    pub fn from_connection(mut conn: Connection) -> Result<Self> {
+        conn.pragma_update(None, "foreign_keys", true)?;
+        migrate(&mut conn)?;
+        Ok(Self { conn })
+    }
+
+    /// Number of migrations applied to the database
+    pub fn schema_version(&self) -> Result<usize> {
+        user_version(&self.conn)
+    }
+
+    /// Fills in the blind indexes of `stored` with one query per
+    /// `INDEX_BATCH_SIZE` events
+    fn load_indexes(&self, stored: &mut [StoredEvent]) -> Result<()> {
+        let mut indexes: HashMap<u64, Vec<BlindIndexEntry>> = HashMap::new();
+        for batch in stored.chunks(INDEX_BATCH_SIZE) {
+            let placeholders = vec!["?"; batch.len()].join(", ");
+            let mut statement = self.conn.prepare_cached(&format!(
+                "SELECT position, name, value FROM event_blind_indexes WHERE position IN ({})
+                 ORDER BY position, name, value",
+                placeholders
+            ))?;
+            let rows = statement.query_map(params_from_iter(batch.iter().map(|event| event.position as i64)), |row| {
+                Ok((
+                    row.get::<_, i64>(0)? as u64,
+                    BlindIndexEntry::new(row.get::<_, String>(1)?, row.get::<_, String>(2)?),
+                ))
+            })?;
+            for row in rows {
+                let (position, entry) = row?;
+                indexes.entry(position).or_default().push(entry);
+            }
+        }
+
+        for event in stored.iter_mut() {
+            event.event.blind_indexes = indexes.remove(&event.position).unwrap_or_default();
+        }
+        Ok(())
+    }
+
+    fn query_events(&self, sql: &str, params: impl rusqlite::Params) -> Result<Vec<StoredEvent>> {
+        let mut stored = self
+            .conn
+            .prepare_cached(sql)?
+            .query_map(params, stored_event)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        self.load_indexes(&mut stored)?;
+        Ok(stored)
+    }
+}
+
+impl EventBackend for SqliteBackend {
+    fn append(&mut self, events: Vec<EncryptedEvent>) -> Result<Vec<StoredEvent>> {
+        let Some(first) = events.first() else {
+            return Ok(Vec::new());
+        };
+
+        // IMMEDIATE takes the write lock up front, so the version check and
+        // the inserts see the same stream even with several writers
+        let tx = self.conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let current: Option<i32> = tx.query_row(
+            "SELECT MAX(version) FROM events WHERE aggregated_key = ?1",
+            [&first.aggregated_key],
+            |row| row.get(0),
+        )?;
+        check_append(current, &events)?;
+
+        let recorded_at = unix_now();
+        let mut stored = Vec::with_capacity(events.len());
+        for event in events {
+            let inserted = tx.execute(
+                "INSERT INTO events (aggregated_key, aggregate_type, version, key_id, payload, recorded_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    event.aggregated_key,
+                    event.aggregate_type,
+                    event.version,
+                    event.key_id,
+                    event.payload,
+                    recorded_at as i64
+                ],
+            );
+            if let Err(rusqlite::Error::SqliteFailure(failure, _)) = &inserted
+                && failure.code == ErrorCode::ConstraintViolation
+            {
+                return Err(EventStoreError::VersionConflict {
+                    aggregated_key: event.aggregated_key,
+                    expected: current.map_or(FIRST_VERSION, |version| version + 1),
+                    actual: event.version,
+                });
+            }
+            inserted?;
+
+            let position = tx.last_insert_rowid() as u64;
+            for entry in &event.blind_indexes {
+                tx.execute(
+                    "INSERT OR IGNORE INTO event_blind_indexes (position, name, value) VALUES (?1, ?2, ?3)",
+                    params![position as i64, entry.name, entry.value],
+                )?;
+            }
+            stored.push(StoredEvent {
+                position,
+                recorded_at,
+                event,
+            });
+        }
+
+        tx.commit()?;
+        Ok(stored)
+    }
+
+    fn read_stream(&self, aggregated_key: &str) -> Result<Vec<StoredEvent>> {
+        self.query_events(
+            &format!("{} WHERE aggregated_key = ?1 ORDER BY version", SELECT_EVENTS),
+            [aggregated_key],
+        )
+    }
+
+    fn read_all(&self, after_position: u64, limit: usize) -> Result<Vec<StoredEvent>> {
+        self.query_events(
+            &format!("{} WHERE position > ?1 ORDER BY position LIMIT ?2", SELECT_EVENTS),
+            params![after_position as i64, limit.min(i64::MAX as usize) as i64],
+        )
+    }
+
+    fn stream_version(&self, aggregated_key: &str) -> Result<Option<i32>> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT version FROM events WHERE aggregated_key = ?1 ORDER BY version DESC LIMIT 1",
+                [aggregated_key],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    fn find_by_blind_index(&self, name: &str, value: &str) -> Result<Vec<StoredEvent>> {
+        self.query_events(
+            &format!(
+                "{} WHERE position IN (SELECT position FROM event_blind_indexes WHERE name = ?1 AND value = ?2)
+                 ORDER BY position",
+                SELECT_EVENTS
+            ),
+            [name, value],
+        )
+    }
+}
+
+fn user_version(conn: &Connection) -> Result<usize> {
+    let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    Ok(version as usize)
+}
+
+fn migrate(conn: &mut Connection) -> Result<()> {
+    let applied = user_version(conn)?;
+    if applied > MIGRATIONS.len() {
+        return Err(EventStoreError::Other(format!(
+            "database schema version {} is newer than this build supports ({})",
+            applied,
+            MIGRATIONS.len()
+        )));
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(applied) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", (index + 1) as i64)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+fn stored_event(row: &Row<'_>) -> rusqlite::Result<StoredEvent> {
+    Ok(StoredEvent {
+        position: row.get::<_, i64>(0)? as u64,
+        recorded_at: row.get::<_, i64>(1)? as u64,
+        event: EncryptedEvent {
+            aggregated_key: row.get(2)?,
+            aggregate_type: row.get(3)?,
+            version: row.get(4)?,
+            key_id: row.get(5)?,
+            payload: row.get(6)?,
+            blind_indexes: Vec::new(),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_store::store::EncryptedEventStore;
+    use crate::kryptor::blind_index::{BlindIndex, BlindIndexer};
+    use crate::kryptor::keyring::Keyring;
+    use crate::models::{EventStore, Profile};
+
+    const TEST_IKM: &str = "rph2pwTQCx+TD/lk+7o9igzQw5A7FU3+S+Z24Cf9Duk=";
+
+    fn profile(name: &str, email: &str) -> Profile {
+        Profile::new(
+            name.to_string(),
+            "1990-01-01".to_string(),
+            email.to_string(),
+            vec!["+254712345678".to_string()],
+        )
+    }
+
+    #[test]
+    fn test_migrations_are_idempotent_and_persist() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("encry-events-{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let keyring = Keyring::single("k1", TEST_IKM)?;
+
+        let mut store = EncryptedEventStore::new(SqliteBackend::open(&path)?, keyring.duplicate());
+        assert_eq!(store.backend().schema_version()?, MIGRATIONS.len());
+        store.append(&[EventStore::with_profile("a".to_string(), profile("Alice", "alice@example.com"))])?;
+        drop(store);
+
+        let mut store = EncryptedEventStore::new(SqliteBackend::open(&path)?, keyring);
+        assert_eq!(store.backend().schema_version()?, MIGRATIONS.len());
+        let profiles: Vec<Profile> = store.read_stream_as("a")?;
+        assert_eq!(profiles[0].name, "Alice");
+
+        let conn = Connection::open(&path)?;
+        let payload: String = conn.query_row("SELECT payload FROM events", [], |row| row.get(0))?;
+        assert!(!payload.contains("Alice"));
+        conn.pragma_update(None, "user_version", 99)?;
+        assert!(SqliteBackend::from_connection(conn).is_err());
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_unique_version_and_global_order() -> Result<()> {
+        let mut backend = SqliteBackend::open_in_memory()?;
+        let event = |key: &str, version| EncryptedEvent {
+            aggregated_key: key.to_string(),
+            aggregate_type: "Profile".to_string(),
+            version,
+            key_id: "k1".to_string(),
+            payload: "ciphertext".to_string(),
+            blind_indexes: Vec::new(),
+        };
+
+        backend.append(vec![event("a", 0), event("a", 1)])?;
+        backend.append(vec![event("b", 0)])?;
+        assert!(matches!(
+            backend.append(vec![event("a", 1)]),
+            Err(EventStoreError::VersionConflict { expected: 2, .. })
+        ));
+
+        // The constraint holds even when the version check is bypassed
+        let duplicate = backend.conn.execute(
+            "INSERT INTO events (aggregated_key, aggregate_type, version, key_id, payload, recorded_at)
+             VALUES ('a', 'Profile', 0, 'k1', 'x', 0)",
+            [],
+        );
+        assert!(duplicate.is_err());
+
+        let positions: Vec<u64> = backend.read_all(1, 10)?.iter().map(|stored| stored.position).collect();
+        assert_eq!(positions, vec![2, 3]);
+        assert_eq!(backend.stream_version("a")?, Some(1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_profiles_by_blind_index() -> Result<()> {
+        let keyring = Keyring::single("k1", TEST_IKM)?;
+        let mut indexer = BlindIndexer::from_keyring(keyring.duplicate());
+        let mut store = EncryptedEventStore::new(SqliteBackend::open_in_memory()?, keyring);
+
+        for (key, profile) in [
+            ("a", profile("Alice", "alice@example.com")),
+            ("b", profile("Bob", "bob@example.com")),
+        ] {
+            let indexes = BlindIndexEntry::from_profile(&indexer.index_profile(&profile, None)?);
+            store.append_with_indexes(&[(EventStore::with_profile(key.to_string(), profile), indexes)])?;
+        }
+
+        let lookup = indexer.compute(&BlindIndex::email(), " Bob@Example.com")?;
+        let found: Vec<Profile> = store.find_by_blind_index_as("email", &lookup)?;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].name, "Bob");
+
+        let phone = indexer.compute(&BlindIndex::phone(None), "+254 712 345 678")?;
+        assert_eq!(store.find_by_blind_index("phone", &phone)?.len(), 2);
+        assert_eq!(store.backend().read_stream("a")?[0].event.blind_indexes.len(), 2);
+
+        let all = store.backend().read_all(0, 10)?;
+        assert_eq!(all.len(), 2);
+        assert!(all.iter().all(|stored| stored.event.blind_indexes.len() == 2));
+        assert_ne!(all[0].event.blind_indexes, all[1].event.blind_indexes);
+        Ok(())
+    }
+}
@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serde::de::DeserializeOwned;
+
+use crate::event_store::backend::{BlindIndexEntry, EncryptedEvent, EventBackend, Result, StoredEvent};
+use crate::kryptor::compression::CompressionPolicy;
+use crate::kryptor::errors::EncryptionError;
+use crate::kryptor::keyring::Keyring;
+use crate::kryptor::serialization::SerializationFormat;
+use crate::kryptor::utilities::KryptorService;
+use crate::models::{EncryptionContext, EventStore};
+
+/// A decrypted event together with its place in the log
+#[derive(Debug, Clone)]
+pub struct RecordedEvent {
+    pub position: u64,
+    pub recorded_at: u64,
+    pub event: EventStore,
+}
+
+impl RecordedEvent {
+    pub fn payload_as<T: DeserializeOwned>(&self) -> Result<T> {
+        Ok(serde_json::from_value(self.event.payload.clone())?)
+    }
+}
+
+/// Event store that encrypts payloads before they reach the backend.
+///
+/// Each payload is encrypted under `EncryptionContext::new(aggregated_key)`
+/// and bound to `EventStore::associated_data`, so a payload cannot be moved
+/// to another aggregate, type or version. Metadata stays in the clear for
+/// concurrency checks and queries.
+#[derive(Debug)]
+pub struct EncryptedEventStore<B: EventBackend> {
+    backend: B,
+    keyring: Arc<Keyring>,
+    format: SerializationFormat,
+    compression: CompressionPolicy,
+    services: HashMap<String, KryptorService>,
+}
+
+impl<B: EventBackend> EncryptedEventStore<B> {
+    pub fn new(backend: B, keyring: impl Into<Arc<Keyring>>) -> Self {
+        Self {
+            backend,
+            keyring: keyring.into(),
+            format: SerializationFormat::default(),
+            compression: CompressionPolicy::default(),
+            services: HashMap::new(),
+        }
+    }
+
+    /// Serializes new payloads with `format`, e.g. CBOR or MessagePack for
+    /// smaller events. Existing events keep the format they were written in.
+    /// Payloads are `serde_json::Value`, which bincode cannot decode, so
+    /// `Bincode` is rejected.
+    pub fn with_format(mut self, format: SerializationFormat) -> Result<Self> {
+        if format == SerializationFormat::Bincode {
+            return Err(EncryptionError::InvalidConfig(
+                "bincode cannot decode event payloads; use CBOR or MessagePack".to_string(),
+            )
+            .into());
+        }
+        self.format = format;
+        self.services.clear();
+        Ok(self)
+    }
+
+    /// Compresses new payloads as `policy` allows. Contexts are
+    /// `EncryptionContext::new(aggregated_key)`, so excluding one exempts
+    /// that aggregate.
+    pub fn with_compression(mut self, policy: CompressionPolicy) -> Self {
+        self.compression = policy;
+        self.services.clear();
+        self
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Appends events to a single stream and returns their global positions.
+    /// Fails with `VersionConflict` when the stream moved on since the caller read it.
+    pub fn append(&mut self, events: &[EventStore]) -> Result<Vec<u64>> {
+        let encrypted = events
+            .iter()
+            .map(|event| self.encrypt(event, Vec::new()))
+            .collect::<Result<Vec<_>>>()?;
+        let stored = self.backend.append(encrypted)?;
+        Ok(stored.iter().map(|event| event.position).collect())
+    }
+
+    /// Like `append`, storing blind indexes alongside each event so it can be
+    /// found with `find_by_blind_index`
+    pub fn append_with_indexes(&mut self, events: &[(EventStore, Vec<BlindIndexEntry>)]) -> Result<Vec<u64>> {
+        let encrypted = events
+            .iter()
+            .map(|(event, indexes)| self.encrypt(event, indexes.clone()))
+            .collect::<Result<Vec<_>>>()?;
+        let stored = self.backend.append(encrypted)?;
+        Ok(stored.iter().map(|event| event.position).collect())
+    }
+
+    pub fn read_stream(&mut self, aggregated_key: &str) -> Result<Vec<RecordedEvent>> {
+        let stored = self.backend.read_stream(aggregated_key)?;
+        stored.into_iter().map(|event| self.decrypt(event)).collect()
+    }
+
+    /// Up to `limit` events of all streams after `after_position`; pass the
+    /// last position seen to resume a subscription
+    pub fn read_all(&mut self, after_position: u64, limit: usize) -> Result<Vec<RecordedEvent>> {
+        let stored = self.backend.read_all(after_position, limit)?;
+        stored.into_iter().map(|event| self.decrypt(event)).collect()
+    }
+
+    pub fn stream_version(&self, aggregated_key: &str) -> Result<Option<i32>> {
+        self.backend.stream_version(aggregated_key)
+    }
+
+    /// Events whose blind index `name` equals `value`, computed with `BlindIndexer`
+    pub fn find_by_blind_index(&mut self, name: &str, value: &str) -> Result<Vec<RecordedEvent>> {
+        let stored = self.backend.find_by_blind_index(name, value)?;
+        stored.into_iter().map(|event| self.decrypt(event)).collect()
+    }
+
+    /// Payloads of a stream decrypted into a typed model, e.g. `Profile`
+    pub fn read_stream_as<T: DeserializeOwned>(&mut self, aggregated_key: &str) -> Result<Vec<T>> {
+        self.read_stream(aggregated_key)?
+            .into_iter()
+            .map(|recorded| recorded.payload_as())
+            .collect()
+    }
+
+    /// Payloads of the events matching a blind index, decrypted into a typed model
+    pub fn find_by_blind_index_as<T: DeserializeOwned>(&mut self, name: &str, value: &str) -> Result<Vec<T>> {
+        self.find_by_blind_index(name, value)?
+            .into_iter()
+            .map(|recorded| recorded.payload_as())
+            .collect()
+    }
+
+    fn service(&mut self, aggregated_key: &str) -> Result<&mut KryptorService> {
+        if !self.services.contains_key(aggregated_key) {
+            let context = EncryptionContext::new(aggregated_key.to_string());
+            let service = KryptorService::with_keyring(Arc::clone(&self.keyring), &context)?
+                .with_format(self.format)
+                .with_compression(self.compression.clone());
+            self.services.insert(aggregated_key.to_string(), service);
+        }
+        Ok(self.services.get_mut(aggregated_key).expect("service was just inserted"))
+    }
+
+    fn encrypt(&mut self, event: &EventStore, blind_indexes: Vec<BlindIndexEntry>) -> Result<EncryptedEvent> {
+        let service = self.service(&event.aggregated_key)?;
+        Ok(EncryptedEvent {
+            aggregated_key: event.aggregated_key.clone(),
+            aggregate_type: event.aggregate_type.clone(),
+            version: event.version,
+            key_id: service.key_id().to_string(),
+            payload: service.encrypt_value_with_aad(&event.payload, &event.associated_data())?,
+            blind_indexes,
+        })
+    }
+
+    fn decrypt(&mut self, stored: StoredEvent) -> Result<RecordedEvent> {
+        let StoredEvent {
+            position,
+            recorded_at,
+            event,
+        } = stored;
+        let mut decrypted = EventStore::new(
+            event.aggregated_key,
+            event.aggregate_type,
+            event.version,
+            serde_json::Value::Null,
+        );
+        decrypted.payload = self
+            .service(&decrypted.aggregated_key)?
+            .decrypt_value_with_aad(&event.payload, &decrypted.associated_data())?;
+
+        Ok(RecordedEvent {
+            position,
+            recorded_at,
+            event: decrypted,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_store::memory::InMemoryBackend;
+    use crate::event_store::errors::EventStoreError;
+
+    const TEST_IKM: &str = "rph2pwTQCx+TD/lk+7o9igzQw5A7FU3+S+Z24Cf9Duk=";
+
+    fn event(aggregated_key: &str, version: i32, name: &str) -> EventStore {
+        EventStore::new(
+            aggregated_key.to_string(),
+            "Profile".to_string(),
+            version,
+            serde_json::json!({ "name": name }),
+        )
+    }
+
+    fn store() -> Result<EncryptedEventStore<InMemoryBackend>> {
+        Ok(EncryptedEventStore::new(InMemoryBackend::new(), Keyring::single("k1", TEST_IKM)?))
+    }
+
+    #[test]
+    fn test_append_and_read_streams() -> Result<()> {
+        let mut store = store()?;
+        assert_eq!(store.append(&[event("a", 0, "Alice"), event("a", 1, "Alicia")])?, vec![1, 2]);
+        assert_eq!(store.append(&[event("b", 0, "Bob")])?, vec![3]);
+        assert_eq!(store.stream_version("a")?, Some(1));
+        assert_eq!(store.stream_version("c")?, None);
+
+        let stream = store.read_stream("a")?;
+        assert_eq!(stream.len(), 2);
+        assert_eq!(stream[1].event.payload["name"], "Alicia");
+
+        let stored = store.backend().read_stream("b")?;
+        assert_eq!(stored[0].event.key_id, "k1");
+        assert!(!stored[0].event.payload.contains("Bob"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_optimistic_concurrency_on_version() -> Result<()> {
+        let mut store = store()?;
+        store.append(&[event("a", 0, "Alice")])?;
+
+        // Two writers both read version 0; only the first append wins
+        store.append(&[event("a", 1, "first")])?;
+        let conflict = store.append(&[event("a", 1, "second")]);
+        assert!(matches!(
+            conflict,
+            Err(EventStoreError::VersionConflict { expected: 2, actual: 1, .. })
+        ));
+
+        assert!(store.append(&[event("b", 1, "gap")]).is_err());
+        assert!(store.append(&[event("c", 0, "Carol"), event("d", 1, "Dan")]).is_err());
+        assert_eq!(store.read_all(0, 100)?.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_compact_formats_mix_in_one_stream() -> Result<()> {
+        let mut store = store()?;
+        store.append(&[event("a", 0, "Alice")])?;
+        let mut store = EncryptedEventStore {
+            backend: store.backend,
+            ..self::store()?.with_format(SerializationFormat::Cbor)?
+        };
+        store.append(&[event("a", 1, "Alicia")])?;
+
+        let stored = store.backend().read_stream("a")?;
+        assert!(stored[1].event.payload.len() < stored[0].event.payload.len());
+        let names: Vec<String> = store
+            .read_stream("a")?
+            .iter()
+            .map(|recorded| recorded.event.payload["name"].as_str().unwrap_or_default().to_string())
+            .collect();
+        assert_eq!(names, vec!["Alice", "Alicia"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bincode_format_is_rejected() -> Result<()> {
+        let rejected = store()?.with_format(SerializationFormat::Bincode);
+        assert!(matches!(
+            rejected,
+            Err(EventStoreError::EncryptionError(EncryptionError::InvalidConfig(_)))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_all_since_position() -> Result<()> {
+        let mut store = store()?;
+        for version in 0..3 {
+            store.append(&[event("a", version, "Alice")])?;
+            store.append(&[event("b", version, "Bob")])?;
+        }
+
+        let page = store.read_all(2, 3)?;
+        let positions: Vec<u64> = page.iter().map(|recorded| recorded.position).collect();
+        assert_eq!(positions, vec![3, 4, 5]);
+        assert_eq!(page[1].event.aggregated_key, "b");
+
+        assert!(store.read_all(6, 10)?.is_empty());
+        Ok(())
+    }
+}
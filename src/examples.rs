@@ -1,8 +1,11 @@
 use crate::{
-    kryptor::{config::AppConfig, errors::EncryptionError, utilities::KryptorService},
+    kryptor::{
+        compression::CompressionPolicy, config::AppConfig, errors::EncryptionError, utilities::KryptorService,
+    },
     models::{EncryptionContext, Profile},
 };
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UserAccount {
@@ -25,15 +28,35 @@ pub struct Transaction {
 
 pub struct EncryptionService {
     config: AppConfig,
+    compression: CompressionPolicy,
+}
+
+impl Default for EncryptionService {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl EncryptionService {
+    /// Service under `AppConfig::development()`, whose key is public; use
+    /// `with_config` for anything but demos and tests
     pub fn new() -> Self {
+        Self::with_config(AppConfig::development())
+    }
+
+    pub fn with_config(config: AppConfig) -> Self {
         Self {
-            config: AppConfig::new(),
+            config,
+            compression: CompressionPolicy::disabled(),
         }
     }
 
+    /// Compresses large records, e.g. accounts with big `metadata`, before encryption
+    pub fn with_compression(mut self, policy: CompressionPolicy) -> Self {
+        self.compression = policy;
+        self
+    }
+
     /// Encrypt any serializable data with a custom context
     pub fn encrypt_with_context<T, C>(
         &self,
@@ -44,7 +67,8 @@ impl EncryptionService {
         T: Serialize,
         C: Serialize,
     {
-        let mut service = KryptorService::with_context(self.config.ikm_base64.clone(), context)?;
+        let mut service = KryptorService::with_keyring(Arc::clone(&self.config.keyring), context)?
+            .with_compression(self.compression.clone());
         service.encrypt_json(data)
     }
 
@@ -58,7 +82,7 @@ impl EncryptionService {
         T: serde::de::DeserializeOwned,
         C: Serialize,
     {
-        let mut service = KryptorService::with_context(self.config.ikm_base64.clone(), context)?;
+        let mut service = KryptorService::with_keyring(Arc::clone(&self.config.keyring), context)?;
         service.decrypt_json(encrypted_data)
     }
 
@@ -99,8 +123,8 @@ impl EncryptionService {
 }
 
 /// Example demonstrating different types of data encryption
-pub fn demonstrate_advanced_encryption() -> Result<(), EncryptionError> {
-    let service = EncryptionService::new();
+pub fn demonstrate_advanced_encryption(config: AppConfig) -> Result<(), EncryptionError> {
+    let service = EncryptionService::with_config(config);
 
     // Example 1: User Account Encryption
     println!("=== User Account Encryption ===");
@@ -143,7 +167,7 @@ pub fn demonstrate_advanced_encryption() -> Result<(), EncryptionError> {
 
     // Example 3: Bulk Encryption with Different Contexts
     println!("\n=== Bulk Encryption Demo ===");
-    let profiles = vec![
+    let profiles = [
         Profile::new(
             "Alice Smith".to_string(),
             "1985-03-15".to_string(),
@@ -234,6 +258,29 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_compressed_user_account_roundtrip() -> Result<(), EncryptionError> {
+        use crate::kryptor::compression::Compression;
+
+        let account = UserAccount {
+            user_id: "big_user".to_string(),
+            username: "big".to_string(),
+            email: "big@example.com".to_string(),
+            created_at: "2024-01-01T00:00:00Z".to_string(),
+            metadata: serde_json::json!({ "audit": vec!["password changed"; 100] }),
+        };
+        let plain = EncryptionService::new();
+        let compressing = EncryptionService::new()
+            .with_compression(CompressionPolicy::new(Compression::Deflate));
+
+        let encrypted = compressing.encrypt_user_account(&account)?;
+        assert!(encrypted.len() < plain.encrypt_user_account(&account)?.len() / 4);
+        let decrypted = plain.decrypt_user_account(&encrypted, "big_user")?;
+        assert_eq!(decrypted.metadata, account.metadata);
+
+        Ok(())
+    }
+
     #[test]
     fn test_different_contexts_produce_different_ciphertexts() -> Result<(), EncryptionError> {
         let service = EncryptionService::new();
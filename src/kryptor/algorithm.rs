@@ -0,0 +1,190 @@
+use aes_gcm::Aes256Gcm;
+use aes_gcm::aead::{Aead, KeyInit, OsRng, Payload, rand_core::RngCore};
+use aes_gcm_siv::Aes256GcmSiv;
+use chacha20poly1305::{ChaCha20Poly1305, XChaCha20Poly1305};
+
+use crate::kryptor::errors::EncryptionError;
+use crate::kryptor::secret::SecretKey;
+
+pub type Result<T> = std::result::Result<T, EncryptionError>;
+
+/// AEAD algorithms a `KryptorService` can encrypt with. The algorithm id is
+/// recorded in every envelope header, so decryption never depends on the
+/// service's configured algorithm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Algorithm {
+    /// AES-256-GCM with a random 12-byte nonce
+    #[default]
+    Aes256Gcm,
+    /// ChaCha20-Poly1305 with a random 12-byte nonce; fast without AES-NI
+    ChaCha20Poly1305,
+    /// XChaCha20-Poly1305 with a random 24-byte nonce, safe for very high volumes
+    XChaCha20Poly1305,
+    /// AES-256-GCM-SIV with a random 12-byte nonce. Nonce-misuse resistant: a
+    /// repeated nonce only reveals whether two plaintexts are equal, and each
+    /// nonce derives its own message keys, so one context key can encrypt far
+    /// more messages than plain GCM.
+    Aes256GcmSiv,
+    /// Deterministic AES-256-SIV without a nonce. Only `DeterministicKryptor`
+    /// encrypts with it; `seal`/`open` refuse it.
+    Aes256Siv,
+    /// HPKE base mode with X25519, HKDF-SHA256 and AES-256-GCM. The nonce
+    /// field carries the 32-byte encapsulated key. Only `HpkeKryptor`
+    /// encrypts with it; `seal`/`open` refuse it.
+    HpkeX25519Aes256Gcm,
+}
+
+impl Algorithm {
+    /// Stable identifier stored in the envelope header
+    pub fn id(&self) -> u8 {
+        match self {
+            Algorithm::Aes256Gcm => 1,
+            Algorithm::ChaCha20Poly1305 => 2,
+            Algorithm::XChaCha20Poly1305 => 3,
+            Algorithm::Aes256GcmSiv => 4,
+            Algorithm::Aes256Siv => 5,
+            Algorithm::HpkeX25519Aes256Gcm => 6,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Result<Self> {
+        match id {
+            1 => Ok(Algorithm::Aes256Gcm),
+            2 => Ok(Algorithm::ChaCha20Poly1305),
+            3 => Ok(Algorithm::XChaCha20Poly1305),
+            4 => Ok(Algorithm::Aes256GcmSiv),
+            5 => Ok(Algorithm::Aes256Siv),
+            6 => Ok(Algorithm::HpkeX25519Aes256Gcm),
+            other => Err(EncryptionError::UnsupportedAlgorithm(other)),
+        }
+    }
+
+    /// Nonce length in bytes expected by the algorithm
+    pub fn nonce_len(&self) -> usize {
+        match self {
+            Algorithm::Aes256Gcm | Algorithm::ChaCha20Poly1305 | Algorithm::Aes256GcmSiv => 12,
+            Algorithm::XChaCha20Poly1305 => 24,
+            Algorithm::Aes256Siv => 0,
+            Algorithm::HpkeX25519Aes256Gcm => 32,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Algorithm::Aes256Gcm => "AES-256-GCM",
+            Algorithm::ChaCha20Poly1305 => "ChaCha20-Poly1305",
+            Algorithm::XChaCha20Poly1305 => "XChaCha20-Poly1305",
+            Algorithm::Aes256GcmSiv => "AES-256-GCM-SIV",
+            Algorithm::Aes256Siv => "AES-256-SIV",
+            Algorithm::HpkeX25519Aes256Gcm => "HPKE-X25519-SHA256-AES-256-GCM",
+        }
+    }
+
+    /// True for algorithms that map equal plaintexts to equal ciphertexts
+    pub fn is_deterministic(&self) -> bool {
+        matches!(self, Algorithm::Aes256Siv)
+    }
+
+    /// True for algorithms that encrypt to a recipient public key
+    pub fn is_public_key(&self) -> bool {
+        matches!(self, Algorithm::HpkeX25519Aes256Gcm)
+    }
+
+    /// Generates a fresh random nonce of the right length
+    pub fn generate_nonce(&self) -> Vec<u8> {
+        let mut nonce = vec![0u8; self.nonce_len()];
+        OsRng.fill_bytes(&mut nonce);
+        nonce
+    }
+
+    /// Encrypts `plaintext` with a 256-bit key, returning `[Ciphertext | Tag]`
+    pub fn seal(&self, key: &SecretKey<32>, nonce: &[u8], plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        self.check_nonce(nonce)?;
+        let key = key.expose_secret();
+        let payload = Payload { msg: plaintext, aad };
+        let result = match self {
+            Algorithm::Aes256Gcm => Aes256Gcm::new(key.into()).encrypt(nonce.into(), payload),
+            Algorithm::ChaCha20Poly1305 => ChaCha20Poly1305::new(key.into()).encrypt(nonce.into(), payload),
+            Algorithm::XChaCha20Poly1305 => XChaCha20Poly1305::new(key.into()).encrypt(nonce.into(), payload),
+            Algorithm::Aes256GcmSiv => Aes256GcmSiv::new(key.into()).encrypt(nonce.into(), payload),
+            Algorithm::Aes256Siv => return Err(deterministic_misuse()),
+            Algorithm::HpkeX25519Aes256Gcm => return Err(public_key_misuse()),
+        };
+        result.map_err(EncryptionError::EncryptionError)
+    }
+
+    /// Verifies and decrypts `[Ciphertext | Tag]` with a 256-bit key
+    pub fn open(&self, key: &SecretKey<32>, nonce: &[u8], ciphertext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        self.check_nonce(nonce)?;
+        let key = key.expose_secret();
+        let payload = Payload { msg: ciphertext, aad };
+        let result = match self {
+            Algorithm::Aes256Gcm => Aes256Gcm::new(key.into()).decrypt(nonce.into(), payload),
+            Algorithm::ChaCha20Poly1305 => ChaCha20Poly1305::new(key.into()).decrypt(nonce.into(), payload),
+            Algorithm::XChaCha20Poly1305 => XChaCha20Poly1305::new(key.into()).decrypt(nonce.into(), payload),
+            Algorithm::Aes256GcmSiv => Aes256GcmSiv::new(key.into()).decrypt(nonce.into(), payload),
+            Algorithm::Aes256Siv => return Err(deterministic_misuse()),
+            Algorithm::HpkeX25519Aes256Gcm => return Err(public_key_misuse()),
+        };
+        result.map_err(EncryptionError::DecryptionError)
+    }
+
+    fn check_nonce(&self, nonce: &[u8]) -> Result<()> {
+        if nonce.len() != self.nonce_len() {
+            return Err(EncryptionError::InvalidEnvelope(format!(
+                "{} expects a {}-byte nonce, got {}",
+                self.name(),
+                self.nonce_len(),
+                nonce.len()
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn deterministic_misuse() -> EncryptionError {
+    EncryptionError::Other("AES-256-SIV is deterministic; use DeterministicKryptor".to_string())
+}
+
+fn public_key_misuse() -> EncryptionError {
+    EncryptionError::Other("HPKE envelopes are public-key encrypted; use HpkeKryptor".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_algorithm_roundtrips_and_authenticates() -> Result<()> {
+        let key = SecretKey::from([9u8; 32]);
+        let algorithms = [
+            Algorithm::Aes256Gcm,
+            Algorithm::ChaCha20Poly1305,
+            Algorithm::XChaCha20Poly1305,
+            Algorithm::Aes256GcmSiv,
+        ];
+        for algorithm in algorithms {
+            assert_eq!(Algorithm::from_id(algorithm.id())?, algorithm);
+
+            let nonce = algorithm.generate_nonce();
+            let sealed = algorithm.seal(&key, &nonce, b"payload", b"aad")?;
+            assert_eq!(algorithm.open(&key, &nonce, &sealed, b"aad")?, b"payload");
+            assert!(algorithm.open(&key, &nonce, &sealed, b"other").is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_gcm_siv_nonce_reuse_keeps_distinct_plaintexts_distinct() -> Result<()> {
+        let key = SecretKey::from([5u8; 32]);
+        let nonce = [0u8; 12];
+        let first = Algorithm::Aes256GcmSiv.seal(&key, &nonce, b"aaaa", b"")?;
+        let second = Algorithm::Aes256GcmSiv.seal(&key, &nonce, b"aaab", b"")?;
+
+        // Unlike GCM, a repeated nonce does not yield related keystreams
+        let xor: Vec<u8> = first.iter().zip(&second).map(|(a, b)| a ^ b).take(4).collect();
+        assert_ne!(xor, vec![0, 0, 0, 3]);
+        assert_eq!(Algorithm::Aes256GcmSiv.open(&key, &nonce, &second, b"")?, b"aaab");
+        Ok(())
+    }
+}
@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::kryptor::errors::EncryptionError;
+use crate::kryptor::keyring::Keyring;
+use crate::kryptor::secret::SecretKey;
+use crate::models::Profile;
+
+pub type Result<T> = std::result::Result<T, EncryptionError>;
+
+/// HKDF info label separating blind-index keys from encryption keys
+const BLIND_INDEX_INFO_LABEL: &[u8] = b"encry/blind-index/hmac-sha256/v1";
+
+/// Default truncation of the HMAC output, in bytes
+pub const DEFAULT_INDEX_LEN: usize = 16;
+
+/// Canonicalizes a value before it is hashed, so equivalent inputs share an index
+#[derive(Debug, Clone)]
+pub enum Normalizer {
+    Trim,
+    Lowercase,
+    /// Trims and lowercases an email address
+    Email,
+    /// Formats a phone number as E.164 (`+<country code><number>`). Numbers
+    /// with a national trunk prefix `0` get `default_country_code` prepended.
+    E164 { default_country_code: Option<String> },
+    Custom(fn(&str) -> Result<String>),
+}
+
+/// Definition of a blind index: a name (which selects the HMAC key),
+/// normalization steps and output length
+#[derive(Debug, Clone)]
+pub struct BlindIndex {
+    name: String,
+    normalizers: Vec<Normalizer>,
+    truncate_to: usize,
+}
+
+/// Computes truncated HMAC-SHA256 blind indexes keyed from the active IKM.
+///
+/// Each index name gets its own HMAC key via HKDF, and keys are not bound to an
+/// encryption context so a value can be looked up across all aggregates.
+/// Indexes change when the active key rotates and must be recomputed then.
+#[derive(Debug)]
+pub struct BlindIndexer {
+    keyring: Arc<Keyring>,
+    derived_keys: HashMap<(String, String), SecretKey<32>>,
+}
+
+/// Blind indexes for the searchable fields of a `Profile`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProfileIndexes {
+    pub email: String,
+    pub phones: Vec<String>,
+}
+
+impl Normalizer {
+    pub fn apply(&self, value: &str) -> Result<String> {
+        match self {
+            Normalizer::Trim => Ok(value.trim().to_string()),
+            Normalizer::Lowercase => Ok(value.to_lowercase()),
+            Normalizer::Email => Ok(value.trim().to_lowercase()),
+            Normalizer::E164 { default_country_code } => to_e164(value, default_country_code.as_deref()),
+            Normalizer::Custom(normalize) => normalize(value),
+        }
+    }
+}
+
+impl BlindIndex {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            normalizers: Vec::new(),
+            truncate_to: DEFAULT_INDEX_LEN,
+        }
+    }
+
+    /// Blind index for email addresses
+    pub fn email() -> Self {
+        Self::new("email").normalize(Normalizer::Email)
+    }
+
+    /// Blind index for phone numbers, normalized to E.164
+    pub fn phone(default_country_code: Option<&str>) -> Self {
+        Self::new("phone").normalize(Normalizer::E164 {
+            default_country_code: default_country_code.map(str::to_string),
+        })
+    }
+
+    /// Appends a normalization step; steps run in the order they were added
+    pub fn normalize(mut self, normalizer: Normalizer) -> Self {
+        self.normalizers.push(normalizer);
+        self
+    }
+
+    /// Sets the number of HMAC bytes kept (1-32). Shorter indexes produce more
+    /// false positives on lookup but leak less about value frequency.
+    pub fn truncate(mut self, len: usize) -> Self {
+        self.truncate_to = len;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn normalized(&self, value: &str) -> Result<String> {
+        self.normalizers
+            .iter()
+            .try_fold(value.to_string(), |value, normalizer| normalizer.apply(&value))
+    }
+}
+
+impl BlindIndexer {
+    pub fn new(ikm_base64: String) -> Self {
+        Self::from_keyring(Keyring::default_key(ikm_base64))
+    }
+
+    pub fn from_keyring(keyring: impl Into<Arc<Keyring>>) -> Self {
+        Self {
+            keyring: keyring.into(),
+            derived_keys: HashMap::new(),
+        }
+    }
+
+    /// Id of the key the indexes are computed under
+    pub fn key_id(&self) -> &str {
+        &self.keyring.active().key_id
+    }
+
+    fn derive_key(&mut self, index_name: &str) -> Result<SecretKey<32>> {
+        let entry = self.keyring.active();
+        let cache_key = (entry.key_id.clone(), index_name.to_string());
+        if let Some(key) = self.derived_keys.get(&cache_key) {
+            return Ok(key.duplicate());
+        }
+
+        let ikm = entry.decode_ikm()?;
+        let mut info = BLIND_INDEX_INFO_LABEL.to_vec();
+        info.push(0);
+        info.extend_from_slice(index_name.as_bytes());
+
+        let hkdf = Hkdf::<Sha256>::new(None, ikm.expose_secret());
+        let mut key = SecretKey::zeroed();
+        hkdf.expand(&info, key.expose_secret_mut())?;
+
+        self.derived_keys.insert(cache_key, key.duplicate());
+        Ok(key)
+    }
+
+    /// Normalizes `value` and returns its truncated HMAC as lowercase hex
+    pub fn compute(&mut self, index: &BlindIndex, value: &str) -> Result<String> {
+        if index.truncate_to == 0 || index.truncate_to > 32 {
+            return Err(EncryptionError::InvalidConfig(format!(
+                "blind index '{}' truncation must be 1-32 bytes, got {}",
+                index.name, index.truncate_to
+            )));
+        }
+
+        let normalized = index.normalized(value)?;
+        let key = self.derive_key(&index.name)?;
+
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key.expose_secret())
+            .map_err(|e| EncryptionError::Other(format!("HMAC key error: {}", e)))?;
+        mac.update(normalized.as_bytes());
+        let digest = mac.finalize().into_bytes();
+
+        Ok(digest[..index.truncate_to]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect())
+    }
+
+    /// Computes the index of each value, preserving order
+    pub fn compute_all<S: AsRef<str>>(&mut self, index: &BlindIndex, values: &[S]) -> Result<Vec<String>> {
+        values.iter().map(|value| self.compute(index, value.as_ref())).collect()
+    }
+
+    /// Computes the email index and one phone index per entry of `Profile.phones`
+    pub fn index_profile(&mut self, profile: &Profile, default_country_code: Option<&str>) -> Result<ProfileIndexes> {
+        Ok(ProfileIndexes {
+            email: self.compute(&BlindIndex::email(), &profile.email)?,
+            phones: self.compute_all(&BlindIndex::phone(default_country_code), &profile.phones)?,
+        })
+    }
+}
+
+fn to_e164(value: &str, default_country_code: Option<&str>) -> Result<String> {
+    let trimmed = value.trim();
+    let international = trimmed.starts_with('+') || trimmed.starts_with("00");
+
+    // '+' is only accepted as the first character; anywhere else it is rejected below
+    let mut digits: String = trimmed
+        .strip_prefix('+')
+        .unwrap_or(trimmed)
+        .chars()
+        .filter(|c| !matches!(c, ' ' | '-' | '.' | '(' | ')'))
+        .collect();
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err(EncryptionError::NormalizationError(format!("'{}' is not a phone number", value)));
+    }
+
+    if international {
+        if let Some(rest) = digits.strip_prefix("00").filter(|_| !trimmed.starts_with('+')) {
+            digits = rest.to_string();
+        }
+    } else {
+        let country_code = default_country_code.ok_or_else(|| {
+            EncryptionError::NormalizationError(format!("'{}' has no country code and no default is set", value))
+        })?;
+        let national = digits.strip_prefix('0').unwrap_or(&digits);
+        digits = format!("{}{}", country_code.trim_start_matches('+'), national);
+    }
+
+    // E.164 numbers have at most 15 digits
+    if !(8..=15).contains(&digits.len()) {
+        return Err(EncryptionError::NormalizationError(format!(
+            "'{}' does not normalize to a valid E.164 number",
+            value
+        )));
+    }
+
+    Ok(format!("+{}", digits))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_IKM: &str = "rph2pwTQCx+TD/lk+7o9igzQw5A7FU3+S+Z24Cf9Duk=";
+
+    #[test]
+    fn test_e164_normalization() -> Result<()> {
+        assert_eq!(to_e164("+254 712-345-678", None)?, "+254712345678");
+        assert_eq!(to_e164("00254712345678", None)?, "+254712345678");
+        assert_eq!(to_e164("0712 345678", Some("254"))?, "+254712345678");
+        assert!(to_e164("0712345678", None).is_err());
+        assert!(to_e164("call me", Some("254")).is_err());
+        assert!(to_e164("25+4712345678", Some("254")).is_err());
+        assert!(to_e164("++254712345678", None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_equivalent_values_share_an_index() -> Result<()> {
+        let mut indexer = BlindIndexer::new(TEST_IKM.to_string());
+        let email = BlindIndex::email();
+
+        let a = indexer.compute(&email, "Alice@Example.com ")?;
+        let b = indexer.compute(&email, "alice@example.com")?;
+        assert_eq!(a, b);
+        assert_eq!(a.len(), DEFAULT_INDEX_LEN * 2);
+
+        // The index name selects the key, so the same value differs across indexes
+        let other = indexer.compute(&BlindIndex::new("username").normalize(Normalizer::Email), "alice@example.com")?;
+        assert_ne!(a, other);
+
+        let short = indexer.compute(&BlindIndex::email().truncate(4), "alice@example.com")?;
+        assert_eq!(short, a[..8]);
+        for len in [0, 33] {
+            assert!(matches!(
+                indexer.compute(&BlindIndex::email().truncate(len), "alice@example.com"),
+                Err(EncryptionError::InvalidConfig(_))
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_profile_indexes() -> Result<()> {
+        let mut indexer = BlindIndexer::new(TEST_IKM.to_string());
+        let profile = Profile::new(
+            "Mwaura S W".to_string(),
+            "1990-01-01".to_string(),
+            "smwaura@outlook.com".to_string(),
+            vec!["+254712345678".to_string(), "0712345679".to_string()],
+        );
+
+        let indexes = indexer.index_profile(&profile, Some("254"))?;
+        assert_eq!(indexes.phones.len(), 2);
+        assert_eq!(
+            indexes.phones[1],
+            indexer.compute(&BlindIndex::phone(None), "+254712345679")?
+        );
+        Ok(())
+    }
+}
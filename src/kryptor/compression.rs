@@ -0,0 +1,208 @@
+use std::collections::HashSet;
+use std::io::{Read, Write};
+
+use base64::{Engine as _, engine::general_purpose};
+use serde::Serialize;
+
+use crate::kryptor::errors::EncryptionError;
+
+pub type Result<T> = std::result::Result<T, EncryptionError>;
+
+/// Plaintexts shorter than this stay uncompressed unless the policy says
+/// otherwise; below it the codec framing tends to outweigh the savings
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 1024;
+
+/// Largest plaintext a compressed payload may inflate to unless the policy
+/// raises it; without a cap a few kilobytes could expand into gigabytes
+pub const DEFAULT_MAX_DECOMPRESSED_LEN: usize = 64 * 1024 * 1024;
+
+/// Codec applied to the plaintext before encryption. It is stored in the low
+/// bits of the envelope flags, so the reader needs no configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    /// Zstandard at level 3; the better ratio and speed of the two
+    Zstd,
+    /// Raw DEFLATE (RFC 1951) at the default level
+    Deflate,
+}
+
+/// When `KryptorService` compresses, and how far it inflates on decryption.
+/// Compression is off by default: it makes ciphertext length depend on
+/// content, see the README before enabling it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompressionPolicy {
+    compression: Compression,
+    threshold: usize,
+    /// Base64 JSON contexts, as stored by `KryptorService`, never compressed
+    excluded_contexts: HashSet<String>,
+    /// `None` for `DEFAULT_MAX_DECOMPRESSED_LEN`
+    max_decompressed_len: Option<usize>,
+}
+
+impl Compression {
+    /// Value stored in the envelope flags
+    pub fn id(&self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Zstd => 1,
+            Compression::Deflate => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Result<Self> {
+        match id {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Zstd),
+            2 => Ok(Compression::Deflate),
+            other => Err(EncryptionError::InvalidEnvelope(format!("unknown compression {}", other))),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Zstd => "zstd",
+            Compression::Deflate => "deflate",
+        }
+    }
+
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Zstd => zstd::encode_all(data, zstd::DEFAULT_COMPRESSION_LEVEL).map_err(|e| codec_error(self, e)),
+            Compression::Deflate => {
+                let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data).map_err(|e| codec_error(self, e))?;
+                encoder.finish().map_err(|e| codec_error(self, e))
+            }
+        }
+    }
+
+    /// Inflates `data`, failing with `CodecError` once the output would
+    /// exceed `limit` bytes
+    pub fn decompress(&self, data: &[u8], limit: usize) -> Result<Vec<u8>> {
+        // One byte past the limit tells a full-size payload from an oversized one
+        let cap = limit as u64 + 1;
+        let mut out = Vec::new();
+        match self {
+            Compression::None => out.extend_from_slice(data),
+            Compression::Zstd => {
+                zstd::Decoder::with_buffer(data)
+                    .and_then(|decoder| decoder.take(cap).read_to_end(&mut out))
+                    .map_err(|e| codec_error(self, e))?;
+            }
+            Compression::Deflate => {
+                flate2::read::DeflateDecoder::new(data)
+                    .take(cap)
+                    .read_to_end(&mut out)
+                    .map_err(|e| codec_error(self, e))?;
+            }
+        }
+        if out.len() > limit {
+            return Err(codec_error(self, format!("output exceeds the {} byte limit", limit)));
+        }
+        Ok(out)
+    }
+}
+
+impl CompressionPolicy {
+    /// Never compresses; the default
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// Compresses plaintexts of at least `DEFAULT_COMPRESSION_THRESHOLD` bytes
+    pub fn new(compression: Compression) -> Self {
+        Self {
+            compression,
+            threshold: DEFAULT_COMPRESSION_THRESHOLD,
+            excluded_contexts: HashSet::new(),
+            max_decompressed_len: None,
+        }
+    }
+
+    /// Minimum plaintext length, in bytes, that gets compressed
+    pub fn with_threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Largest plaintext, in bytes, a compressed payload may inflate to on
+    /// decryption
+    pub fn with_max_decompressed_len(mut self, max_decompressed_len: usize) -> Self {
+        self.max_decompressed_len = Some(max_decompressed_len);
+        self
+    }
+
+    /// Never compresses under `context`, e.g. for records that mix secrets
+    /// with attacker-chosen input
+    pub fn exclude_context<T: Serialize>(mut self, context: &T) -> Result<Self> {
+        let context_base64 = general_purpose::STANDARD.encode(serde_json::to_vec(context)?);
+        self.excluded_contexts.insert(context_base64);
+        Ok(self)
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    pub fn max_decompressed_len(&self) -> usize {
+        self.max_decompressed_len.unwrap_or(DEFAULT_MAX_DECOMPRESSED_LEN)
+    }
+
+    /// Codec for a plaintext of `len` bytes encrypted under `context_base64`
+    pub fn select(&self, context_base64: &str, len: usize) -> Compression {
+        if len < self.threshold || self.excluded_contexts.contains(context_base64) {
+            return Compression::None;
+        }
+        self.compression
+    }
+}
+
+fn codec_error(compression: &Compression, err: impl std::fmt::Display) -> EncryptionError {
+    EncryptionError::CodecError(format!("{}: {}", compression.name(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codecs_round_trip() -> Result<()> {
+        let data = "metadata ".repeat(200).into_bytes();
+        for compression in [Compression::Zstd, Compression::Deflate] {
+            let compressed = compression.compress(&data)?;
+            assert!(compressed.len() < data.len() / 10);
+            assert_eq!(compression.decompress(&compressed, data.len())?, data);
+            assert_eq!(Compression::from_id(compression.id())?, compression);
+            assert!(compression.decompress(b"not compressed", data.len()).is_err());
+
+            // A payload inflating past the limit is refused, not buffered
+            let oversized = compression.decompress(&compressed, data.len() - 1);
+            assert!(matches!(oversized, Err(EncryptionError::CodecError(_))));
+        }
+        assert!(Compression::from_id(3).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_policy_threshold_and_excluded_contexts() -> Result<()> {
+        let policy = CompressionPolicy::new(Compression::Zstd)
+            .with_threshold(100)
+            .exclude_context(&"secrets")?;
+        let context = general_purpose::STANDARD.encode(serde_json::to_vec(&"events")?);
+        let excluded = general_purpose::STANDARD.encode(serde_json::to_vec(&"secrets")?);
+
+        assert_eq!(policy.select(&context, 100), Compression::Zstd);
+        assert_eq!(policy.select(&context, 99), Compression::None);
+        assert_eq!(policy.select(&excluded, 10_000), Compression::None);
+        assert_eq!(CompressionPolicy::disabled().select(&context, 10_000), Compression::None);
+        Ok(())
+    }
+}
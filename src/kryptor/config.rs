@@ -1,21 +1,406 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use crate::kryptor::errors::EncryptionError;
+use crate::kryptor::keyring::{KeyEntry, Keyring};
+use crate::kryptor::kms::KeyManagementService;
+use crate::kryptor::passphrase::KeyDescriptor;
+use crate::kryptor::secret::SecretString;
+use crate::kryptor::utilities::DEFAULT_KEY_ID;
+
+pub type Result<T> = std::result::Result<T, EncryptionError>;
+
+/// Base64 IKM for a single key
+pub const ENV_IKM: &str = "ENCRY_IKM";
+/// Path to a key file holding a base64 IKM or a JSON keyring
+pub const ENV_KEY_FILE: &str = "ENCRY_KEY_FILE";
+/// Key id recorded for a single IKM
+pub const ENV_KEY_ID: &str = "ENCRY_KEY_ID";
+/// Path to a TOML or JSON config file
+pub const ENV_CONFIG: &str = "ENCRY_CONFIG";
+/// Passphrase for a key file holding a `KeyDescriptor`
+pub const ENV_PASSPHRASE: &str = "ENCRY_PASSPHRASE";
+
+/// Minimum decoded IKM length; HKDF-SHA256 needs 256 bits of input to give 256-bit keys
+pub const MIN_IKM_LEN: usize = 32;
+
+/// Publicly known key used by `AppConfig::development`
+const DEVELOPMENT_IKM: &str = "rph2pwTQCx+TD/lk+7o9igzQw5A7FU3+S+Z24Cf9Duk=";
+
+/// Cloning shares the keyring; it never copies the IKMs
+#[derive(Debug, Clone)]
 pub struct AppConfig {
-    pub ikm_base64: String,
+    pub keyring: Arc<Keyring>,
+    /// Source of package DEKs for services built from this config
+    pub kms: Option<Arc<dyn KeyManagementService>>,
 }
 
 impl AppConfig {
-    pub fn new() -> Self {
+    /// Loads the configuration from the process environment, see `ConfigLoader`
+    pub fn load() -> Result<Self> {
+        ConfigLoader::from_env().load()
+    }
+
+    /// Loads the configuration from a TOML or JSON config file only
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        ConfigLoader::with_env(Vec::new()).config_file(path.as_ref()).load()
+    }
+
+    /// Development configuration with a built-in key that is published in
+    /// this repository. Anything encrypted with it is readable by anyone;
+    /// never use it outside of demos and tests.
+    pub fn development() -> Self {
         Self {
-            ikm_base64: "rph2pwTQCx+TD/lk+7o9igzQw5A7FU3+S+Z24Cf9Duk=".to_string(),
+            keyring: Arc::new(Keyring::default_key(DEVELOPMENT_IKM)),
+            kms: None,
         }
     }
 
+    /// Configuration with a single IKM that is not validated; a malformed or
+    /// short IKM only fails once a key is derived from it
+    #[deprecated(note = "use `try_with_ikm`, which validates the IKM up front")]
     pub fn with_ikm(ikm_base64: String) -> Self {
-        Self { ikm_base64 }
+        Self {
+            keyring: Arc::new(Keyring::default_key(ikm_base64)),
+            kms: None,
+        }
+    }
+
+    /// Configuration with a single IKM, which must decode to at least `MIN_IKM_LEN` bytes
+    pub fn try_with_ikm(ikm_base64: String) -> Result<Self> {
+        Self::with_keyring(Keyring::default_key(ikm_base64))
+    }
+
+    /// Validates that every key in the keyring is at least `MIN_IKM_LEN` bytes
+    pub fn with_keyring(keyring: Keyring) -> Result<Self> {
+        for entry in keyring.entries() {
+            validate_ikm(entry)?;
+        }
+        Ok(Self {
+            keyring: Arc::new(keyring),
+            kms: None,
+        })
+    }
+
+    /// Wraps package DEKs under `kms`, see `KryptorService::with_kms`
+    pub fn with_kms(mut self, kms: Arc<dyn KeyManagementService>) -> Self {
+        self.kms = Some(kms);
+        self
     }
 }
 
-impl Default for AppConfig {
-    fn default() -> Self {
-        Self::new()
+/// Layered configuration loading.
+///
+/// The first source that supplies a key wins, in this order:
+///
+/// 1. a key file set with `key_file` (e.g. from a command-line flag)
+/// 2. the key file named by `ENCRY_KEY_FILE`
+/// 3. the base64 IKM in `ENCRY_IKM` (or the variable set with `ikm_var`)
+/// 4. the config file set with `config_file`, else the one named by `ENCRY_CONFIG`
+///
+/// A key file holds a base64 IKM, a JSON keyring or a JSON `KeyDescriptor`;
+/// a descriptor derives the IKM from the passphrase set with `passphrase`,
+/// else `ENCRY_PASSPHRASE`, and records it under the descriptor's own key id.
+///
+/// A single IKM is recorded under the key id set with `key_id`, else
+/// `ENCRY_KEY_ID`, else the config file's `key_id` when the key comes from
+/// it, else `"default"`. The config file is neither read nor parsed when an
+/// earlier source supplies the key. Loading fails when no source supplies one.
+#[derive(Debug)]
+pub struct ConfigLoader {
+    env: HashMap<String, SecretString>,
+    ikm_var: String,
+    key_file: Option<PathBuf>,
+    config_file: Option<PathBuf>,
+    key_id: Option<String>,
+    passphrase: Option<SecretString>,
+}
+
+/// Contents of a config file. Exactly one of `ikm`, `key_file` and `keyring`
+/// must be set; a relative `key_file` is resolved against the config file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    ikm: Option<SecretString>,
+    key_id: Option<String>,
+    key_file: Option<PathBuf>,
+    keyring: Option<Keyring>,
+}
+
+impl ConfigLoader {
+    pub fn from_env() -> Self {
+        Self::with_env(std::env::vars())
+    }
+
+    /// Loader reading `vars` instead of the process environment
+    pub fn with_env<I: IntoIterator<Item = (String, String)>>(vars: I) -> Self {
+        Self {
+            env: vars.into_iter().map(|(name, value)| (name, SecretString::from(value))).collect(),
+            ikm_var: ENV_IKM.to_string(),
+            key_file: None,
+            config_file: None,
+            key_id: None,
+            passphrase: None,
+        }
+    }
+
+    /// Reads the base64 IKM from `name` instead of `ENCRY_IKM`
+    pub fn ikm_var(mut self, name: impl Into<String>) -> Self {
+        self.ikm_var = name.into();
+        self
+    }
+
+    pub fn key_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.key_file = Some(path.into());
+        self
+    }
+
+    pub fn config_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config_file = Some(path.into());
+        self
+    }
+
+    pub fn key_id(mut self, key_id: impl Into<String>) -> Self {
+        self.key_id = Some(key_id.into());
+        self
+    }
+
+    /// Passphrase for a `KeyDescriptor` key file, e.g. read from a prompt
+    pub fn passphrase(mut self, passphrase: impl Into<SecretString>) -> Self {
+        self.passphrase = Some(passphrase.into());
+        self
+    }
+
+    pub fn load(&self) -> Result<AppConfig> {
+        let key_id = self.key_id.clone().or_else(|| self.var(ENV_KEY_ID).map(str::to_string));
+        let default_key_id = || DEFAULT_KEY_ID.to_string();
+
+        let passphrase = self.passphrase.as_ref().or_else(|| {
+            self.env
+                .get(ENV_PASSPHRASE)
+                .filter(|value| !value.expose_secret().is_empty())
+        });
+
+        let key_file = self.key_file.clone().or_else(|| self.var(ENV_KEY_FILE).map(PathBuf::from));
+        let config_path = self.config_file.clone().or_else(|| self.var(ENV_CONFIG).map(PathBuf::from));
+        let keyring = if let Some(path) = key_file {
+            read_key_file(&path, &key_id.unwrap_or_else(default_key_id), passphrase)?
+        } else if let Some(ikm) = self.var(&self.ikm_var) {
+            parse_ikm(ikm, &key_id.unwrap_or_else(default_key_id))?
+        } else if let Some(path) = config_path {
+            // Only read once it is known to be the key source
+            let file = read_config_file(&path)?;
+            let key_id = key_id.or_else(|| file.key_id.clone()).unwrap_or_else(default_key_id);
+            keyring_from_config(file, &path, &key_id, passphrase)?
+        } else {
+            return Err(EncryptionError::InvalidConfig(format!(
+                "no key supplied; set {}, {} or {}",
+                self.ikm_var, ENV_KEY_FILE, ENV_CONFIG
+            )));
+        };
+
+        AppConfig::with_keyring(keyring)
+    }
+
+    fn var(&self, name: &str) -> Option<&str> {
+        self.env.get(name).map(SecretString::expose_secret).filter(|value| !value.trim().is_empty())
+    }
+}
+
+/// Parses a key file holding a base64 IKM, a JSON keyring or a JSON key descriptor
+fn parse_key_source(source: &str, key_id: &str, passphrase: Option<&SecretString>) -> Result<Keyring> {
+    let source = source.trim();
+    if source.starts_with('[') {
+        return Ok(serde_json::from_str(source)?);
+    }
+    if source.starts_with('{') {
+        let descriptor: KeyDescriptor = serde_json::from_str(source)?;
+        let passphrase = passphrase.ok_or_else(|| {
+            EncryptionError::InvalidConfig(format!(
+                "key file holds a key descriptor; set {} to derive key '{}'",
+                ENV_PASSPHRASE, descriptor.key_id
+            ))
+        })?;
+        return descriptor.derive_keyring(passphrase);
+    }
+    parse_ikm(source, key_id)
+}
+
+fn parse_ikm(ikm_base64: &str, key_id: &str) -> Result<Keyring> {
+    let keyring = Keyring::single(key_id, ikm_base64.trim())?;
+    validate_ikm(keyring.active())?;
+    Ok(keyring)
+}
+
+fn validate_ikm(entry: &KeyEntry) -> Result<()> {
+    let ikm = entry.decode_ikm().map_err(|e| {
+        EncryptionError::InvalidConfig(format!("IKM for key '{}' is not valid base64: {}", entry.key_id, e))
+    })?;
+    if ikm.len() < MIN_IKM_LEN {
+        return Err(EncryptionError::InvalidConfig(format!(
+            "IKM for key '{}' is {} bytes; at least {} are required",
+            entry.key_id,
+            ikm.len(),
+            MIN_IKM_LEN
+        )));
+    }
+    Ok(())
+}
+
+fn read_key_file(path: &Path, key_id: &str, passphrase: Option<&SecretString>) -> Result<Keyring> {
+    let source = fs::read_to_string(path)
+        .map(SecretString::from)
+        .map_err(|e| EncryptionError::InvalidConfig(format!("cannot read key file {}: {}", path.display(), e)))?;
+    parse_key_source(source.expose_secret(), key_id, passphrase)
+}
+
+fn read_config_file(path: &Path) -> Result<ConfigFile> {
+    let source = fs::read_to_string(path)
+        .map(SecretString::from)
+        .map_err(|e| EncryptionError::InvalidConfig(format!("cannot read config file {}: {}", path.display(), e)))?;
+    let source = source.expose_secret();
+
+    let is_json = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => true,
+        Some("toml") => false,
+        _ => source.trim_start().starts_with('{'),
+    };
+    if is_json {
+        Ok(serde_json::from_str(source)?)
+    } else {
+        toml::from_str(source)
+            .map_err(|e| EncryptionError::InvalidConfig(format!("invalid config file {}: {}", path.display(), e)))
+    }
+}
+
+fn keyring_from_config(file: ConfigFile, path: &Path, key_id: &str, passphrase: Option<&SecretString>) -> Result<Keyring> {
+    match (file.ikm, file.key_file, file.keyring) {
+        (Some(ikm), None, None) => parse_ikm(ikm.expose_secret(), key_id),
+        (None, Some(key_file), None) => {
+            let key_file = match path.parent() {
+                Some(dir) if key_file.is_relative() => dir.join(key_file),
+                _ => key_file,
+            };
+            read_key_file(&key_file, key_id, passphrase)
+        }
+        (None, None, Some(keyring)) => Ok(keyring),
+        (None, None, None) => Err(EncryptionError::InvalidConfig(format!(
+            "config file {} does not supply a key",
+            path.display()
+        ))),
+        _ => Err(EncryptionError::InvalidConfig(format!(
+            "config file {} must set only one of ikm, key_file and keyring",
+            path.display()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kryptor::passphrase::Argon2Params;
+
+    const IKM: &str = "rph2pwTQCx+TD/lk+7o9igzQw5A7FU3+S+Z24Cf9Duk=";
+    const OTHER_IKM: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+
+    fn env(vars: &[(&str, &str)]) -> ConfigLoader {
+        ConfigLoader::with_env(vars.iter().map(|(k, v)| (k.to_string(), v.to_string())))
+    }
+
+    fn temp_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("encry-config-{}-{}", std::process::id(), name));
+        fs::write(&path, contents).expect("write temp file");
+        path
+    }
+
+    #[test]
+    fn test_refuses_missing_and_short_keys() {
+        assert!(matches!(env(&[]).load(), Err(EncryptionError::InvalidConfig(_))));
+        assert!(env(&[(ENV_IKM, "")]).load().is_err());
+        assert!(env(&[(ENV_IKM, "c2hvcnQ=")]).load().is_err());
+        assert!(AppConfig::try_with_ikm("not base64!".to_string()).is_err());
+        assert!(AppConfig::try_with_ikm(DEVELOPMENT_IKM.to_string()).is_ok());
+        #[allow(deprecated)]
+        let unchecked = AppConfig::with_ikm("not base64!".to_string());
+        assert!(unchecked.keyring.active().decode_ikm().is_err());
+    }
+
+    #[test]
+    fn test_precedence() -> Result<()> {
+        let key_file = temp_file("key", OTHER_IKM);
+        let config = temp_file("config.toml", &format!("ikm = \"{}\"\nkey_id = \"from-file\"\n", IKM));
+        let key_file_var = key_file.to_string_lossy().to_string();
+        let config_var = config.to_string_lossy().to_string();
+
+        let from_config = env(&[(ENV_CONFIG, &config_var)]).load()?;
+        assert_eq!(from_config.keyring.active().key_id, "from-file");
+        assert_eq!(from_config.keyring.active().ikm_base64.expose_secret(), IKM);
+
+        let from_env = env(&[(ENV_CONFIG, &config_var), (ENV_IKM, OTHER_IKM), (ENV_KEY_ID, "k2")]).load()?;
+        assert_eq!(from_env.keyring.active().key_id, "k2");
+        assert_eq!(from_env.keyring.active().ikm_base64.expose_secret(), OTHER_IKM);
+
+        let from_key_file = env(&[(ENV_IKM, IKM), (ENV_KEY_FILE, &key_file_var)]).load()?;
+        assert_eq!(from_key_file.keyring.active().ikm_base64.expose_secret(), OTHER_IKM);
+
+        // A config file that is not the key source is never read
+        let broken = temp_file("broken.toml", "ikm = ");
+        let broken_var = broken.to_string_lossy().to_string();
+        let from_env = env(&[(ENV_CONFIG, &broken_var), (ENV_IKM, OTHER_IKM)]).load()?;
+        assert_eq!(from_env.keyring.active().key_id, DEFAULT_KEY_ID);
+        let from_key_file = env(&[(ENV_KEY_FILE, &key_file_var)]).config_file("/nonexistent/encry.toml").load()?;
+        assert_eq!(from_key_file.keyring.active().ikm_base64.expose_secret(), OTHER_IKM);
+        assert!(env(&[(ENV_CONFIG, &broken_var)]).load().is_err());
+
+        fs::remove_file(key_file)?;
+        fs::remove_file(config)?;
+        fs::remove_file(broken)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_config_with_keyring() -> Result<()> {
+        let config = temp_file(
+            "config.json",
+            &format!(
+                r#"{{"keyring": [{{"key_id": "k1", "ikm_base64": "{}", "status": "active", "created_at": 0}}]}}"#,
+                IKM
+            ),
+        );
+
+        let loaded = AppConfig::from_file(&config)?;
+        assert_eq!(loaded.keyring.active().key_id, "k1");
+
+        fs::remove_file(config)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_descriptor_needs_passphrase() -> Result<()> {
+        let params = Argon2Params {
+            memory_kib: 64,
+            iterations: 1,
+            parallelism: 1,
+        };
+        let descriptor = KeyDescriptor::generate("pass-1", params)?;
+        let key_file = temp_file("descriptor", &serde_json::to_string(&descriptor)?);
+        let key_file_var = key_file.to_string_lossy().to_string();
+
+        assert!(env(&[(ENV_KEY_FILE, &key_file_var)]).load().is_err());
+
+        let first = env(&[(ENV_KEY_FILE, &key_file_var), (ENV_PASSPHRASE, "hunter2 hunter2")]).load()?;
+        let second = env(&[(ENV_KEY_FILE, &key_file_var)]).passphrase("hunter2 hunter2").load()?;
+        assert_eq!(first.keyring.active().key_id, "pass-1");
+        assert_eq!(
+            first.keyring.active().ikm_base64.expose_secret(),
+            second.keyring.active().ikm_base64.expose_secret()
+        );
+
+        fs::remove_file(key_file)?;
+        Ok(())
     }
 }
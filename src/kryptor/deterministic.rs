@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use aes_siv::KeyInit;
+use aes_siv::siv::Aes256Siv;
+use base64::{Engine as _, engine::general_purpose};
+use hkdf::Hkdf;
+use serde::{Serialize, de::DeserializeOwned};
+use sha2::Sha256;
+
+use crate::kryptor::algorithm::Algorithm;
+use crate::kryptor::envelope::{Envelope, EnvelopeHeader};
+use crate::kryptor::errors::EncryptionError;
+use crate::kryptor::keyring::{KeyStatus, Keyring};
+use crate::kryptor::secret::SecretKey;
+
+pub type Result<T> = std::result::Result<T, EncryptionError>;
+
+/// HKDF info label separating deterministic keys from the randomized context keys
+const DETERMINISTIC_INFO_LABEL: &[u8] = b"encry/deterministic/aes-siv/v1";
+
+/// Deterministic encryption with AES-256-SIV, for equality lookups only.
+///
+/// The same plaintext under the same context and key id always produces the
+/// same ciphertext, so an encrypted column can be matched with a plain `=`
+/// query. That is also what it leaks: anyone holding the ciphertexts learns
+/// which rows share a value. Use `KryptorService` for everything that is not a
+/// lookup key. Rotating the active key changes every ciphertext, so lookup
+/// columns have to be rewritten after a rotation.
+#[derive(Debug)]
+pub struct DeterministicKryptor {
+    keyring: Arc<Keyring>,
+    context_base64: String,
+    derived_keys: HashMap<String, SecretKey<64>>,
+}
+
+impl DeterministicKryptor {
+    pub fn new(ikm_base64: String, context_base64: String) -> Self {
+        Self::from_keyring(Keyring::default_key(ikm_base64), context_base64)
+    }
+
+    pub fn from_keyring(keyring: impl Into<Arc<Keyring>>, context_base64: String) -> Self {
+        Self {
+            keyring: keyring.into(),
+            context_base64,
+            derived_keys: HashMap::new(),
+        }
+    }
+
+    pub fn with_keyring<T: Serialize>(keyring: impl Into<Arc<Keyring>>, context: &T) -> Result<Self> {
+        let context_json = serde_json::to_string(context)?;
+        let context_base64 = general_purpose::STANDARD.encode(&context_json);
+        Ok(Self::from_keyring(keyring, context_base64))
+    }
+
+    /// Derives the 512-bit AES-SIV key for a keyring entry. The HKDF info is
+    /// prefixed with its own label, so it never equals a randomized-mode key.
+    fn derive_key_for(&mut self, key_id: &str) -> Result<SecretKey<64>> {
+        if let Some(key) = self.derived_keys.get(key_id) {
+            return Ok(key.duplicate());
+        }
+
+        let entry = self
+            .keyring
+            .get(key_id)
+            .ok_or_else(|| EncryptionError::UnknownKey(key_id.to_string()))?;
+
+        let ikm = entry.decode_ikm()?;
+        let context = general_purpose::STANDARD.decode(&self.context_base64)?;
+        let mut info = DETERMINISTIC_INFO_LABEL.to_vec();
+        info.push(0);
+        info.extend_from_slice(&context);
+
+        let hkdf = Hkdf::<Sha256>::new(None, ikm.expose_secret());
+        let mut key = SecretKey::zeroed();
+        hkdf.expand(&info, key.expose_secret_mut())?;
+
+        self.derived_keys.insert(key_id.to_string(), key.duplicate());
+        Ok(key)
+    }
+
+    /// Deterministically encrypts any serializable type
+    pub fn encrypt_json<T: Serialize>(&mut self, data: &T) -> Result<String> {
+        self.encrypt_json_with_aad(data, &[])
+    }
+
+    /// Deterministically encrypts any serializable type, binding it to `aad`.
+    /// Equal plaintexts only produce equal ciphertexts under equal `aad`.
+    pub fn encrypt_json_with_aad<T: Serialize>(&mut self, data: &T, aad: &[u8]) -> Result<String> {
+        let json = serde_json::to_vec(data)?;
+        self.encrypt_bytes_with_aad(&json, aad)
+    }
+
+    pub fn decrypt_json<T: DeserializeOwned>(&mut self, encrypted_base64: &str) -> Result<T> {
+        self.decrypt_json_with_aad(encrypted_base64, &[])
+    }
+
+    pub fn decrypt_json_with_aad<T: DeserializeOwned>(&mut self, encrypted_base64: &str, aad: &[u8]) -> Result<T> {
+        let json = self.decrypt_bytes_with_aad(encrypted_base64, aad)?;
+        Ok(serde_json::from_slice(&json)?)
+    }
+
+    /// Deterministically encrypts raw bytes under the active key.
+    /// Returns a base64-encoded envelope of [Header | SIV Tag | Ciphertext].
+    pub fn encrypt_bytes(&mut self, plaintext: &[u8]) -> Result<String> {
+        self.encrypt_bytes_with_aad(plaintext, &[])
+    }
+
+    pub fn encrypt_bytes_with_aad(&mut self, plaintext: &[u8], aad: &[u8]) -> Result<String> {
+        let key_id = self.keyring.active().key_id.clone();
+        let key = self.derive_key_for(&key_id)?;
+
+        let header = EnvelopeHeader::new(Algorithm::Aes256Siv, key_id, Vec::new())?;
+        let mut cipher = Aes256Siv::new(key.expose_secret().into());
+        let ciphertext = cipher
+            .encrypt([header.to_bytes().as_slice(), aad], plaintext)
+            .map_err(EncryptionError::EncryptionError)?;
+
+        Ok(general_purpose::STANDARD.encode(Envelope { header, ciphertext }.to_bytes()))
+    }
+
+    pub fn decrypt_bytes(&mut self, encoded_b64: &str) -> Result<Vec<u8>> {
+        self.decrypt_bytes_with_aad(encoded_b64, &[])
+    }
+
+    pub fn decrypt_bytes_with_aad(&mut self, encoded_b64: &str, aad: &[u8]) -> Result<Vec<u8>> {
+        let data = general_purpose::STANDARD.decode(encoded_b64)?;
+        let envelope = Envelope::parse(&data)?;
+        if envelope.header.algorithm != Algorithm::Aes256Siv {
+            return Err(EncryptionError::InvalidEnvelope(format!(
+                "{} ciphertexts are not deterministic; decrypt them with KryptorService",
+                envelope.header.algorithm.name()
+            )));
+        }
+
+        let status = self
+            .keyring
+            .get(&envelope.header.key_id)
+            .map(|entry| entry.status)
+            .ok_or_else(|| EncryptionError::UnknownKey(envelope.header.key_id.clone()))?;
+        if status == KeyStatus::Retired {
+            return Err(EncryptionError::KeyRetired(envelope.header.key_id.clone()));
+        }
+
+        let key = self.derive_key_for(&envelope.header.key_id)?;
+        let mut cipher = Aes256Siv::new(key.expose_secret().into());
+        cipher
+            .decrypt([envelope.header.to_bytes().as_slice(), aad], &envelope.ciphertext)
+            .map_err(EncryptionError::DecryptionError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kryptor::utilities::KryptorService;
+
+    const TEST_IKM: &str = "rph2pwTQCx+TD/lk+7o9igzQw5A7FU3+S+Z24Cf9Duk=";
+
+    #[test]
+    fn test_same_plaintext_and_context_give_same_ciphertext() -> Result<()> {
+        let keyring = Keyring::single("k1", TEST_IKM)?;
+        let mut first = DeterministicKryptor::with_keyring(keyring.duplicate(), &"email")?;
+        let mut second = DeterministicKryptor::with_keyring(keyring.duplicate(), &"email")?;
+        let mut other_context = DeterministicKryptor::with_keyring(keyring, &"phone")?;
+
+        let a = first.encrypt_json(&"alice@example.com")?;
+        let b = second.encrypt_json(&"alice@example.com")?;
+        assert_eq!(a, b);
+        assert_ne!(a, first.encrypt_json(&"bob@example.com")?);
+        assert_ne!(a, other_context.encrypt_json(&"alice@example.com")?);
+
+        assert_eq!(second.decrypt_json::<String>(&a)?, "alice@example.com");
+        Ok(())
+    }
+
+    #[test]
+    fn test_modes_do_not_cross_decrypt() -> Result<()> {
+        let mut deterministic = DeterministicKryptor::new(TEST_IKM.to_string(), "Y3R4".to_string());
+        let mut randomized = KryptorService::new(TEST_IKM.to_string(), "Y3R4".to_string());
+
+        let det = deterministic.encrypt_bytes(b"value")?;
+        let rnd = randomized.encrypt_bytes(b"value")?;
+
+        assert!(randomized.decrypt_bytes(&det).is_err());
+        assert!(deterministic.decrypt_bytes(&rnd).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,86 @@
+use base64::{Engine as _, engine::general_purpose};
+
+use crate::kryptor::errors::EncryptionError;
+
+pub type Result<T> = std::result::Result<T, EncryptionError>;
+
+/// Text form of ciphertexts returned by the `String` methods of
+/// `KryptorService`. The `_to_vec` / `_from_slice` methods skip it entirely.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum TextEncoding {
+    /// Standard base64 with padding; the format of every earlier release
+    #[default]
+    Base64,
+    /// URL- and filename-safe base64 without padding
+    Base64UrlNoPad,
+    /// Lowercase hex; decoding also accepts uppercase
+    Hex,
+}
+
+impl TextEncoding {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TextEncoding::Base64 => "base64",
+            TextEncoding::Base64UrlNoPad => "base64url",
+            TextEncoding::Hex => "hex",
+        }
+    }
+
+    pub fn encode(&self, data: &[u8]) -> String {
+        match self {
+            TextEncoding::Base64 => general_purpose::STANDARD.encode(data),
+            TextEncoding::Base64UrlNoPad => general_purpose::URL_SAFE_NO_PAD.encode(data),
+            TextEncoding::Hex => data.iter().map(|byte| format!("{:02x}", byte)).collect(),
+        }
+    }
+
+    pub fn decode(&self, text: &str) -> Result<Vec<u8>> {
+        match self {
+            TextEncoding::Base64 => Ok(general_purpose::STANDARD.decode(text)?),
+            TextEncoding::Base64UrlNoPad => Ok(general_purpose::URL_SAFE_NO_PAD.decode(text)?),
+            TextEncoding::Hex => decode_hex(text),
+        }
+    }
+}
+
+fn decode_hex(text: &str) -> Result<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return Err(EncryptionError::InvalidEncoding("hex input has an odd length".to_string()));
+    }
+    text.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            // from_str_radix alone would accept a sign, e.g. "+1"
+            std::str::from_utf8(pair)
+                .ok()
+                .filter(|digits| digits.bytes().all(|byte| byte.is_ascii_hexdigit()))
+                .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                .ok_or_else(|| {
+                    EncryptionError::InvalidEncoding(format!("invalid hex digits {:?}", String::from_utf8_lossy(pair)))
+                })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encodings_round_trip() -> Result<()> {
+        let data = [0x00, 0xfb, 0xff, 0x10, 0x7e];
+        for encoding in [TextEncoding::Base64, TextEncoding::Base64UrlNoPad, TextEncoding::Hex] {
+            assert_eq!(encoding.decode(&encoding.encode(&data))?, data);
+        }
+
+        assert_eq!(TextEncoding::Base64.encode(&data), "APv/EH4=");
+        assert_eq!(TextEncoding::Base64UrlNoPad.encode(&data), "APv_EH4");
+        assert_eq!(TextEncoding::Hex.encode(&data), "00fbff107e");
+        assert_eq!(TextEncoding::Hex.decode("00FBFF107E")?, data);
+
+        assert!(TextEncoding::Hex.decode("abc").is_err());
+        assert!(TextEncoding::Hex.decode("+1").is_err());
+        assert!(TextEncoding::Base64UrlNoPad.decode("APv/EH4=").is_err());
+        Ok(())
+    }
+}
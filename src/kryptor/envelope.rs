@@ -0,0 +1,334 @@
+use crate::kryptor::algorithm::Algorithm;
+use crate::kryptor::compression::Compression;
+use crate::kryptor::errors::EncryptionError;
+
+pub type Result<T> = std::result::Result<T, EncryptionError>;
+
+/// Magic prefix identifying an encry ciphertext envelope
+pub const MAGIC: [u8; 4] = *b"ENCY";
+
+/// Original envelope format, without a content-type byte. `KryptorService`
+/// wrote JSON into it as base64 text; other kryptors still write it.
+pub const FORMAT_V1: u8 = 1;
+
+/// Adds a content-type byte describing the plaintext encoding
+pub const FORMAT_V2: u8 = 2;
+
+/// Newest envelope format version this build reads and writes
+pub const FORMAT_VERSION: u8 = FORMAT_V2;
+
+/// Header flag bits recording the `Compression` codec; v2 only
+pub const FLAG_COMPRESSION_MASK: u8 = 0b0000_0011;
+
+/// Length of the legacy AES-GCM IV used by bare `[IV | Ciphertext | Tag]` blobs
+pub const LEGACY_IV_LEN: usize = 12;
+
+/// How the plaintext inside a v2 envelope is encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentType {
+    /// Opaque bytes from `encrypt_bytes`
+    Bytes,
+    /// UTF-8 JSON from `encrypt_json`, stored as is
+    Json,
+    /// CBOR (RFC 8949) from `encrypt_value`
+    Cbor,
+    /// MessagePack with named struct fields from `encrypt_value`
+    MessagePack,
+    /// bincode 2 standard configuration from `encrypt_value`
+    Bincode,
+}
+
+/// Self-describing header that precedes every ciphertext.
+///
+/// Wire layout:
+/// `MAGIC(4) | version(1) | algorithm(1) | flags(1) | [content_type(1)] | key_id_len(1) | key_id | nonce_len(1) | nonce`
+/// followed by the AEAD output `[Ciphertext | Tag]`. The content-type byte is
+/// only present from v2 on, where the low flag bits name the compression
+/// codec. The encoded header is authenticated as associated data, so none of
+/// its fields can be altered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvelopeHeader {
+    pub version: u8,
+    pub algorithm: Algorithm,
+    pub flags: u8,
+    /// `None` for v1 envelopes
+    pub content_type: Option<ContentType>,
+    pub key_id: String,
+    pub nonce: Vec<u8>,
+}
+
+impl ContentType {
+    pub fn id(self) -> u8 {
+        match self {
+            ContentType::Bytes => 0,
+            ContentType::Json => 1,
+            ContentType::Cbor => 2,
+            ContentType::MessagePack => 3,
+            ContentType::Bincode => 4,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Result<Self> {
+        match id {
+            0 => Ok(ContentType::Bytes),
+            1 => Ok(ContentType::Json),
+            2 => Ok(ContentType::Cbor),
+            3 => Ok(ContentType::MessagePack),
+            4 => Ok(ContentType::Bincode),
+            other => Err(EncryptionError::InvalidEnvelope(format!("unknown content type {}", other))),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ContentType::Bytes => "bytes",
+            ContentType::Json => "json",
+            ContentType::Cbor => "cbor",
+            ContentType::MessagePack => "msgpack",
+            ContentType::Bincode => "bincode",
+        }
+    }
+}
+
+/// A parsed envelope: header plus the AEAD output it protects
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Envelope {
+    pub header: EnvelopeHeader,
+    pub ciphertext: Vec<u8>,
+}
+
+impl EnvelopeHeader {
+    /// v1 header. Deterministic ciphertexts stay on v1 so equal plaintexts
+    /// keep producing the ciphertexts already stored for lookups.
+    pub fn new(algorithm: Algorithm, key_id: String, nonce: Vec<u8>) -> Result<Self> {
+        if key_id.is_empty() || key_id.len() > u8::MAX as usize {
+            return Err(EncryptionError::InvalidEnvelope(format!(
+                "key id must be 1-255 bytes, got {}",
+                key_id.len()
+            )));
+        }
+        if nonce.len() != algorithm.nonce_len() {
+            return Err(EncryptionError::InvalidEnvelope(format!(
+                "{} expects a {}-byte nonce, got {}",
+                algorithm.name(),
+                algorithm.nonce_len(),
+                nonce.len()
+            )));
+        }
+
+        Ok(Self {
+            version: FORMAT_V1,
+            algorithm,
+            flags: 0,
+            content_type: None,
+            key_id,
+            nonce,
+        })
+    }
+
+    /// Upgrades the header to v2, recording how the plaintext is encoded
+    pub fn with_content_type(mut self, content_type: ContentType) -> Self {
+        self.version = FORMAT_V2;
+        self.content_type = Some(content_type);
+        self
+    }
+
+    /// Records that the plaintext was compressed with `compression` before
+    /// encryption. Only valid on v2 headers.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.flags = (self.flags & !FLAG_COMPRESSION_MASK) | compression.id();
+        self
+    }
+
+    pub fn compression(&self) -> Result<Compression> {
+        Compression::from_id(self.flags & FLAG_COMPRESSION_MASK)
+    }
+
+    /// Serializes the header; the result doubles as the AEAD associated data
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(10 + self.key_id.len() + self.nonce.len());
+        out.extend_from_slice(&MAGIC);
+        out.push(self.version);
+        out.push(self.algorithm.id());
+        out.push(self.flags);
+        if let Some(content_type) = self.content_type {
+            out.push(content_type.id());
+        }
+        out.push(self.key_id.len() as u8);
+        out.extend_from_slice(self.key_id.as_bytes());
+        out.push(self.nonce.len() as u8);
+        out.extend_from_slice(&self.nonce);
+        out
+    }
+
+    /// Parses a header from the front of `input`, returning it together with
+    /// the number of bytes consumed
+    pub fn parse(input: &[u8]) -> Result<(Self, usize)> {
+        let mut reader = ByteReader::new(input);
+
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(EncryptionError::InvalidEnvelope("missing magic prefix".to_string()));
+        }
+
+        let version = reader.byte()?;
+        if version != FORMAT_V1 && version != FORMAT_V2 {
+            return Err(EncryptionError::UnsupportedVersion(version));
+        }
+
+        let algorithm = Algorithm::from_id(reader.byte()?)?;
+
+        let flags = reader.byte()?;
+        let known_flags = if version == FORMAT_V1 { 0 } else { FLAG_COMPRESSION_MASK };
+        if flags & !known_flags != 0 {
+            return Err(EncryptionError::InvalidEnvelope(format!("unknown flags {:#04x}", flags)));
+        }
+        Compression::from_id(flags & FLAG_COMPRESSION_MASK)?;
+
+        let content_type = match version {
+            FORMAT_V1 => None,
+            _ => Some(ContentType::from_id(reader.byte()?)?),
+        };
+
+        let key_id_len = reader.byte()? as usize;
+        let key_id = String::from_utf8(reader.take(key_id_len)?.to_vec())?;
+        if key_id.is_empty() {
+            return Err(EncryptionError::InvalidEnvelope("empty key id".to_string()));
+        }
+
+        let nonce_len = reader.byte()? as usize;
+        if nonce_len != algorithm.nonce_len() {
+            return Err(EncryptionError::InvalidEnvelope(format!(
+                "{} expects a {}-byte nonce, got {}",
+                algorithm.name(),
+                algorithm.nonce_len(),
+                nonce_len
+            )));
+        }
+        let nonce = reader.take(nonce_len)?.to_vec();
+
+        let header = Self {
+            version,
+            algorithm,
+            flags,
+            content_type,
+            key_id,
+            nonce,
+        };
+        Ok((header, reader.position()))
+    }
+}
+
+impl Envelope {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = self.header.to_bytes();
+        out.extend_from_slice(&self.ciphertext);
+        out
+    }
+
+    pub fn parse(input: &[u8]) -> Result<Self> {
+        let (header, consumed) = EnvelopeHeader::parse(input)?;
+        Ok(Self {
+            header,
+            ciphertext: input[consumed..].to_vec(),
+        })
+    }
+
+    /// Returns true when `input` starts with the envelope magic prefix.
+    /// Bare legacy blobs start with a random IV and must be read through the
+    /// explicit legacy decrypt path instead.
+    pub fn is_envelope(input: &[u8]) -> bool {
+        input.starts_with(&MAGIC)
+    }
+}
+
+struct ByteReader<'a> {
+    input: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn new(input: &'a [u8]) -> Self {
+        Self { input, position: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.position + len;
+        if end > self.input.len() {
+            return Err(EncryptionError::InvalidEnvelope("truncated header".to_string()));
+        }
+        let slice = &self.input[self.position..end];
+        self.position = end;
+        Ok(slice)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn position(&self) -> usize {
+        self.position
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_envelope_roundtrip() -> Result<()> {
+        let header = EnvelopeHeader::new(Algorithm::Aes256Gcm, "default".to_string(), vec![7u8; 12])?;
+        let envelope = Envelope {
+            header: header.clone(),
+            ciphertext: vec![1, 2, 3, 4],
+        };
+
+        let bytes = envelope.to_bytes();
+        assert!(Envelope::is_envelope(&bytes));
+
+        let parsed = Envelope::parse(&bytes)?;
+        assert_eq!(parsed.header, header);
+        assert_eq!(parsed.ciphertext, vec![1, 2, 3, 4]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_unknown_version_and_truncation() -> Result<()> {
+        let header = EnvelopeHeader::new(Algorithm::Aes256Gcm, "k1".to_string(), vec![0u8; 12])?;
+        let mut bytes = header.to_bytes();
+
+        assert!(EnvelopeHeader::parse(&bytes[..bytes.len() - 1]).is_err());
+
+        bytes[4] = 99;
+        assert!(matches!(
+            EnvelopeHeader::parse(&bytes),
+            Err(EncryptionError::UnsupportedVersion(99))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_v2_records_content_type() -> Result<()> {
+        let v1 = EnvelopeHeader::new(Algorithm::Aes256Gcm, "k1".to_string(), vec![0u8; 12])?;
+        let v2 = v1.clone().with_content_type(ContentType::Json);
+        assert_eq!(v2.version, FORMAT_VERSION);
+        assert_eq!(v2.to_bytes().len(), v1.to_bytes().len() + 1);
+
+        let (parsed, consumed) = EnvelopeHeader::parse(&v2.to_bytes())?;
+        assert_eq!(parsed, v2);
+        assert_eq!(consumed, v2.to_bytes().len());
+        assert_eq!(EnvelopeHeader::parse(&v1.to_bytes())?.0.content_type, None);
+
+        let mut unknown = v2.to_bytes();
+        unknown[7] = 200;
+        assert!(EnvelopeHeader::parse(&unknown).is_err());
+
+        let compressed = v2.with_compression(Compression::Deflate);
+        assert_eq!(EnvelopeHeader::parse(&compressed.to_bytes())?.0.compression()?, Compression::Deflate);
+        let mut flagged_v1 = v1.to_bytes();
+        flagged_v1[6] = Compression::Zstd.id();
+        assert!(EnvelopeHeader::parse(&flagged_v1).is_err());
+        Ok(())
+    }
+}
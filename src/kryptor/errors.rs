@@ -1,6 +1,7 @@
 use std::fmt;
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum EncryptionError {
     SerializationError(serde_json::Error),
     Base64DecodeError(base64::DecodeError),
@@ -9,6 +10,19 @@ pub enum EncryptionError {
     DecryptionError(aes_gcm::Error),
     AesGcmError(aes_gcm::Error),
     Utf8Error(std::string::FromUtf8Error),
+    InvalidEnvelope(String),
+    UnsupportedVersion(u8),
+    UnsupportedAlgorithm(u8),
+    UnknownKey(String),
+    KeyRetired(String),
+    KeyShredded(String),
+    InvalidKeyring(String),
+    InvalidConfig(String),
+    NormalizationError(String),
+    InvalidEncoding(String),
+    /// A serialization format or compression codec failed on a payload
+    CodecError(String),
+    IoError(std::io::Error),
     Other(String),
 }
 
@@ -22,6 +36,20 @@ impl fmt::Display for EncryptionError {
             EncryptionError::DecryptionError(e) => write!(f, "Decryption error: {}", e),
             EncryptionError::AesGcmError(e) => write!(f, "AES-GCM error: {:?}", e),
             EncryptionError::Utf8Error(e) => write!(f, "UTF-8 error: {}", e),
+            EncryptionError::InvalidEnvelope(s) => write!(f, "Invalid envelope: {}", s),
+            EncryptionError::UnsupportedVersion(v) => write!(f, "Unsupported envelope version: {}", v),
+            EncryptionError::UnsupportedAlgorithm(id) => write!(f, "Unsupported algorithm id: {}", id),
+            EncryptionError::UnknownKey(id) => write!(f, "Unknown key id: {}", id),
+            EncryptionError::KeyRetired(id) => write!(f, "Key {} is retired", id),
+            EncryptionError::KeyShredded(aggregate) => {
+                write!(f, "Key for aggregate {} was shredded; its data is permanently unreadable", aggregate)
+            }
+            EncryptionError::InvalidKeyring(s) => write!(f, "Invalid keyring: {}", s),
+            EncryptionError::InvalidConfig(s) => write!(f, "Invalid configuration: {}", s),
+            EncryptionError::NormalizationError(s) => write!(f, "Normalization error: {}", s),
+            EncryptionError::InvalidEncoding(s) => write!(f, "Invalid encoding: {}", s),
+            EncryptionError::CodecError(s) => write!(f, "Codec error: {}", s),
+            EncryptionError::IoError(e) => write!(f, "I/O error: {}", e),
             EncryptionError::Other(s) => write!(f, "Other error: {}", s),
         }
     }
@@ -58,6 +86,13 @@ impl From<std::string::FromUtf8Error> for EncryptionError {
         EncryptionError::Utf8Error(err)
     }
 }
+
+impl From<std::io::Error> for EncryptionError {
+    fn from(err: std::io::Error) -> Self {
+        EncryptionError::IoError(err)
+    }
+}
+
 impl From<String> for EncryptionError {
     fn from(err: String) -> Self {
         EncryptionError::Other(err)
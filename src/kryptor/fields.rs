@@ -0,0 +1,182 @@
+use std::sync::Arc;
+
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::kryptor::blind_index::{BlindIndex, BlindIndexer, Normalizer};
+use crate::kryptor::deterministic::DeterministicKryptor;
+use crate::kryptor::errors::EncryptionError;
+use crate::kryptor::keyring::Keyring;
+use crate::kryptor::utilities::KryptorService;
+
+pub use encry_derive::EncryptFields;
+
+pub type Result<T> = std::result::Result<T, EncryptionError>;
+
+/// Context for deterministic fields. It is shared by every record so that
+/// equal values stay comparable across aggregates; the field label passed as
+/// associated data keeps different fields apart.
+const DETERMINISTIC_FIELD_CONTEXT: &str = "encry:fields";
+
+/// Field-level encryption generated by `#[derive(EncryptFields)]`.
+///
+/// `#[encrypt]` fields become randomized ciphertexts, `#[encrypt(deterministic)]`
+/// fields become AES-SIV ciphertexts usable for equality lookups, and
+/// `#[blind_index]` adds a `<field>_index` column. Other fields are copied as-is.
+pub trait EncryptFields: Sized {
+    type Encrypted;
+
+    fn encrypt_fields(&self, encryptor: &mut FieldEncryptor) -> Result<Self::Encrypted>;
+
+    fn decrypt_fields(encrypted: &Self::Encrypted, encryptor: &mut FieldEncryptor) -> Result<Self>;
+}
+
+/// Field types that a blind index can be computed for
+pub trait BlindIndexValue {
+    type Index;
+
+    fn blind_index(&self, indexer: &mut BlindIndexer, index: &BlindIndex) -> Result<Self::Index>;
+}
+
+/// Bundles the services a derived `EncryptFields` impl needs
+pub struct FieldEncryptor {
+    service: KryptorService,
+    deterministic: DeterministicKryptor,
+    indexer: BlindIndexer,
+    default_country_code: Option<String>,
+}
+
+impl FieldEncryptor {
+    /// Randomized fields are encrypted under `context`, e.g. the aggregate key
+    pub fn with_keyring<C: Serialize>(keyring: impl Into<Arc<Keyring>>, context: &C) -> Result<Self> {
+        let keyring = keyring.into();
+        Ok(Self {
+            service: KryptorService::with_keyring(Arc::clone(&keyring), context)?,
+            deterministic: DeterministicKryptor::with_keyring(Arc::clone(&keyring), &DETERMINISTIC_FIELD_CONTEXT)?,
+            indexer: BlindIndexer::from_keyring(keyring),
+            default_country_code: None,
+        })
+    }
+
+    /// Country code used by `normalize = "e164"` for numbers without one
+    pub fn with_default_country_code(mut self, country_code: impl Into<String>) -> Self {
+        self.default_country_code = Some(country_code.into());
+        self
+    }
+
+    pub fn encrypt_field<T: Serialize>(&mut self, label: &str, value: &T) -> Result<String> {
+        self.service.encrypt_json_with_aad(value, label.as_bytes())
+    }
+
+    pub fn decrypt_field<T: DeserializeOwned>(&mut self, label: &str, encrypted: &str) -> Result<T> {
+        self.service.decrypt_json_with_aad(encrypted, label.as_bytes())
+    }
+
+    pub fn encrypt_field_deterministic<T: Serialize>(&mut self, label: &str, value: &T) -> Result<String> {
+        self.deterministic.encrypt_json_with_aad(value, label.as_bytes())
+    }
+
+    pub fn decrypt_field_deterministic<T: DeserializeOwned>(&mut self, label: &str, encrypted: &str) -> Result<T> {
+        self.deterministic.decrypt_json_with_aad(encrypted, label.as_bytes())
+    }
+
+    pub fn blind_index<V: BlindIndexValue + ?Sized>(
+        &mut self,
+        name: &str,
+        normalize: Option<&str>,
+        value: &V,
+    ) -> Result<V::Index> {
+        let mut index = BlindIndex::new(name);
+        if let Some(normalize) = normalize {
+            index = index.normalize(self.normalizer(normalize)?);
+        }
+        value.blind_index(&mut self.indexer, &index)
+    }
+
+    fn normalizer(&self, name: &str) -> Result<Normalizer> {
+        match name {
+            "trim" => Ok(Normalizer::Trim),
+            "lowercase" => Ok(Normalizer::Lowercase),
+            "email" => Ok(Normalizer::Email),
+            "e164" => Ok(Normalizer::E164 {
+                default_country_code: self.default_country_code.clone(),
+            }),
+            other => Err(EncryptionError::NormalizationError(format!("unknown normalizer '{}'", other))),
+        }
+    }
+}
+
+impl BlindIndexValue for String {
+    type Index = String;
+
+    fn blind_index(&self, indexer: &mut BlindIndexer, index: &BlindIndex) -> Result<String> {
+        indexer.compute(index, self)
+    }
+}
+
+impl BlindIndexValue for Option<String> {
+    type Index = Option<String>;
+
+    fn blind_index(&self, indexer: &mut BlindIndexer, index: &BlindIndex) -> Result<Option<String>> {
+        self.as_deref().map(|value| indexer.compute(index, value)).transpose()
+    }
+}
+
+impl BlindIndexValue for Vec<String> {
+    type Index = Vec<String>;
+
+    fn blind_index(&self, indexer: &mut BlindIndexer, index: &BlindIndex) -> Result<Vec<String>> {
+        indexer.compute_all(index, self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Profile, ProfileEncrypted};
+
+    const TEST_IKM: &str = "rph2pwTQCx+TD/lk+7o9igzQw5A7FU3+S+Z24Cf9Duk=";
+
+    fn sample_profile() -> Profile {
+        Profile::new(
+            "Mwaura S W".to_string(),
+            "1990-01-01".to_string(),
+            "smwaura@outlook.com".to_string(),
+            vec!["+254712345678".to_string()],
+        )
+    }
+
+    #[test]
+    fn test_derived_profile_roundtrip() -> Result<()> {
+        let keyring = Keyring::single("k1", TEST_IKM)?;
+        let mut encryptor = FieldEncryptor::with_keyring(keyring, &"aggregate-1")?;
+        let profile = sample_profile();
+
+        let encrypted: ProfileEncrypted = profile.encrypt_fields(&mut encryptor)?;
+        assert_ne!(encrypted.dob, profile.dob);
+        assert_eq!(encrypted.phones_index.len(), 1);
+
+        let decrypted = Profile::decrypt_fields(&encrypted, &mut encryptor)?;
+        assert_eq!(decrypted.email, profile.email);
+        assert_eq!(decrypted.phones, profile.phones);
+        Ok(())
+    }
+
+    #[test]
+    fn test_deterministic_fields_match_across_aggregates() -> Result<()> {
+        let keyring = Keyring::single("k1", TEST_IKM)?;
+        let mut first = FieldEncryptor::with_keyring(keyring.duplicate(), &"aggregate-1")?;
+        let mut second = FieldEncryptor::with_keyring(keyring, &"aggregate-2")?;
+
+        let a = sample_profile().encrypt_fields(&mut first)?;
+        let b = sample_profile().encrypt_fields(&mut second)?;
+        assert_eq!(a.email, b.email);
+        assert_eq!(a.email_index, b.email_index);
+        assert_ne!(a.dob, b.dob);
+
+        // Randomized fields are bound to their field label
+        let mut swapped = a.clone();
+        swapped.dob = a.name.clone();
+        assert!(Profile::decrypt_fields(&swapped, &mut first).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use aes_gcm::aead::OsRng;
+use aes_gcm::aead::rand_core::RngCore;
+
+/// Writes `contents` readable only by the owner where the platform supports it.
+/// An existing file is replaced atomically: the contents go to a synced
+/// temporary file in the same directory that is then renamed over `path`, so
+/// a crash or a full disk leaves either the old or the new file, never a torn one.
+/// Every call gets its own randomly named temporary file, so concurrent writers
+/// of the same path never share one.
+pub fn write_private(path: &Path, contents: &[u8], create_new: bool) -> io::Result<()> {
+    if create_new {
+        return write_new_private(path, contents);
+    }
+
+    let (temp, mut file) = create_temp_private(path)?;
+    let written = file.write_all(contents).and_then(|()| file.sync_all());
+    drop(file);
+    if let Err(e) = written.and_then(|()| fs::rename(&temp, path)) {
+        // Only the temporary file this call created is removed
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+    sync_parent(path)
+}
+
+fn write_new_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut file = create_private(path)?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+fn create_private(path: &Path) -> io::Result<fs::File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)
+}
+
+/// Creates `.{name}.{random}.tmp` next to `path`, retrying on a name clash
+fn create_temp_private(path: &Path) -> io::Result<(PathBuf, fs::File)> {
+    const ATTEMPTS: usize = 16;
+
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    for _ in 0..ATTEMPTS {
+        let mut temp_name = OsString::from(".");
+        temp_name.push(file_name);
+        temp_name.push(format!(".{:016x}.tmp", OsRng.next_u64()));
+        let temp = path.with_file_name(temp_name);
+        match create_private(&temp) {
+            Ok(file) => return Ok((temp, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::AlreadyExists, "could not create a unique temporary file"))
+}
+
+/// Persists the rename itself; only Unix can open a directory to sync it
+fn sync_parent(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty());
+        fs::File::open(parent.unwrap_or(Path::new(".")))?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_concurrent_writers_never_tear_the_file() -> io::Result<()> {
+        let path = std::env::temp_dir().join(format!("encry-files-{}-concurrent.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let writers: Vec<_> = (0..8u8)
+            .map(|writer| {
+                let path = path.clone();
+                std::thread::spawn(move || -> io::Result<()> {
+                    let contents = vec![b'a' + writer; 64 * 1024];
+                    for _ in 0..20 {
+                        write_private(&path, &contents, false)?;
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().expect("writer thread panicked")?;
+        }
+
+        let contents = fs::read(&path)?;
+        assert_eq!(contents.len(), 64 * 1024);
+        assert!(contents.iter().all(|&byte| byte == contents[0]));
+
+        let prefix = format!(".{}.", path.file_name().unwrap().to_string_lossy());
+        let leftovers = fs::read_dir(path.parent().unwrap())?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+            .count();
+        assert_eq!(leftovers, 0);
+
+        fs::remove_file(path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,271 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::{Engine as _, engine::general_purpose};
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::kryptor::errors::EncryptionError;
+use crate::kryptor::secret::{SecretBytes, SecretString};
+use crate::kryptor::utilities::DEFAULT_KEY_ID;
+
+pub type Result<T> = std::result::Result<T, EncryptionError>;
+
+/// Lifecycle of an IKM inside a keyring
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyStatus {
+    /// Used for new encryptions and for decryption
+    Active,
+    /// Only used to decrypt data written before a rotation
+    DecryptOnly,
+    /// Kept for rewrap jobs; regular decryption refuses it
+    Retired,
+}
+
+/// A keyring entry. It is not `Clone`: copying the IKM takes an explicit
+/// `duplicate`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyEntry {
+    pub key_id: String,
+    pub ikm_base64: SecretString,
+    pub status: KeyStatus,
+    /// Creation time in seconds since the Unix epoch
+    pub created_at: u64,
+}
+
+/// A set of IKMs with exactly one active key. Services share it through an
+/// `Arc`; copying the IKMs takes an explicit `duplicate`.
+#[derive(Debug, Deserialize)]
+#[serde(try_from = "Vec<KeyEntry>")]
+pub struct Keyring {
+    entries: Vec<KeyEntry>,
+}
+
+impl KeyEntry {
+    pub fn new(key_id: impl Into<String>, ikm_base64: impl Into<SecretString>, status: KeyStatus) -> Self {
+        Self {
+            key_id: key_id.into(),
+            ikm_base64: ikm_base64.into(),
+            status,
+            created_at: unix_now(),
+        }
+    }
+
+    pub fn with_created_at(mut self, created_at: u64) -> Self {
+        self.created_at = created_at;
+        self
+    }
+
+    /// Decodes the IKM into a buffer that is zeroed on drop
+    pub fn decode_ikm(&self) -> Result<SecretBytes> {
+        Ok(SecretBytes::from(general_purpose::STANDARD.decode(self.ikm_base64.expose_secret())?))
+    }
+
+    /// Explicit copy of the entry; the IKM goes into another zeroizing buffer
+    pub fn duplicate(&self) -> Self {
+        Self {
+            key_id: self.key_id.clone(),
+            ikm_base64: self.ikm_base64.duplicate(),
+            status: self.status,
+            created_at: self.created_at,
+        }
+    }
+}
+
+impl Keyring {
+    /// Builds a keyring, validating that key ids are unique and exactly one key is active
+    pub fn new(entries: Vec<KeyEntry>) -> Result<Self> {
+        let keyring = Self { entries };
+        keyring.validate()?;
+        Ok(keyring)
+    }
+
+    /// Keyring holding a single active key, validating its key id like `new`
+    pub fn single(key_id: impl Into<String>, ikm_base64: impl Into<SecretString>) -> Result<Self> {
+        Self::new(vec![KeyEntry::new(key_id, ikm_base64, KeyStatus::Active)])
+    }
+
+    /// Keyring holding a single active key under `DEFAULT_KEY_ID`, which
+    /// always passes validation
+    pub(crate) fn default_key(ikm_base64: impl Into<SecretString>) -> Self {
+        Self {
+            entries: vec![KeyEntry::new(DEFAULT_KEY_ID, ikm_base64, KeyStatus::Active)],
+        }
+    }
+
+    /// The key used for all new encryptions
+    pub fn active(&self) -> &KeyEntry {
+        self.entries
+            .iter()
+            .find(|entry| entry.status == KeyStatus::Active)
+            .expect("keyring invariant: exactly one active key")
+    }
+
+    pub fn get(&self, key_id: &str) -> Option<&KeyEntry> {
+        self.entries.iter().find(|entry| entry.key_id == key_id)
+    }
+
+    pub fn entries(&self) -> &[KeyEntry] {
+        &self.entries
+    }
+
+    /// Explicit copy of every entry, e.g. to rotate a shared keyring
+    pub fn duplicate(&self) -> Self {
+        Self {
+            entries: self.entries.iter().map(KeyEntry::duplicate).collect(),
+        }
+    }
+
+    /// Adds a new active key and demotes the previous active key to decrypt-only.
+    /// Existing ciphertexts keep working because they record their key id.
+    pub fn rotate(&mut self, entry: KeyEntry) -> Result<()> {
+        validate_key_id(&entry.key_id)?;
+        if self.get(&entry.key_id).is_some() {
+            return Err(EncryptionError::InvalidKeyring(format!("duplicate key id '{}'", entry.key_id)));
+        }
+
+        for existing in self.entries.iter_mut() {
+            if existing.status == KeyStatus::Active {
+                existing.status = KeyStatus::DecryptOnly;
+            }
+        }
+
+        self.entries.push(KeyEntry {
+            status: KeyStatus::Active,
+            ..entry
+        });
+        Ok(())
+    }
+
+    /// Changes the status of a non-active key, e.g. to retire it once rewrapped
+    pub fn set_status(&mut self, key_id: &str, status: KeyStatus) -> Result<()> {
+        if status == KeyStatus::Active {
+            return Err(EncryptionError::InvalidKeyring(
+                "use rotate to promote a key to active".to_string(),
+            ));
+        }
+
+        let entry = self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.key_id == key_id)
+            .ok_or_else(|| EncryptionError::UnknownKey(key_id.to_string()))?;
+
+        if entry.status == KeyStatus::Active {
+            return Err(EncryptionError::InvalidKeyring(format!(
+                "key '{}' is active; rotate to a new key first",
+                key_id
+            )));
+        }
+
+        entry.status = status;
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<()> {
+        for (index, entry) in self.entries.iter().enumerate() {
+            validate_key_id(&entry.key_id)?;
+            if self.entries[..index].iter().any(|other| other.key_id == entry.key_id) {
+                return Err(EncryptionError::InvalidKeyring(format!("duplicate key id '{}'", entry.key_id)));
+            }
+        }
+
+        let active = self
+            .entries
+            .iter()
+            .filter(|entry| entry.status == KeyStatus::Active)
+            .count();
+        if active != 1 {
+            return Err(EncryptionError::InvalidKeyring(format!(
+                "expected exactly one active key, found {}",
+                active
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+impl TryFrom<Vec<KeyEntry>> for Keyring {
+    type Error = EncryptionError;
+
+    fn try_from(entries: Vec<KeyEntry>) -> Result<Self> {
+        Self::new(entries)
+    }
+}
+
+impl Serialize for Keyring {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        self.entries.serialize(serializer)
+    }
+}
+
+impl From<Keyring> for Vec<KeyEntry> {
+    fn from(keyring: Keyring) -> Self {
+        keyring.entries
+    }
+}
+
+/// Key ids are written into envelopes behind a one-byte length
+fn validate_key_id(key_id: &str) -> Result<()> {
+    if key_id.is_empty() || key_id.len() > u8::MAX as usize {
+        return Err(EncryptionError::InvalidKeyring(format!(
+            "key id '{}' must be 1-255 bytes",
+            key_id
+        )));
+    }
+    Ok(())
+}
+
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotate_demotes_previous_active_key() -> Result<()> {
+        let mut keyring = Keyring::single("k1", "AAAA")?;
+        keyring.rotate(KeyEntry::new("k2", "BBBB", KeyStatus::DecryptOnly))?;
+
+        assert_eq!(keyring.active().key_id, "k2");
+        assert_eq!(keyring.get("k1").map(|entry| entry.status), Some(KeyStatus::DecryptOnly));
+
+        keyring.set_status("k1", KeyStatus::Retired)?;
+        assert!(keyring.set_status("k2", KeyStatus::Retired).is_err());
+
+        let copy = keyring.duplicate();
+        assert_eq!(copy.active().key_id, "k2");
+        assert_eq!(copy.get("k1").map(|entry| entry.ikm_base64.expose_secret()), Some("AAAA"));
+        assert_eq!(serde_json::to_string(&copy)?, serde_json::to_string(&keyring)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_invalid_keyrings() {
+        let two_active = vec![
+            KeyEntry::new("k1", "AAAA", KeyStatus::Active),
+            KeyEntry::new("k2", "BBBB", KeyStatus::Active),
+        ];
+        assert!(Keyring::new(two_active).is_err());
+
+        let duplicate = vec![
+            KeyEntry::new("k1", "AAAA", KeyStatus::Active),
+            KeyEntry::new("k1", "BBBB", KeyStatus::DecryptOnly),
+        ];
+        assert!(Keyring::new(duplicate).is_err());
+
+        let long_id = "k".repeat(256);
+        assert!(matches!(Keyring::single("", "AAAA"), Err(EncryptionError::InvalidKeyring(_))));
+        assert!(matches!(Keyring::single(long_id.as_str(), "AAAA"), Err(EncryptionError::InvalidKeyring(_))));
+        assert!(Keyring::single(&long_id[..255], "AAAA").is_ok());
+
+        let mut keyring = Keyring::default_key("AAAA");
+        assert!(keyring.rotate(KeyEntry::new(long_id, "BBBB", KeyStatus::Active)).is_err());
+        assert_eq!(keyring.active().key_id, DEFAULT_KEY_ID);
+    }
+}
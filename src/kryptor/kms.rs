@@ -0,0 +1,326 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use aes_gcm::aead::OsRng;
+use aes_gcm::aead::rand_core::RngCore;
+use base64::{Engine as _, engine::general_purpose};
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::kryptor::compression::DEFAULT_MAX_DECOMPRESSED_LEN;
+use crate::kryptor::errors::EncryptionError;
+use crate::kryptor::files::write_private;
+use crate::kryptor::keyring::{KeyEntry, KeyStatus, Keyring};
+use crate::kryptor::recipients::{self, DEK_WRAP_AAD, PackageBody, Recipient, WrappedKey};
+use crate::kryptor::secret::{SecretBytes, SecretKey, SecretString};
+use crate::kryptor::utilities::{EncryptedData, KryptorService};
+
+pub type Result<T> = std::result::Result<T, EncryptionError>;
+
+/// A DEK as returned by `generate_data_key`: the plaintext for immediate use
+/// and the wrapped copy to store next to the ciphertext
+#[derive(Debug)]
+pub struct DataKey {
+    pub plaintext: SecretKey<32>,
+    pub wrapped: WrappedKey,
+}
+
+/// Wraps data-encryption keys under key-encryption keys (KEKs) it never
+/// hands out. Implement it over a cloud KMS to keep the KEK outside the process.
+///
+/// `context_base64` is the package context. Implementations must bind it to
+/// the wrapped key (e.g. as the KMS encryption context), so a wrapped DEK
+/// cannot be moved to a package with another context.
+pub trait KeyManagementService: std::fmt::Debug + Send + Sync {
+    /// Id of the KEK new DEKs are wrapped under
+    fn key_id(&self) -> String;
+
+    fn wrap(&self, plaintext: &[u8], context_base64: &str) -> Result<WrappedKey>;
+
+    /// Unwraps a DEK. Must fail with `UnknownKey` when `wrapped.key_id` is not
+    /// a KEK of this service, so other recipients of a package can be tried.
+    fn unwrap(&self, wrapped: &WrappedKey, context_base64: &str) -> Result<SecretBytes>;
+
+    /// Generates and wraps a fresh 256-bit DEK. Override it with the KMS's
+    /// own data key call where one exists.
+    fn generate_data_key(&self, context_base64: &str) -> Result<DataKey> {
+        let mut plaintext = SecretKey::<32>::zeroed();
+        OsRng.fill_bytes(plaintext.expose_secret_mut());
+        let wrapped = self.wrap(plaintext.expose_secret(), context_base64)?;
+        Ok(DataKey { plaintext, wrapped })
+    }
+}
+
+/// File-backed stand-in for a KMS, for tests and local development.
+///
+/// KEKs are kept in a JSON keyring file and DEKs are wrapped exactly like a
+/// symmetric multi-recipient package, so a `KryptorService` holding the same
+/// keyring can read packages wrapped by it.
+#[derive(Debug)]
+pub struct LocalKms {
+    keyring: Arc<Keyring>,
+    path: Option<PathBuf>,
+}
+
+impl LocalKms {
+    /// In-memory KMS over `keyring`
+    pub fn new(keyring: impl Into<Arc<Keyring>>) -> Self {
+        Self {
+            keyring: keyring.into(),
+            path: None,
+        }
+    }
+
+    /// Loads the KEK keyring from a JSON keyring file
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let source = fs::read_to_string(&path)
+            .map(SecretString::from)
+            .map_err(|e| EncryptionError::InvalidConfig(format!("cannot read KMS file {}: {}", path.display(), e)))?;
+        let keyring: Keyring = serde_json::from_str(source.expose_secret())?;
+        Ok(Self {
+            keyring: Arc::new(keyring),
+            path: Some(path),
+        })
+    }
+
+    /// Creates a KMS file holding a single random KEK; refuses to overwrite an existing file
+    pub fn create(path: impl Into<PathBuf>, key_id: impl Into<String>) -> Result<Self> {
+        let kms = Self {
+            keyring: Arc::new(Keyring::single(key_id, random_kek())?),
+            path: Some(path.into()),
+        };
+        kms.save(true)?;
+        Ok(kms)
+    }
+
+    /// Adds a random KEK as the active key; older KEKs still unwrap.
+    /// Services already handed the old keyring keep it.
+    pub fn rotate(&mut self, key_id: impl Into<String>) -> Result<()> {
+        let mut keyring = self.keyring.duplicate();
+        keyring.rotate(KeyEntry::new(key_id, random_kek(), KeyStatus::Active))?;
+        self.keyring = Arc::new(keyring);
+        self.save(false)
+    }
+
+    pub fn keyring(&self) -> &Keyring {
+        &self.keyring
+    }
+
+    fn service(&self, context_base64: &str) -> KryptorService {
+        KryptorService::from_keyring(Arc::clone(&self.keyring), context_base64.to_string())
+    }
+
+    fn save(&self, create_new: bool) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let json = SecretString::from(serde_json::to_string_pretty(&*self.keyring)?);
+        write_private(path, json.expose_secret().as_bytes(), create_new)
+            .map_err(|e| EncryptionError::InvalidConfig(format!("cannot write KMS file {}: {}", path.display(), e)))
+    }
+}
+
+impl KeyManagementService for LocalKms {
+    fn key_id(&self) -> String {
+        self.keyring.active().key_id.clone()
+    }
+
+    fn wrap(&self, plaintext: &[u8], context_base64: &str) -> Result<WrappedKey> {
+        Ok(WrappedKey {
+            key_id: self.key_id(),
+            wrapped_dek: self
+                .service(context_base64)
+                .encrypt_bytes_with_aad(plaintext, DEK_WRAP_AAD)?,
+        })
+    }
+
+    fn unwrap(&self, wrapped: &WrappedKey, context_base64: &str) -> Result<SecretBytes> {
+        if self.keyring.get(&wrapped.key_id).is_none() {
+            return Err(EncryptionError::UnknownKey(wrapped.key_id.clone()));
+        }
+        recipients::unwrap_symmetric(&mut self.service(context_base64), wrapped)
+    }
+}
+
+/// Encrypts packages under per-package DEKs from a KMS. The process holds no
+/// IKM: every package carries its DEK wrapped under the KMS's KEK. A process
+/// that also holds a keyring sets the KMS on `KryptorService` instead.
+#[derive(Debug)]
+pub struct KmsKryptor {
+    kms: Arc<dyn KeyManagementService>,
+    context_base64: String,
+}
+
+impl KmsKryptor {
+    pub fn new(kms: Arc<dyn KeyManagementService>, context_base64: String) -> Self {
+        Self { kms, context_base64 }
+    }
+
+    pub fn with_context<T: Serialize>(kms: Arc<dyn KeyManagementService>, context: &T) -> Result<Self> {
+        let context_json = serde_json::to_vec(context)?;
+        Ok(Self::new(kms, general_purpose::STANDARD.encode(context_json)))
+    }
+
+    /// Id of the KEK new DEKs are wrapped under
+    pub fn key_id(&self) -> String {
+        self.kms.key_id()
+    }
+
+    pub fn context_base64(&self) -> &str {
+        &self.context_base64
+    }
+
+    /// Creates a package whose DEK comes from `generate_data_key`
+    pub fn create_encrypted_package<T: Serialize>(&self, data: &T) -> Result<EncryptedData> {
+        self.create_encrypted_package_for_with_aad(data, &[], &[])
+    }
+
+    pub fn create_encrypted_package_with_aad<T: Serialize>(&self, data: &T, aad: &[u8]) -> Result<EncryptedData> {
+        self.create_encrypted_package_for_with_aad(data, &[], aad)
+    }
+
+    /// Creates a package readable through the KMS and by every one of `recipients`
+    pub fn create_encrypted_package_for<T: Serialize>(&self, data: &T, recipients: &[Recipient]) -> Result<EncryptedData> {
+        self.create_encrypted_package_for_with_aad(data, recipients, &[])
+    }
+
+    pub fn create_encrypted_package_for_with_aad<T: Serialize>(
+        &self,
+        data: &T,
+        recipients: &[Recipient],
+        aad: &[u8],
+    ) -> Result<EncryptedData> {
+        let data_key = self.kms.generate_data_key(&self.context_base64)?;
+        recipients::create_package_with_dek(
+            data,
+            &self.context_base64,
+            &data_key.plaintext,
+            vec![data_key.wrapped],
+            recipients,
+            aad,
+            &PackageBody::default(),
+        )
+    }
+
+    pub fn decrypt_package<T: DeserializeOwned>(&self, package: &EncryptedData) -> Result<T> {
+        self.decrypt_package_with_aad(package, &[])
+    }
+
+    /// Decrypts a package by unwrapping its DEK through the KMS
+    pub fn decrypt_package_with_aad<T: DeserializeOwned>(&self, package: &EncryptedData, aad: &[u8]) -> Result<T> {
+        if !package.is_multi_recipient() {
+            return Err(EncryptionError::InvalidEnvelope(
+                "package carries no wrapped DEK; decrypt it with KryptorService".to_string(),
+            ));
+        }
+        recipients::decrypt_package(package, aad, DEFAULT_MAX_DECOMPRESSED_LEN, |wrapped| {
+            self.kms.unwrap(wrapped, &package.context)
+        })
+    }
+
+    /// Wraps the DEK of a package for one more recipient without touching the body
+    pub fn add_recipient(&self, package: &mut EncryptedData, recipient: &Recipient) -> Result<()> {
+        let context_base64 = package.context.clone();
+        recipients::add_recipient(package, recipient, |wrapped| self.kms.unwrap(wrapped, &context_base64))
+    }
+}
+
+fn random_kek() -> SecretString {
+    let mut kek = SecretKey::<32>::zeroed();
+    OsRng.fill_bytes(kek.expose_secret_mut());
+    SecretString::from(general_purpose::STANDARD.encode(kek.expose_secret()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kryptor::config::AppConfig;
+
+    const SEARCH_IKM: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("encry-kms-{}-{}", std::process::id(), name));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_local_kms_file_round_trip() -> Result<()> {
+        let path = temp_path("round-trip.json");
+        let kms = LocalKms::create(&path, "kek-1")?;
+        assert!(LocalKms::create(&path, "kek-1").is_err());
+
+        let kryptor = KmsKryptor::with_context(Arc::new(kms), &"profile")?;
+        let package = kryptor.create_encrypted_package_with_aad(&"alice", b"user-1")?;
+        assert_eq!(package.recipients.len(), 1);
+        assert_eq!(package.recipients[0].key_id, "kek-1");
+
+        let reopened = KmsKryptor::new(Arc::new(LocalKms::open(&path)?), kryptor.context_base64().to_string());
+        assert_eq!(reopened.decrypt_package_with_aad::<String>(&package, b"user-1")?, "alice");
+        assert!(reopened.decrypt_package_with_aad::<String>(&package, b"user-2").is_err());
+
+        let mut moved = package.clone();
+        moved.context = general_purpose::STANDARD.encode("\"other\"");
+        assert!(reopened.decrypt_package_with_aad::<String>(&moved, b"user-1").is_err());
+
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_rotated_kek_and_extra_recipients() -> Result<()> {
+        let path = temp_path("rotate.json");
+        let mut kms = LocalKms::create(&path, "kek-1")?;
+        let old = KmsKryptor::with_context(Arc::new(LocalKms::open(&path)?), &"profile")?
+            .create_encrypted_package(&1)?;
+
+        kms.rotate("kek-2")?;
+        let kryptor = KmsKryptor::with_context(Arc::new(LocalKms::open(&path)?), &"profile")?;
+        assert_eq!(kryptor.key_id(), "kek-2");
+        assert_eq!(kryptor.decrypt_package::<i32>(&old)?, 1);
+
+        let search_keyring = Keyring::single("search", SEARCH_IKM)?;
+        let package = kryptor.create_encrypted_package_for(&2, &[Recipient::from(search_keyring.duplicate())])?;
+        let mut search = KryptorService::with_keyring(search_keyring, &"profile")?;
+        assert_eq!(search.decrypt_package::<i32>(&package)?, 2);
+
+        // A service holding the KEK keyring reads KMS packages directly
+        let mut kek_holder = KryptorService::with_keyring(kms.keyring().duplicate(), &"profile")?;
+        assert_eq!(kek_holder.decrypt_package::<i32>(&package)?, 2);
+
+        let plain = kek_holder.create_encrypted_package(&3)?;
+        assert!(kryptor.decrypt_package::<i32>(&plain).is_err());
+
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_kryptor_service_takes_package_keys_from_kms() -> Result<()> {
+        let kms: Arc<dyn KeyManagementService> = Arc::new(LocalKms::new(Keyring::single("kek-1", SEARCH_IKM)?));
+        let config = AppConfig::development().with_kms(Arc::clone(&kms));
+        let mut service = KryptorService::builder().config(&config).context(&"profile").build()?;
+
+        let package = service.create_encrypted_package_with_aad(&"alice", b"user-1")?;
+        assert_eq!(package.recipients.len(), 1);
+        assert_eq!(package.recipients[0].key_id, "kek-1");
+        assert_eq!(service.decrypt_package_with_aad::<String>(&package, b"user-1")?, "alice");
+        assert_eq!(
+            KmsKryptor::with_context(kms, &"profile")?.decrypt_package_with_aad::<String>(&package, b"user-1")?,
+            "alice"
+        );
+
+        // The keyring alone cannot unwrap a KMS-wrapped DEK
+        let mut keyring_only = KryptorService::builder().config(&AppConfig::development()).context(&"profile").build()?;
+        assert!(matches!(
+            keyring_only.decrypt_package::<String>(&package),
+            Err(EncryptionError::UnknownKey(_))
+        ));
+
+        // Packages wrapped under the keyring still open through the fallback
+        let legacy = keyring_only.create_encrypted_package_for(&"bob", &[])?;
+        assert_eq!(service.decrypt_package::<String>(&legacy)?, "bob");
+        Ok(())
+    }
+}
@@ -1,3 +1,21 @@
 pub mod utilities;
 pub mod errors;
-pub mod config;
\ No newline at end of file
+pub mod config;
+pub mod envelope;
+pub mod encoding;
+pub mod serialization;
+pub mod compression;
+pub mod algorithm;
+pub mod deterministic;
+pub mod blind_index;
+pub mod fields;
+pub mod stream;
+pub mod keyring;
+pub mod rewrap;
+pub mod secret;
+pub mod passphrase;
+pub mod public_key;
+pub mod recipients;
+pub mod kms;
+pub mod shredding;
+pub mod files;
\ No newline at end of file
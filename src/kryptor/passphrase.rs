@@ -0,0 +1,158 @@
+use argon2::{Argon2, Params, Version};
+use base64::{Engine as _, engine::general_purpose};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::kryptor::errors::EncryptionError;
+use crate::kryptor::keyring::Keyring;
+use crate::kryptor::secret::{SecretKey, SecretString};
+
+pub type Result<T> = std::result::Result<T, EncryptionError>;
+
+/// Salt length used for new descriptors
+pub const SALT_LEN: usize = 16;
+
+/// Password hashing function named in a key descriptor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kdf {
+    Argon2id,
+}
+
+/// Argon2id cost parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Argon2Params {
+    /// Memory cost in KiB
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for Argon2Params {
+    /// OWASP's recommended Argon2id baseline: 19 MiB, 2 iterations, 1 lane
+    fn default() -> Self {
+        Self {
+            memory_kib: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+/// Everything except the passphrase needed to reproduce a passphrase-derived IKM.
+///
+/// The descriptor is not secret and is stored as a JSON key file next to the
+/// application config. The derived 256-bit IKM feeds HKDF exactly like a
+/// base64 IKM does, so per-context keys are unchanged. Changing the salt or
+/// any parameter yields a different IKM; give it a new `key_id` and rotate.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeyDescriptor {
+    pub kdf: Kdf,
+    pub key_id: String,
+    /// Base64 salt
+    pub salt: String,
+    #[serde(flatten)]
+    pub params: Argon2Params,
+}
+
+impl KeyDescriptor {
+    /// New descriptor with a random salt
+    pub fn generate(key_id: impl Into<String>, params: Argon2Params) -> Result<Self> {
+        let mut salt = [0u8; SALT_LEN];
+        rand::rng().fill_bytes(&mut salt);
+
+        let descriptor = Self {
+            kdf: Kdf::Argon2id,
+            key_id: key_id.into(),
+            salt: general_purpose::STANDARD.encode(salt),
+            params,
+        };
+        descriptor.argon2()?;
+        Ok(descriptor)
+    }
+
+    /// Runs Argon2id over `passphrase` and returns the base64 IKM
+    pub fn derive_ikm(&self, passphrase: &SecretString) -> Result<SecretString> {
+        let salt = general_purpose::STANDARD.decode(&self.salt)?;
+        if salt.len() < SALT_LEN {
+            return Err(EncryptionError::InvalidConfig(format!(
+                "key descriptor salt is {} bytes; at least {} are required",
+                salt.len(),
+                SALT_LEN
+            )));
+        }
+        if passphrase.expose_secret().is_empty() {
+            return Err(EncryptionError::InvalidConfig("passphrase is empty".to_string()));
+        }
+
+        let mut ikm = SecretKey::<32>::zeroed();
+        self.argon2()?
+            .hash_password_into(passphrase.expose_secret().as_bytes(), &salt, ikm.expose_secret_mut())
+            .map_err(|e| EncryptionError::InvalidConfig(format!("Argon2id failed: {}", e)))?;
+
+        Ok(SecretString::from(general_purpose::STANDARD.encode(ikm.expose_secret())))
+    }
+
+    /// Keyring holding the passphrase-derived IKM as its active key
+    pub fn derive_keyring(&self, passphrase: &SecretString) -> Result<Keyring> {
+        Keyring::single(self.key_id.clone(), self.derive_ikm(passphrase)?)
+    }
+
+    fn argon2(&self) -> Result<Argon2<'static>> {
+        let params = Params::new(
+            self.params.memory_kib,
+            self.params.iterations,
+            self.params.parallelism,
+            Some(32),
+        )
+        .map_err(|e| EncryptionError::InvalidConfig(format!("invalid Argon2id parameters: {}", e)))?;
+        Ok(Argon2::new(argon2::Algorithm::Argon2id, Version::V0x13, params))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Cheap parameters keep the tests fast; real descriptors use the defaults
+    const TEST_PARAMS: Argon2Params = Argon2Params {
+        memory_kib: 64,
+        iterations: 1,
+        parallelism: 1,
+    };
+
+    #[test]
+    fn test_same_passphrase_and_descriptor_reproduce_the_key() -> Result<()> {
+        let descriptor = KeyDescriptor::generate("k1", TEST_PARAMS)?;
+        let reloaded: KeyDescriptor = serde_json::from_str(&serde_json::to_string(&descriptor)?)?;
+        let passphrase = SecretString::from("correct horse battery staple");
+
+        let ikm = descriptor.derive_ikm(&passphrase)?;
+        assert_eq!(ikm.expose_secret(), reloaded.derive_ikm(&passphrase)?.expose_secret());
+        assert_ne!(
+            ikm.expose_secret(),
+            descriptor.derive_ikm(&SecretString::from("wrong"))?.expose_secret()
+        );
+
+        let other_salt = KeyDescriptor::generate("k1", TEST_PARAMS)?;
+        assert_ne!(ikm.expose_secret(), other_salt.derive_ikm(&passphrase)?.expose_secret());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_weak_descriptors() -> Result<()> {
+        let mut descriptor = KeyDescriptor::generate("k1", TEST_PARAMS)?;
+        assert!(descriptor.derive_ikm(&SecretString::from("")).is_err());
+
+        descriptor.salt = general_purpose::STANDARD.encode([0u8; 8]);
+        assert!(descriptor.derive_ikm(&SecretString::from("passphrase")).is_err());
+
+        let no_memory = Argon2Params {
+            memory_kib: 1,
+            ..TEST_PARAMS
+        };
+        assert!(KeyDescriptor::generate("k1", no_memory).is_err());
+        Ok(())
+    }
+}
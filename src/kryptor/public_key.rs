@@ -0,0 +1,411 @@
+use aes_gcm::aead::OsRng;
+use base64::{Engine as _, engine::general_purpose};
+use hpke::aead::AesGcm256;
+use hpke::kdf::HkdfSha256;
+use hpke::kem::X25519HkdfSha256;
+use hpke::{Deserializable, Kem as _, OpModeR, OpModeS, Serializable};
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de::DeserializeOwned};
+use sha2::{Digest, Sha256};
+
+use crate::kryptor::algorithm::Algorithm;
+use crate::kryptor::compression::DEFAULT_MAX_DECOMPRESSED_LEN;
+use crate::kryptor::envelope::{Envelope, EnvelopeHeader};
+use crate::kryptor::errors::EncryptionError;
+use crate::kryptor::recipients::{self, PackageBody, Recipient};
+use crate::kryptor::secret::{SecretBytes, SecretKey, SecretString};
+use crate::kryptor::utilities::EncryptedData;
+
+pub type Result<T> = std::result::Result<T, EncryptionError>;
+
+type Kem = X25519HkdfSha256;
+
+/// HPKE info label; the decoded encryption context follows it
+const HPKE_INFO_LABEL: &[u8] = b"encry/hpke/v1";
+
+/// Length of the key id derived from a public key, in bytes before hex encoding
+const KEY_ID_LEN: usize = 8;
+
+/// X25519 public key of a recipient. Serializes as base64.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HpkePublicKey([u8; 32]);
+
+/// X25519 key pair held by the service that decrypts public-key envelopes
+#[derive(Debug)]
+pub struct HpkeKeyPair {
+    private_key: SecretKey<32>,
+    public_key: HpkePublicKey,
+}
+
+/// Public-key encryption with HPKE (RFC 9180) in base mode:
+/// DHKEM(X25519, HKDF-SHA256), HKDF-SHA256 and AES-256-GCM.
+///
+/// Producers only hold the recipient's public key, so they can encrypt but
+/// never decrypt; the consumer holding the private key decrypts. Base mode
+/// does not authenticate the sender: anyone with the public key can produce
+/// valid ciphertexts, so bind them to their row with associated data and
+/// authorize producers separately.
+///
+/// Ciphertexts are regular envelopes whose nonce field carries the 32-byte
+/// encapsulated key and whose key id is the recipient's `key_id`. The
+/// encryption context is bound through the HPKE info string, and the header
+/// plus caller associated data through the AEAD associated data.
+#[derive(Debug)]
+pub struct HpkeKryptor {
+    recipient: HpkePublicKey,
+    key_pair: Option<HpkeKeyPair>,
+    context_base64: String,
+}
+
+impl HpkePublicKey {
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn from_base64(encoded: &str) -> Result<Self> {
+        let bytes = general_purpose::STANDARD.decode(encoded.trim())?;
+        let bytes: [u8; 32] = bytes
+            .try_into()
+            .map_err(|_| EncryptionError::InvalidKeyring("X25519 public keys are 32 bytes".to_string()))?;
+        Ok(Self(bytes))
+    }
+
+    pub fn to_base64(&self) -> String {
+        general_purpose::STANDARD.encode(self.0)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// Key id recorded in envelopes: the first 8 bytes of SHA-256 of the key, as hex
+    pub fn key_id(&self) -> String {
+        Sha256::digest(self.0)[..KEY_ID_LEN]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    fn to_kem(&self) -> Result<<Kem as hpke::Kem>::PublicKey> {
+        <Kem as hpke::Kem>::PublicKey::from_bytes(&self.0)
+            .map_err(|e| EncryptionError::InvalidKeyring(format!("invalid X25519 public key: {}", e)))
+    }
+}
+
+impl Serialize for HpkePublicKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_base64())
+    }
+}
+
+impl<'de> Deserialize<'de> for HpkePublicKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        Self::from_base64(&encoded).map_err(serde::de::Error::custom)
+    }
+}
+
+impl HpkeKeyPair {
+    pub fn generate() -> Self {
+        let (private_key, public_key) = Kem::gen_keypair(&mut OsRng);
+        let mut private_bytes = SecretKey::<32>::zeroed();
+        private_bytes.expose_secret_mut().copy_from_slice(&private_key.to_bytes());
+        Self {
+            private_key: private_bytes,
+            public_key: HpkePublicKey(public_key.to_bytes().into()),
+        }
+    }
+
+    pub fn from_private_key_base64(encoded: &SecretString) -> Result<Self> {
+        let bytes = SecretBytes::from(general_purpose::STANDARD.decode(encoded.expose_secret().trim())?);
+        if bytes.len() != 32 {
+            return Err(EncryptionError::InvalidKeyring("X25519 private keys are 32 bytes".to_string()));
+        }
+        let mut private_key = SecretKey::<32>::zeroed();
+        private_key.expose_secret_mut().copy_from_slice(bytes.expose_secret());
+
+        let public_key = Kem::sk_to_pk(&Self::kem_private_key(&private_key)?);
+        Ok(Self {
+            private_key,
+            public_key: HpkePublicKey(public_key.to_bytes().into()),
+        })
+    }
+
+    /// Base64 private key, for storing in a key file
+    pub fn private_key_base64(&self) -> SecretString {
+        SecretString::from(general_purpose::STANDARD.encode(self.private_key.expose_secret()))
+    }
+
+    pub fn public_key(&self) -> &HpkePublicKey {
+        &self.public_key
+    }
+
+    fn kem_private_key(private_key: &SecretKey<32>) -> Result<<Kem as hpke::Kem>::PrivateKey> {
+        <Kem as hpke::Kem>::PrivateKey::from_bytes(private_key.expose_secret())
+            .map_err(|e| EncryptionError::InvalidKeyring(format!("invalid X25519 private key: {}", e)))
+    }
+}
+
+impl HpkeKryptor {
+    /// Encrypt-only kryptor for producers
+    pub fn for_recipient(recipient: HpkePublicKey, context_base64: String) -> Self {
+        Self {
+            recipient,
+            key_pair: None,
+            context_base64,
+        }
+    }
+
+    pub fn with_recipient<T: Serialize>(recipient: HpkePublicKey, context: &T) -> Result<Self> {
+        Ok(Self::for_recipient(recipient, encode_context(context)?))
+    }
+
+    /// Kryptor for the consumer; it encrypts to its own public key and decrypts
+    pub fn from_key_pair(key_pair: HpkeKeyPair, context_base64: String) -> Self {
+        Self {
+            recipient: key_pair.public_key.clone(),
+            key_pair: Some(key_pair),
+            context_base64,
+        }
+    }
+
+    pub fn with_key_pair<T: Serialize>(key_pair: HpkeKeyPair, context: &T) -> Result<Self> {
+        Ok(Self::from_key_pair(key_pair, encode_context(context)?))
+    }
+
+    /// Key id of the recipient new ciphertexts are encrypted to
+    pub fn key_id(&self) -> String {
+        self.recipient.key_id()
+    }
+
+    pub fn context_base64(&self) -> &str {
+        &self.context_base64
+    }
+
+    pub fn encrypt_json<T: Serialize>(&self, data: &T) -> Result<String> {
+        self.encrypt_json_with_aad(data, &[])
+    }
+
+    pub fn encrypt_json_with_aad<T: Serialize>(&self, data: &T, aad: &[u8]) -> Result<String> {
+        let json = serde_json::to_vec(data)?;
+        self.encrypt_bytes_with_aad(&json, aad)
+    }
+
+    pub fn decrypt_json<T: DeserializeOwned>(&self, encrypted_base64: &str) -> Result<T> {
+        self.decrypt_json_with_aad(encrypted_base64, &[])
+    }
+
+    pub fn decrypt_json_with_aad<T: DeserializeOwned>(&self, encrypted_base64: &str, aad: &[u8]) -> Result<T> {
+        let json = self.decrypt_bytes_with_aad(encrypted_base64, aad)?;
+        Ok(serde_json::from_slice(&json)?)
+    }
+
+    /// Encrypts raw bytes to the recipient.
+    /// Returns a base64-encoded envelope of [Header | Ciphertext | Tag].
+    pub fn encrypt_bytes(&self, plaintext: &[u8]) -> Result<String> {
+        self.encrypt_bytes_with_aad(plaintext, &[])
+    }
+
+    pub fn encrypt_bytes_with_aad(&self, plaintext: &[u8], aad: &[u8]) -> Result<String> {
+        Ok(general_purpose::STANDARD.encode(self.seal(plaintext, aad)?))
+    }
+
+    pub fn decrypt_bytes(&self, encoded_b64: &str) -> Result<Vec<u8>> {
+        self.decrypt_bytes_with_aad(encoded_b64, &[])
+    }
+
+    /// Decrypts an envelope bound to `aad`. Requires the private key.
+    pub fn decrypt_bytes_with_aad(&self, encoded_b64: &str, aad: &[u8]) -> Result<Vec<u8>> {
+        let data = general_purpose::STANDARD.decode(encoded_b64)?;
+        self.open(&data, aad)
+    }
+
+    /// Creates an EncryptedData package; its shape matches `KryptorService` packages
+    pub fn create_encrypted_package<T: Serialize>(&self, data: &T) -> Result<EncryptedData> {
+        self.create_encrypted_package_with_aad(data, &[])
+    }
+
+    pub fn create_encrypted_package_with_aad<T: Serialize>(&self, data: &T, aad: &[u8]) -> Result<EncryptedData> {
+        Ok(EncryptedData {
+            data: self.encrypt_json_with_aad(data, aad)?,
+            context: self.context_base64.clone(),
+            recipients: Vec::new(),
+        })
+    }
+
+    /// Creates a package readable by this kryptor's recipient and every one of `recipients`
+    pub fn create_encrypted_package_for<T: Serialize>(&self, data: &T, recipients: &[Recipient]) -> Result<EncryptedData> {
+        self.create_encrypted_package_for_with_aad(data, recipients, &[])
+    }
+
+    pub fn create_encrypted_package_for_with_aad<T: Serialize>(
+        &self,
+        data: &T,
+        recipients: &[Recipient],
+        aad: &[u8],
+    ) -> Result<EncryptedData> {
+        let mut all = vec![Recipient::PublicKey(self.recipient.clone())];
+        all.extend_from_slice(recipients);
+        recipients::create_package(data, &self.context_base64, &all, aad, &PackageBody::default())
+    }
+
+    /// Wraps the DEK of a multi-recipient package for one more recipient.
+    /// Requires the private key of an existing recipient.
+    pub fn add_recipient(&self, package: &mut EncryptedData, recipient: &Recipient) -> Result<()> {
+        let context_base64 = package.context.clone();
+        recipients::add_recipient(package, recipient, |wrapped| {
+            recipients::unwrap_public_key(self, wrapped, &context_base64)
+        })
+    }
+
+    pub fn decrypt_package<T: DeserializeOwned>(&self, package: &EncryptedData) -> Result<T> {
+        self.decrypt_package_with_aad(package, &[])
+    }
+
+    /// Decrypts a package under the context it carries
+    pub fn decrypt_package_with_aad<T: DeserializeOwned>(&self, package: &EncryptedData, aad: &[u8]) -> Result<T> {
+        if package.is_multi_recipient() {
+            return recipients::decrypt_package(package, aad, DEFAULT_MAX_DECOMPRESSED_LEN, |wrapped| {
+                recipients::unwrap_public_key(self, wrapped, &package.context)
+            });
+        }
+        let data = general_purpose::STANDARD.decode(&package.data)?;
+        let json = self.open_with_context(&data, aad, &package.context)?;
+        Ok(serde_json::from_slice(&json)?)
+    }
+
+    fn seal(&self, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        // The encapsulated key goes into the header, which is itself part of
+        // the associated data, so encapsulate before building the AAD
+        let info = hpke_info(&self.context_base64)?;
+        let recipient = self.recipient.to_kem()?;
+        let (encapped_key, mut sender) =
+            hpke::setup_sender::<AesGcm256, HkdfSha256, Kem, _>(&OpModeS::Base, &recipient, &info, &mut OsRng)
+                .map_err(|_| EncryptionError::EncryptionError(aes_gcm::Error))?;
+
+        let header = EnvelopeHeader::new(
+            Algorithm::HpkeX25519Aes256Gcm,
+            self.recipient.key_id(),
+            encapped_key.to_bytes().to_vec(),
+        )?;
+        let mut full_aad = header.to_bytes();
+        full_aad.extend_from_slice(aad);
+
+        let ciphertext = sender
+            .seal(plaintext, &full_aad)
+            .map_err(|_| EncryptionError::EncryptionError(aes_gcm::Error))?;
+        Ok(Envelope { header, ciphertext }.to_bytes())
+    }
+
+    fn open(&self, data: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        self.open_with_context(data, aad, &self.context_base64)
+    }
+
+    pub(crate) fn decrypt_bytes_with_context(&self, encoded_b64: &str, aad: &[u8], context_base64: &str) -> Result<Vec<u8>> {
+        let data = general_purpose::STANDARD.decode(encoded_b64)?;
+        self.open_with_context(&data, aad, context_base64)
+    }
+
+    fn open_with_context(&self, data: &[u8], aad: &[u8], context_base64: &str) -> Result<Vec<u8>> {
+        let key_pair = self.key_pair.as_ref().ok_or_else(|| {
+            EncryptionError::Other("this HpkeKryptor only holds a public key and cannot decrypt".to_string())
+        })?;
+
+        let envelope = Envelope::parse(data)?;
+        let header = &envelope.header;
+        if header.algorithm != Algorithm::HpkeX25519Aes256Gcm {
+            return Err(EncryptionError::InvalidEnvelope(format!(
+                "{} ciphertexts are symmetric; decrypt them with KryptorService",
+                header.algorithm.name()
+            )));
+        }
+        if header.key_id != key_pair.public_key.key_id() {
+            return Err(EncryptionError::UnknownKey(header.key_id.clone()));
+        }
+
+        let private_key = HpkeKeyPair::kem_private_key(&key_pair.private_key)?;
+        let encapped_key = <Kem as hpke::Kem>::EncappedKey::from_bytes(&header.nonce)
+            .map_err(|e| EncryptionError::InvalidEnvelope(format!("invalid encapsulated key: {}", e)))?;
+        let mut full_aad = header.to_bytes();
+        full_aad.extend_from_slice(aad);
+
+        hpke::single_shot_open::<AesGcm256, HkdfSha256, Kem>(
+            &OpModeR::Base,
+            &private_key,
+            &encapped_key,
+            &hpke_info(context_base64)?,
+            &envelope.ciphertext,
+            &full_aad,
+        )
+        .map_err(|_| EncryptionError::DecryptionError(aes_gcm::Error))
+    }
+}
+
+fn encode_context<T: Serialize>(context: &T) -> Result<String> {
+    let context_json = serde_json::to_string(context)?;
+    Ok(general_purpose::STANDARD.encode(&context_json))
+}
+
+fn hpke_info(context_base64: &str) -> Result<Vec<u8>> {
+    let mut info = HPKE_INFO_LABEL.to_vec();
+    info.push(0);
+    info.extend_from_slice(&general_purpose::STANDARD.decode(context_base64)?);
+    Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kryptor::utilities::KryptorService;
+    use crate::models::{EncryptionContext, EventStore};
+
+    #[test]
+    fn test_producer_encrypts_and_only_consumer_decrypts() -> Result<()> {
+        let consumer_keys = HpkeKeyPair::generate();
+        let context = EncryptionContext::new("aggregate-1".to_string());
+        let producer = HpkeKryptor::with_recipient(consumer_keys.public_key().clone(), &context)?;
+        let event = EventStore::new(
+            "aggregate-1".to_string(),
+            "Profile".to_string(),
+            0,
+            serde_json::json!({"name": "Mwaura"}),
+        );
+
+        let aad = event.associated_data();
+        let encrypted = producer.encrypt_json_with_aad(&event.payload, &aad)?;
+        assert!(producer.decrypt_json::<serde_json::Value>(&encrypted).is_err());
+
+        let consumer = HpkeKryptor::with_key_pair(consumer_keys, &context)?;
+        let payload: serde_json::Value = consumer.decrypt_json_with_aad(&encrypted, &aad)?;
+        assert_eq!(payload, event.payload);
+        assert!(consumer.decrypt_json::<serde_json::Value>(&encrypted).is_err());
+
+        let other_context = HpkeKryptor::from_key_pair(
+            HpkeKeyPair::from_private_key_base64(&consumer.key_pair.as_ref().unwrap().private_key_base64())?,
+            "b3RoZXI=".to_string(),
+        );
+        assert!(other_context.decrypt_json_with_aad::<serde_json::Value>(&encrypted, &aad).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_package_and_key_selection() -> Result<()> {
+        let consumer_keys = HpkeKeyPair::generate();
+        let public_key: HpkePublicKey = serde_json::from_str(&serde_json::to_string(consumer_keys.public_key())?)?;
+        let producer = HpkeKryptor::with_recipient(public_key, &"ctx")?;
+        let package = producer.create_encrypted_package(&"secret")?;
+
+        let consumer = HpkeKryptor::with_key_pair(consumer_keys, &"unrelated")?;
+        assert_eq!(consumer.decrypt_package::<String>(&package)?, "secret");
+
+        let stranger = HpkeKryptor::with_key_pair(HpkeKeyPair::generate(), &"ctx")?;
+        assert!(matches!(
+            stranger.decrypt_package::<String>(&package),
+            Err(EncryptionError::UnknownKey(_))
+        ));
+
+        // Symmetric services refuse public-key envelopes instead of misreading them
+        let mut symmetric = KryptorService::new("rph2pwTQCx+TD/lk+7o9igzQw5A7FU3+S+Z24Cf9Duk=".to_string(), package.context);
+        assert!(symmetric.decrypt_bytes(&package.data).is_err());
+        Ok(())
+    }
+}
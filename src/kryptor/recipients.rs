@@ -0,0 +1,385 @@
+use std::sync::Arc;
+
+use aes_gcm::aead::OsRng;
+use aes_gcm::aead::rand_core::RngCore;
+use base64::{Engine as _, engine::general_purpose};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::kryptor::algorithm::Algorithm;
+use crate::kryptor::compression::CompressionPolicy;
+use crate::kryptor::envelope::{Envelope, EnvelopeHeader};
+use crate::kryptor::errors::EncryptionError;
+use crate::kryptor::keyring::Keyring;
+use crate::kryptor::kms::KeyManagementService;
+use crate::kryptor::public_key::{HpkeKryptor, HpkePublicKey};
+use crate::kryptor::secret::{SecretBytes, SecretKey};
+use crate::kryptor::serialization::SerializationFormat;
+use crate::kryptor::utilities::{self, EncryptedData, KryptorService};
+
+pub type Result<T> = std::result::Result<T, EncryptionError>;
+
+/// Key id recorded in the body envelope of a multi-recipient package
+pub const DEK_KEY_ID: &str = "dek";
+
+/// Associated data of every wrapped DEK, so a wrapped DEK never decrypts as a payload
+pub(crate) const DEK_WRAP_AAD: &[u8] = b"encry/dek/v1";
+
+/// A party a multi-recipient package is readable by
+#[derive(Debug, Clone)]
+pub enum Recipient {
+    /// A service holding this keyring; the DEK is wrapped under its active key
+    Symmetric(Arc<Keyring>),
+    /// A service holding the matching HPKE private key
+    PublicKey(HpkePublicKey),
+    /// Whoever can call `unwrap` on this key management service
+    Kms(Arc<dyn KeyManagementService>),
+}
+
+/// How the body of a multi-recipient package is sealed. The envelope records
+/// all of it, so readers need no matching configuration.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PackageBody {
+    pub algorithm: Algorithm,
+    pub format: SerializationFormat,
+    pub compression: CompressionPolicy,
+}
+
+/// The package DEK wrapped for one recipient
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WrappedKey {
+    /// Keyring key id, public key id or KMS key id of the recipient
+    pub key_id: String,
+    /// Base64 envelope of the DEK, symmetric or HPKE; opaque for a KMS
+    pub wrapped_dek: String,
+}
+
+impl Recipient {
+    pub fn key_id(&self) -> String {
+        match self {
+            Recipient::Symmetric(keyring) => keyring.active().key_id.clone(),
+            Recipient::PublicKey(public_key) => public_key.key_id(),
+            Recipient::Kms(kms) => kms.key_id(),
+        }
+    }
+
+    fn wrap(&self, dek: &SecretKey<32>, context_base64: &str) -> Result<WrappedKey> {
+        let wrapped_dek = match self {
+            Recipient::Symmetric(keyring) => {
+                KryptorService::from_keyring(Arc::clone(keyring), context_base64.to_string())
+                    .encrypt_bytes_with_aad(dek.expose_secret(), DEK_WRAP_AAD)?
+            }
+            Recipient::PublicKey(public_key) => {
+                HpkeKryptor::for_recipient(public_key.clone(), context_base64.to_string())
+                    .encrypt_bytes_with_aad(dek.expose_secret(), DEK_WRAP_AAD)?
+            }
+            Recipient::Kms(kms) => return kms.wrap(dek.expose_secret(), context_base64),
+        };
+        Ok(WrappedKey {
+            key_id: self.key_id(),
+            wrapped_dek,
+        })
+    }
+}
+
+impl From<HpkePublicKey> for Recipient {
+    fn from(public_key: HpkePublicKey) -> Self {
+        Recipient::PublicKey(public_key)
+    }
+}
+
+impl From<Arc<dyn KeyManagementService>> for Recipient {
+    fn from(kms: Arc<dyn KeyManagementService>) -> Self {
+        Recipient::Kms(kms)
+    }
+}
+
+impl From<Keyring> for Recipient {
+    fn from(keyring: Keyring) -> Self {
+        Recipient::Symmetric(Arc::new(keyring))
+    }
+}
+
+impl From<Arc<Keyring>> for Recipient {
+    fn from(keyring: Arc<Keyring>) -> Self {
+        Recipient::Symmetric(keyring)
+    }
+}
+
+impl EncryptedData {
+    /// True when the payload is encrypted under a DEK wrapped for `recipients`
+    pub fn is_multi_recipient(&self) -> bool {
+        !self.recipients.is_empty()
+    }
+
+    /// Drops every wrapped DEK for `key_id`; returns whether one was removed.
+    /// The body is not re-encrypted, so a removed recipient that kept the DEK
+    /// or an older copy of the package can still read it.
+    pub fn remove_recipient(&mut self, key_id: &str) -> bool {
+        let before = self.recipients.len();
+        self.recipients.retain(|recipient| recipient.key_id != key_id);
+        self.recipients.len() != before
+    }
+}
+
+/// Encrypts `data` once under a random 256-bit DEK and wraps the DEK for
+/// each recipient under `context_base64`.
+pub(crate) fn create_package<T: Serialize>(
+    data: &T,
+    context_base64: &str,
+    recipients: &[Recipient],
+    aad: &[u8],
+    body: &PackageBody,
+) -> Result<EncryptedData> {
+    if recipients.is_empty() {
+        return Err(EncryptionError::Other("a multi-recipient package needs at least one recipient".to_string()));
+    }
+
+    let mut dek = SecretKey::<32>::zeroed();
+    OsRng.fill_bytes(dek.expose_secret_mut());
+    create_package_with_dek(data, context_base64, &dek, Vec::new(), recipients, aad, body)
+}
+
+/// Encrypts `data` under `dek` in a v2 envelope laid out by `body`, keeping
+/// the DEKs already wrapped in `wrapped` and wrapping it for each of `recipients`
+pub(crate) fn create_package_with_dek<T: Serialize>(
+    data: &T,
+    context_base64: &str,
+    dek: &SecretKey<32>,
+    mut wrapped: Vec<WrappedKey>,
+    recipients: &[Recipient],
+    aad: &[u8],
+    body: &PackageBody,
+) -> Result<EncryptedData> {
+    let payload = body.format.serialize(data)?;
+    let compression = body.compression.select(context_base64, payload.len());
+    let envelope = utilities::seal_envelope(
+        dek,
+        DEK_KEY_ID.to_string(),
+        &payload,
+        aad,
+        body.algorithm,
+        Some(body.format.content_type()),
+        compression,
+    )?;
+
+    for recipient in recipients {
+        wrapped.push(recipient.wrap(dek, context_base64)?);
+    }
+
+    Ok(EncryptedData {
+        data: general_purpose::STANDARD.encode(envelope),
+        context: context_base64.to_string(),
+        recipients: wrapped,
+    })
+}
+
+/// Wraps the DEK, unwrapped with `unwrap`, for one more recipient
+pub(crate) fn add_recipient<F>(package: &mut EncryptedData, recipient: &Recipient, unwrap: F) -> Result<()>
+where
+    F: FnMut(&WrappedKey) -> Result<SecretBytes>,
+{
+    let dek = unwrap_dek(package, unwrap)?;
+    let wrapped = recipient.wrap(&dek, &package.context)?;
+    package.recipients.push(wrapped);
+    Ok(())
+}
+
+/// Decrypts the body of a multi-recipient package. Bodies written before
+/// v2 envelopes were used hold plain JSON.
+pub(crate) fn decrypt_package<T, F>(
+    package: &EncryptedData,
+    aad: &[u8],
+    max_decompressed_len: usize,
+    unwrap: F,
+) -> Result<T>
+where
+    T: DeserializeOwned,
+    F: FnMut(&WrappedKey) -> Result<SecretBytes>,
+{
+    let dek = unwrap_dek(package, unwrap)?;
+    let envelope = Envelope::parse(&general_purpose::STANDARD.decode(&package.data)?)?;
+    let header = &envelope.header;
+    if header.key_id != DEK_KEY_ID {
+        return Err(EncryptionError::InvalidEnvelope(format!(
+            "multi-recipient body is under key '{}', expected '{}'",
+            header.key_id, DEK_KEY_ID
+        )));
+    }
+
+    let plaintext = utilities::open_envelope(&dek, &envelope, aad, max_decompressed_len)?;
+    match header.content_type {
+        None => Ok(serde_json::from_slice(&plaintext)?),
+        Some(content_type) => SerializationFormat::from_content_type(content_type)
+            .unwrap_or_default()
+            .deserialize(&plaintext),
+    }
+}
+
+/// Tries `unwrap` on each wrapped DEK in turn. Recipients that are not ours
+/// fail with `UnknownKey` and are skipped; the last other error is reported.
+pub(crate) fn unwrap_dek<F>(package: &EncryptedData, mut unwrap: F) -> Result<SecretKey<32>>
+where
+    F: FnMut(&WrappedKey) -> Result<SecretBytes>,
+{
+    let mut last_error = None;
+    for wrapped in &package.recipients {
+        match unwrap(wrapped) {
+            Ok(dek) => {
+                let dek: &[u8; 32] = dek
+                    .expose_secret()
+                    .try_into()
+                    .map_err(|_| EncryptionError::InvalidEnvelope("wrapped DEK is not 32 bytes".to_string()))?;
+                return Ok(SecretKey::from(*dek));
+            }
+            Err(EncryptionError::UnknownKey(_)) => {}
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| {
+        EncryptionError::UnknownKey(format!(
+            "none of the package recipients ({})",
+            package
+                .recipients
+                .iter()
+                .map(|recipient| recipient.key_id.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+    }))
+}
+
+pub(crate) fn unwrap_symmetric(service: &mut KryptorService, wrapped: &WrappedKey) -> Result<SecretBytes> {
+    if !matches!(wrapped_algorithm(wrapped), Ok(algorithm) if !algorithm.is_public_key()) {
+        return Err(EncryptionError::UnknownKey(wrapped.key_id.clone()));
+    }
+    service
+        .decrypt_bytes_with_aad(&wrapped.wrapped_dek, DEK_WRAP_AAD)
+        .map(SecretBytes::from)
+}
+
+pub(crate) fn unwrap_public_key(kryptor: &HpkeKryptor, wrapped: &WrappedKey, context_base64: &str) -> Result<SecretBytes> {
+    if !matches!(wrapped_algorithm(wrapped), Ok(algorithm) if algorithm.is_public_key()) {
+        return Err(EncryptionError::UnknownKey(wrapped.key_id.clone()));
+    }
+    kryptor
+        .decrypt_bytes_with_context(&wrapped.wrapped_dek, DEK_WRAP_AAD, context_base64)
+        .map(SecretBytes::from)
+}
+
+/// Algorithm recorded in a wrapped DEK's envelope
+pub(crate) fn wrapped_algorithm(wrapped: &WrappedKey) -> Result<Algorithm> {
+    let data = general_purpose::STANDARD.decode(&wrapped.wrapped_dek)?;
+    Ok(EnvelopeHeader::parse(&data)?.0.algorithm)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kryptor::compression::Compression;
+    use crate::kryptor::envelope::ContentType;
+    use crate::kryptor::keyring::{KeyEntry, KeyStatus};
+    use crate::kryptor::public_key::HpkeKeyPair;
+    use crate::kryptor::rewrap::Rewrapper;
+
+    const BILLING_IKM: &str = "rph2pwTQCx+TD/lk+7o9igzQw5A7FU3+S+Z24Cf9Duk=";
+    const SEARCH_IKM: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+
+    #[test]
+    fn test_every_recipient_decrypts_the_same_body() -> Result<()> {
+        let mut billing = KryptorService::with_keyring(Keyring::single("billing", BILLING_IKM)?, &"profile")?;
+        let search_keyring = Keyring::single("search", SEARCH_IKM)?;
+        let analytics_keys = HpkeKeyPair::generate();
+
+        let package = billing.create_encrypted_package_for_with_aad(
+            &"alice",
+            &[
+                Recipient::from(search_keyring.duplicate()),
+                Recipient::from(analytics_keys.public_key().clone()),
+            ],
+            b"user-1",
+        )?;
+        assert_eq!(package.recipients.len(), 3);
+
+        let mut search = KryptorService::with_keyring(search_keyring, &"other context")?;
+        let analytics = HpkeKryptor::with_key_pair(analytics_keys, &"other context")?;
+        assert_eq!(billing.decrypt_package_with_aad::<String>(&package, b"user-1")?, "alice");
+        assert_eq!(search.decrypt_package_with_aad::<String>(&package, b"user-1")?, "alice");
+        assert_eq!(analytics.decrypt_package_with_aad::<String>(&package, b"user-1")?, "alice");
+        assert!(search.decrypt_package_with_aad::<String>(&package, b"user-2").is_err());
+
+        let mut stranger = KryptorService::with_keyring(Keyring::single("stranger", SEARCH_IKM)?, &"profile")?;
+        assert!(matches!(
+            stranger.decrypt_package::<String>(&package),
+            Err(EncryptionError::UnknownKey(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_and_remove_recipients_keep_the_body() -> Result<()> {
+        let mut billing = KryptorService::with_keyring(Keyring::single("billing", BILLING_IKM)?, &"profile")?;
+        let mut package = billing.create_encrypted_package_for(&42, &[])?;
+        let body = package.data.clone();
+
+        let search_keyring = Keyring::single("search", SEARCH_IKM)?;
+        billing.add_recipient(&mut package, &Recipient::from(search_keyring.duplicate()))?;
+        assert_eq!(package.data, body);
+
+        let mut search = KryptorService::with_keyring(search_keyring, &"profile")?;
+        assert_eq!(search.decrypt_package::<i32>(&package)?, 42);
+
+        assert!(package.remove_recipient("search"));
+        assert!(!package.remove_recipient("search"));
+        assert_eq!(package.data, body);
+        assert!(search.decrypt_package::<i32>(&package).is_err());
+        assert_eq!(billing.decrypt_package::<i32>(&package)?, 42);
+        Ok(())
+    }
+
+    #[test]
+    fn test_package_body_follows_the_service_settings() -> Result<()> {
+        let search_keyring = Keyring::single("search", SEARCH_IKM)?;
+        let mut billing = KryptorService::with_keyring(Keyring::single("billing", BILLING_IKM)?, &"profile")?
+            .with_algorithm(Algorithm::XChaCha20Poly1305)
+            .with_format(SerializationFormat::Cbor)
+            .with_compression(CompressionPolicy::new(Compression::Zstd).with_threshold(0));
+        let record = "metadata ".repeat(200);
+        let package = billing.create_encrypted_package_for(&record, &[Recipient::from(search_keyring.duplicate())])?;
+
+        let header = Envelope::parse(&general_purpose::STANDARD.decode(&package.data)?)?.header;
+        assert_eq!(header.key_id, DEK_KEY_ID);
+        assert_eq!(header.algorithm, Algorithm::XChaCha20Poly1305);
+        assert_eq!(header.content_type, Some(ContentType::Cbor));
+        assert_eq!(header.compression()?, Compression::Zstd);
+
+        // Readers need none of the writer's settings
+        let mut search = KryptorService::with_keyring(search_keyring, &"profile")?;
+        assert_eq!(search.decrypt_package::<String>(&package)?, record);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rewrap_only_touches_our_wrapped_keys() -> Result<()> {
+        let mut keyring = Keyring::single("billing-1", BILLING_IKM)?;
+        let analytics_keys = HpkeKeyPair::generate();
+        let mut billing = KryptorService::with_keyring(keyring.duplicate(), &"profile")?;
+        let package =
+            billing.create_encrypted_package_for(&"alice", &[Recipient::from(analytics_keys.public_key().clone())])?;
+
+        keyring.rotate(KeyEntry::new("billing-2", SEARCH_IKM, KeyStatus::Active))?;
+        let rewrapped = Rewrapper::with_context(keyring.duplicate(), &"profile")?
+            .rewrap_package(&package)?
+            .expect("the billing DEK is under a decrypt-only key");
+        assert_eq!(rewrapped.data, package.data);
+        assert_eq!(rewrapped.recipients[0].key_id, "billing-2");
+        assert_eq!(rewrapped.recipients[1], package.recipients[1]);
+
+        keyring.set_status("billing-1", KeyStatus::Retired)?;
+        let mut billing = KryptorService::with_keyring(keyring, &"profile")?;
+        let analytics = HpkeKryptor::with_key_pair(analytics_keys, &"profile")?;
+        assert_eq!(billing.decrypt_package::<String>(&rewrapped)?, "alice");
+        assert_eq!(analytics.decrypt_package::<String>(&rewrapped)?, "alice");
+        Ok(())
+    }
+}
@@ -0,0 +1,330 @@
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::kryptor::compression::Compression;
+use crate::kryptor::encoding::TextEncoding;
+use crate::kryptor::envelope::Envelope;
+use crate::kryptor::errors::EncryptionError;
+use crate::kryptor::keyring::Keyring;
+use crate::kryptor::recipients::{self, DEK_WRAP_AAD};
+use crate::kryptor::utilities::{EncryptedData, KryptorService};
+
+pub type Result<T> = std::result::Result<T, EncryptionError>;
+
+/// A ciphertext to migrate onto the active key
+#[derive(Debug, Clone)]
+pub enum RewrapItem {
+    /// Base64 ciphertext from `encrypt_bytes`/`encrypt_json`, under the rewrapper's context
+    Ciphertext(String),
+    /// Ciphertext bound to associated data, e.g. `EventStore::associated_data`
+    Bound { data: String, aad: Vec<u8> },
+    /// Package carrying its own context
+    Package(EncryptedData),
+    /// Package bound to associated data, from `create_encrypted_package_with_aad`
+    BoundPackage { package: EncryptedData, aad: Vec<u8> },
+}
+
+#[derive(Debug)]
+pub enum RewrapOutcome {
+    /// The item was decrypted under its old key and re-encrypted under the active key
+    Rewrapped(RewrapItem),
+    /// The item is already encrypted under the active key and was left untouched
+    AlreadyCurrent,
+    Failed(EncryptionError),
+}
+
+/// Result of rewrapping a single item, keyed by its position in the input stream
+#[derive(Debug)]
+pub struct RewrapReport {
+    pub index: u64,
+    pub outcome: RewrapOutcome,
+}
+
+/// Checkpoint of a rewrap job. Persist it after each report to resume an
+/// interrupted job over the same input stream.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RewrapProgress {
+    /// Index of the next item to process; earlier items are skipped on resume
+    pub next_index: u64,
+    pub rewrapped: u64,
+    pub already_current: u64,
+    pub failed: u64,
+}
+
+/// Migrates ciphertexts written under decrypt-only or retired keys onto the
+/// keyring's active key. Bare legacy blobs are upgraded to envelopes as well.
+pub struct Rewrapper {
+    service: KryptorService,
+}
+
+impl Rewrapper {
+    pub fn new(keyring: impl Into<Arc<Keyring>>, context_base64: String) -> Self {
+        Self {
+            service: KryptorService::from_keyring(keyring, context_base64),
+        }
+    }
+
+    pub fn with_context<T: Serialize>(keyring: impl Into<Arc<Keyring>>, context: &T) -> Result<Self> {
+        Ok(Self {
+            service: KryptorService::with_keyring(keyring, context)?,
+        })
+    }
+
+    /// Text encoding of the ciphertexts passed to `rewrap_ciphertext`; packages
+    /// are always standard base64
+    pub fn with_encoding(mut self, encoding: TextEncoding) -> Self {
+        self.service = self.service.with_encoding(encoding);
+        self
+    }
+
+    /// Rewraps a single ciphertext, returning `None` when it already uses the active key
+    pub fn rewrap_ciphertext(&mut self, encrypted_base64: &str) -> Result<Option<String>> {
+        rewrap_with(&mut self.service, encrypted_base64, &[])
+    }
+
+    /// Rewraps a ciphertext bound to `aad`, keeping it bound to the same associated data
+    pub fn rewrap_ciphertext_with_aad(&mut self, encrypted_base64: &str, aad: &[u8]) -> Result<Option<String>> {
+        rewrap_with(&mut self.service, encrypted_base64, aad)
+    }
+
+    /// Rewraps a package under its own context, returning `None` when it already uses the active key.
+    /// For a multi-recipient package only the DEKs wrapped under this keyring are rewrapped.
+    pub fn rewrap_package(&mut self, package: &EncryptedData) -> Result<Option<EncryptedData>> {
+        self.rewrap_package_with_aad(package, &[])
+    }
+
+    /// Rewraps a package bound to `aad`, keeping it bound to the same associated data
+    pub fn rewrap_package_with_aad(&mut self, package: &EncryptedData, aad: &[u8]) -> Result<Option<EncryptedData>> {
+        let keyring = Arc::clone(self.service.shared_keyring());
+        let mut service = KryptorService::from_keyring(keyring, package.context.clone());
+        if package.is_multi_recipient() {
+            return rewrap_recipients(&mut service, package);
+        }
+
+        let rewrapped = rewrap_with(&mut service, &package.data, aad)?;
+        Ok(rewrapped.map(|data| EncryptedData {
+            data,
+            context: package.context.clone(),
+            recipients: Vec::new(),
+        }))
+    }
+
+    pub fn rewrap_item(&mut self, item: &RewrapItem) -> RewrapOutcome {
+        let result = match item {
+            RewrapItem::Ciphertext(data) => self
+                .rewrap_ciphertext(data)
+                .map(|rewrapped| rewrapped.map(RewrapItem::Ciphertext)),
+            RewrapItem::Bound { data, aad } => self.rewrap_ciphertext_with_aad(data, aad).map(|rewrapped| {
+                rewrapped.map(|data| RewrapItem::Bound {
+                    data,
+                    aad: aad.clone(),
+                })
+            }),
+            RewrapItem::Package(package) => self
+                .rewrap_package(package)
+                .map(|rewrapped| rewrapped.map(RewrapItem::Package)),
+            RewrapItem::BoundPackage { package, aad } => self.rewrap_package_with_aad(package, aad).map(|rewrapped| {
+                rewrapped.map(|package| RewrapItem::BoundPackage {
+                    package,
+                    aad: aad.clone(),
+                })
+            }),
+        };
+
+        match result {
+            Ok(Some(item)) => RewrapOutcome::Rewrapped(item),
+            Ok(None) => RewrapOutcome::AlreadyCurrent,
+            Err(e) => RewrapOutcome::Failed(e),
+        }
+    }
+
+    /// Processes a stream of items, skipping those before `progress.next_index`.
+    ///
+    /// Each report is handed to `on_report`, which is expected to persist the
+    /// rewrapped item (and the progress checkpoint). Per-item failures are
+    /// reported and counted without stopping the job; an error returned by
+    /// `on_report` stops the job with `progress` pointing at that item.
+    pub fn run<I, F>(&mut self, items: I, progress: &mut RewrapProgress, mut on_report: F) -> Result<()>
+    where
+        I: IntoIterator<Item = RewrapItem>,
+        F: FnMut(&RewrapReport, &RewrapProgress) -> Result<()>,
+    {
+        for (index, item) in items.into_iter().enumerate().skip(progress.next_index as usize) {
+            let report = RewrapReport {
+                index: index as u64,
+                outcome: self.rewrap_item(&item),
+            };
+
+            let mut next = progress.clone();
+            match report.outcome {
+                RewrapOutcome::Rewrapped(_) => next.rewrapped += 1,
+                RewrapOutcome::AlreadyCurrent => next.already_current += 1,
+                RewrapOutcome::Failed(_) => next.failed += 1,
+            }
+            next.next_index = report.index + 1;
+
+            on_report(&report, &next)?;
+            *progress = next;
+        }
+
+        Ok(())
+    }
+}
+
+/// Re-seals a ciphertext in the service's text encoding under the active key
+fn rewrap_with(service: &mut KryptorService, encrypted: &str, aad: &[u8]) -> Result<Option<String>> {
+    let encoding = service.encoding();
+    let data = encoding.decode(encrypted)?;
+
+    // Algorithm, content type and compression are kept, so double-encoded
+    // v1 JSON stays v1 and a ChaCha20 ciphertext stays ChaCha20
+    let (plaintext, algorithm, content_type, compression) = if Envelope::is_envelope(&data) {
+        let (header, plaintext) = service.open(&data, aad, true)?;
+        if header.key_id == service.key_id() {
+            return Ok(None);
+        }
+        (plaintext, header.algorithm, header.content_type, header.compression()?)
+    } else {
+        // Legacy blobs predate associated data support
+        (service.open_legacy(&data, true)?, service.algorithm(), None, Compression::None)
+    };
+
+    let envelope = service.seal_with(&plaintext, aad, algorithm, content_type, compression)?;
+    Ok(Some(encoding.encode(&envelope)))
+}
+
+fn rewrap_recipients(service: &mut KryptorService, package: &EncryptedData) -> Result<Option<EncryptedData>> {
+    let mut rewrapped = package.clone();
+    let mut changed = false;
+
+    for wrapped in rewrapped.recipients.iter_mut() {
+        let ours = service.keyring().get(&wrapped.key_id).is_some()
+            && matches!(recipients::wrapped_algorithm(wrapped), Ok(algorithm) if !algorithm.is_public_key());
+        if !ours {
+            continue;
+        }
+        if let Some(wrapped_dek) = rewrap_with(service, &wrapped.wrapped_dek, DEK_WRAP_AAD)? {
+            wrapped.key_id = service.key_id().to_string();
+            wrapped.wrapped_dek = wrapped_dek;
+            changed = true;
+        }
+    }
+
+    Ok(changed.then_some(rewrapped))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kryptor::algorithm::Algorithm;
+    use crate::kryptor::keyring::{KeyEntry, KeyStatus};
+
+    const OLD_IKM: &str = "rph2pwTQCx+TD/lk+7o9igzQw5A7FU3+S+Z24Cf9Duk=";
+    const NEW_IKM: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+
+    #[test]
+    fn test_rewrap_resumes_and_reports_per_item() -> Result<()> {
+        let mut keyring = Keyring::single("old", OLD_IKM)?;
+        let mut old_service = KryptorService::with_keyring(keyring.duplicate(), &"ctx")?;
+        let items = vec![
+            RewrapItem::Ciphertext(old_service.encrypt_json(&1)?),
+            RewrapItem::Ciphertext("not base64!".to_string()),
+            RewrapItem::Package(old_service.create_encrypted_package(&3)?),
+        ];
+
+        keyring.rotate(KeyEntry::new("new", NEW_IKM, KeyStatus::Active))?;
+        keyring.set_status("old", KeyStatus::Retired)?;
+        let mut rewrapper = Rewrapper::with_context(keyring.duplicate(), &"ctx")?;
+
+        // Stop after the first item to simulate an interrupted job
+        let mut progress = RewrapProgress::default();
+        let mut rewrapped = Vec::new();
+        let interrupted = rewrapper.run(items.clone(), &mut progress, |report, _| {
+            if report.index == 1 {
+                return Err(EncryptionError::Other("interrupted".to_string()));
+            }
+            if let RewrapOutcome::Rewrapped(item) = &report.outcome {
+                rewrapped.push(item.clone());
+            }
+            Ok(())
+        });
+        assert!(interrupted.is_err());
+        assert_eq!(progress.next_index, 1);
+
+        rewrapper.run(items, &mut progress, |report, _| {
+            if let RewrapOutcome::Rewrapped(item) = &report.outcome {
+                rewrapped.push(item.clone());
+            }
+            Ok(())
+        })?;
+        assert_eq!(progress.next_index, 3);
+        assert_eq!(progress.rewrapped, 2);
+        assert_eq!(progress.failed, 1);
+
+        let mut new_service = KryptorService::with_keyring(keyring, &"ctx")?;
+        match &rewrapped[..] {
+            [RewrapItem::Ciphertext(data), RewrapItem::Package(package)] => {
+                assert_eq!(new_service.decrypt_json::<i32>(data)?, 1);
+                assert_eq!(new_service.decrypt_package::<i32>(package)?, 3);
+                assert!(rewrapper.rewrap_ciphertext(data)?.is_none());
+            }
+            other => panic!("unexpected rewrap results: {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rewrap_keeps_algorithm_and_encoding() -> Result<()> {
+        let mut keyring = Keyring::single("old", OLD_IKM)?;
+        let mut old_service = KryptorService::with_keyring(keyring.duplicate(), &"ctx")?
+            .with_algorithm(Algorithm::ChaCha20Poly1305)
+            .with_encoding(TextEncoding::Hex);
+        let encrypted = old_service.encrypt_json_with_aad(&"alice", b"user-1")?;
+
+        keyring.rotate(KeyEntry::new("new", NEW_IKM, KeyStatus::Active))?;
+        let mut rewrapper = Rewrapper::with_context(keyring.duplicate(), &"ctx")?.with_encoding(TextEncoding::Hex);
+        let rewrapped = rewrapper
+            .rewrap_ciphertext_with_aad(&encrypted, b"user-1")?
+            .expect("written under the old key");
+
+        let header = Envelope::parse(&TextEncoding::Hex.decode(&rewrapped)?)?.header;
+        assert_eq!(header.key_id, "new");
+        assert_eq!(header.algorithm, Algorithm::ChaCha20Poly1305);
+
+        let mut new_service = KryptorService::with_keyring(keyring, &"ctx")?.with_encoding(TextEncoding::Hex);
+        assert_eq!(new_service.decrypt_json_with_aad::<String>(&rewrapped, b"user-1")?, "alice");
+        Ok(())
+    }
+
+    #[test]
+    fn test_rewrap_bound_package() -> Result<()> {
+        let mut keyring = Keyring::single("old", OLD_IKM)?;
+        let mut old_service = KryptorService::with_keyring(keyring.duplicate(), &"ctx")?;
+        let package = old_service.create_encrypted_package_with_aad(&"alice", b"user-1")?;
+
+        keyring.rotate(KeyEntry::new("new", NEW_IKM, KeyStatus::Active))?;
+        keyring.set_status("old", KeyStatus::Retired)?;
+        let mut rewrapper = Rewrapper::with_context(keyring.duplicate(), &"ctx")?;
+        assert!(rewrapper.rewrap_package(&package).is_err());
+
+        let item = RewrapItem::BoundPackage {
+            package,
+            aad: b"user-1".to_vec(),
+        };
+        let rewrapped = match rewrapper.rewrap_item(&item) {
+            RewrapOutcome::Rewrapped(RewrapItem::BoundPackage { package, aad }) => {
+                assert_eq!(aad, b"user-1");
+                package
+            }
+            other => panic!("unexpected rewrap outcome: {:?}", other),
+        };
+
+        let mut new_service = KryptorService::with_keyring(keyring, &"ctx")?;
+        assert_eq!(new_service.decrypt_package_with_aad::<String>(&rewrapped, b"user-1")?, "alice");
+        assert!(new_service.decrypt_package::<String>(&rewrapped).is_err());
+        assert!(rewrapper.rewrap_package_with_aad(&rewrapped, b"user-1")?.is_none());
+        Ok(())
+    }
+}
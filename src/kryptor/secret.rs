@@ -0,0 +1,147 @@
+use std::fmt;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use zeroize::{Zeroize, Zeroizing};
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Fixed-size key material, zeroed on drop.
+///
+/// The bytes live on the heap so moving the wrapper never leaves copies on
+/// the stack. It is neither `Copy` nor `Clone`; use `duplicate` to copy it.
+pub struct SecretKey<const N: usize>(Box<[u8; N]>);
+
+/// Variable-length secret bytes such as a decoded IKM, zeroed on drop
+pub struct SecretBytes(Zeroizing<Vec<u8>>);
+
+/// Secret text such as a base64 IKM, zeroed on drop. Serializing it writes
+/// the secret verbatim, so only serialize it into key files.
+pub struct SecretString(Zeroizing<String>);
+
+impl<const N: usize> SecretKey<N> {
+    pub fn zeroed() -> Self {
+        Self(Box::new([0u8; N]))
+    }
+
+    pub fn expose_secret(&self) -> &[u8; N] {
+        &self.0
+    }
+
+    pub fn expose_secret_mut(&mut self) -> &mut [u8; N] {
+        &mut self.0
+    }
+
+    /// Explicit copy of the key
+    pub fn duplicate(&self) -> Self {
+        let mut copy = Self::zeroed();
+        copy.0.copy_from_slice(self.0.as_slice());
+        copy
+    }
+}
+
+impl<const N: usize> From<[u8; N]> for SecretKey<N> {
+    /// Moves the bytes onto the heap and zeroes the array passed in
+    fn from(mut bytes: [u8; N]) -> Self {
+        let key = Self(Box::new(bytes));
+        bytes.zeroize();
+        key
+    }
+}
+
+impl<const N: usize> Drop for SecretKey<N> {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl<const N: usize> fmt::Debug for SecretKey<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretKey<{}>({})", N, REDACTED)
+    }
+}
+
+impl SecretBytes {
+    pub fn expose_secret(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<Vec<u8>> for SecretBytes {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self(Zeroizing::new(bytes))
+    }
+}
+
+impl fmt::Debug for SecretBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretBytes({})", REDACTED)
+    }
+}
+
+impl SecretString {
+    pub fn expose_secret(&self) -> &str {
+        &self.0
+    }
+
+    /// Explicit copy of the secret
+    pub fn duplicate(&self) -> Self {
+        Self::from(self.expose_secret())
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(secret: String) -> Self {
+        Self(Zeroizing::new(secret))
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(secret: &str) -> Self {
+        Self::from(secret.to_string())
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretString({})", REDACTED)
+    }
+}
+
+impl Serialize for SecretString {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.expose_secret())
+    }
+}
+
+impl<'de> Deserialize<'de> for SecretString {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debug_is_redacted() {
+        let mut key = SecretKey::<32>::zeroed();
+        key.expose_secret_mut()[0] = 0xAB;
+        let ikm = SecretString::from("c2VjcmV0");
+
+        assert_eq!(format!("{:?}", key), "SecretKey<32>([REDACTED])");
+        assert_eq!(format!("{:?}", ikm), "SecretString([REDACTED])");
+        assert!(!format!("{:?}", SecretBytes::from(b"secret".to_vec())).contains("secret"));
+
+        let copy = key.duplicate();
+        assert_eq!(copy.expose_secret(), key.expose_secret());
+    }
+}
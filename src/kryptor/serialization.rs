@@ -0,0 +1,121 @@
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::kryptor::envelope::ContentType;
+use crate::kryptor::errors::EncryptionError;
+
+pub type Result<T> = std::result::Result<T, EncryptionError>;
+
+/// Payload serialization used by `encrypt_value`. The format is recorded as
+/// the envelope's content type, so `decrypt_value` needs no configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum SerializationFormat {
+    #[default]
+    Json,
+    /// Compact and self-describing; round-trips `serde_json::Value`
+    Cbor,
+    /// Compact and self-describing. Structs are written as maps with field
+    /// names, so `#[serde(default)]` and field reordering keep working.
+    MessagePack,
+    /// Smallest and fastest, but not self-describing: it needs the exact
+    /// target type and cannot decode `serde_json::Value` or
+    /// `#[serde(skip_serializing_if)]` fields
+    Bincode,
+}
+
+impl SerializationFormat {
+    pub fn name(&self) -> &'static str {
+        self.content_type().name()
+    }
+
+    pub fn content_type(&self) -> ContentType {
+        match self {
+            SerializationFormat::Json => ContentType::Json,
+            SerializationFormat::Cbor => ContentType::Cbor,
+            SerializationFormat::MessagePack => ContentType::MessagePack,
+            SerializationFormat::Bincode => ContentType::Bincode,
+        }
+    }
+
+    /// Format of a content type; `None` for opaque bytes
+    pub fn from_content_type(content_type: ContentType) -> Option<Self> {
+        match content_type {
+            ContentType::Bytes => None,
+            ContentType::Json => Some(SerializationFormat::Json),
+            ContentType::Cbor => Some(SerializationFormat::Cbor),
+            ContentType::MessagePack => Some(SerializationFormat::MessagePack),
+            ContentType::Bincode => Some(SerializationFormat::Bincode),
+        }
+    }
+
+    pub fn serialize<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>> {
+        match self {
+            SerializationFormat::Json => Ok(serde_json::to_vec(value)?),
+            SerializationFormat::Cbor => {
+                let mut out = Vec::new();
+                ciborium::into_writer(value, &mut out).map_err(|e| codec_error(self, e))?;
+                Ok(out)
+            }
+            SerializationFormat::MessagePack => rmp_serde::to_vec_named(value).map_err(|e| codec_error(self, e)),
+            SerializationFormat::Bincode => {
+                bincode::serde::encode_to_vec(value, bincode::config::standard()).map_err(|e| codec_error(self, e))
+            }
+        }
+    }
+
+    pub fn deserialize<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T> {
+        match self {
+            SerializationFormat::Json => Ok(serde_json::from_slice(bytes)?),
+            SerializationFormat::Cbor => ciborium::from_reader(bytes).map_err(|e| codec_error(self, e)),
+            SerializationFormat::MessagePack => rmp_serde::from_slice(bytes).map_err(|e| codec_error(self, e)),
+            SerializationFormat::Bincode => {
+                let (value, read) = bincode::serde::decode_from_slice(bytes, bincode::config::standard())
+                    .map_err(|e| codec_error(self, e))?;
+                if read != bytes.len() {
+                    return Err(codec_error(self, format!("{} trailing bytes", bytes.len() - read)));
+                }
+                Ok(value)
+            }
+        }
+    }
+}
+
+fn codec_error(format: &SerializationFormat, err: impl std::fmt::Display) -> EncryptionError {
+    EncryptionError::CodecError(format!("{}: {}", format.name(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Profile;
+
+    #[test]
+    fn test_formats_round_trip_and_shrink_payloads() -> Result<()> {
+        let profile = Profile::new(
+            "Alice".to_string(),
+            "1990-01-01".to_string(),
+            "alice@example.com".to_string(),
+            vec!["+254712345678".to_string()],
+        );
+        let json_len = SerializationFormat::Json.serialize(&profile)?.len();
+
+        for format in [
+            SerializationFormat::Cbor,
+            SerializationFormat::MessagePack,
+            SerializationFormat::Bincode,
+        ] {
+            let bytes = format.serialize(&profile)?;
+            assert!(bytes.len() < json_len, "{} is not smaller than JSON", format.name());
+            let decoded: Profile = format.deserialize(&bytes)?;
+            assert_eq!(decoded.email, profile.email);
+            assert_eq!(SerializationFormat::from_content_type(format.content_type()), Some(format));
+        }
+
+        let value = serde_json::json!({ "name": "Alice", "tags": [1, 2] });
+        for format in [SerializationFormat::Cbor, SerializationFormat::MessagePack] {
+            assert_eq!(format.deserialize::<serde_json::Value>(&format.serialize(&value)?)?, value);
+        }
+        let bincode = SerializationFormat::Bincode.serialize(&value)?;
+        assert!(SerializationFormat::Bincode.deserialize::<serde_json::Value>(&bincode).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,347 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use aes_gcm::aead::OsRng;
+use aes_gcm::aead::rand_core::RngCore;
+use base64::{Engine as _, engine::general_purpose};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::kryptor::errors::EncryptionError;
+use crate::kryptor::files::write_private;
+use crate::kryptor::keyring::{Keyring, unix_now};
+use crate::kryptor::kms::{KeyManagementService, LocalKms};
+use crate::kryptor::recipients::WrappedKey;
+use crate::kryptor::secret::{SecretKey, SecretString};
+use crate::kryptor::utilities::KryptorService;
+use crate::models::EncryptionContext;
+
+pub type Result<T> = std::result::Result<T, EncryptionError>;
+
+/// Key id recorded in envelopes encrypted under a per-aggregate key
+pub const AGGREGATE_KEY_ID: &str = "aggregate";
+
+/// What the key store holds for one aggregate
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum AggregateKeyRecord {
+    /// The aggregate's random IKM, wrapped by the key management service
+    Active { wrapped_key: WrappedKey, created_at: u64 },
+    /// Tombstone left after shredding; no new key is ever created for the aggregate
+    Shredded { shredded_at: u64 },
+}
+
+/// Shred status of an aggregate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShredStatus {
+    /// Nothing has been encrypted under a per-aggregate key yet
+    NoKey,
+    Active { created_at: u64 },
+    Shredded { shredded_at: u64 },
+}
+
+/// Persists per-aggregate key records
+pub trait AggregateKeyStore: std::fmt::Debug {
+    fn get(&self, aggregate_key: &str) -> Result<Option<AggregateKeyRecord>>;
+
+    /// Inserts or replaces the record. Replacing an active record with a
+    /// tombstone must discard the wrapped key for good.
+    fn put(&mut self, aggregate_key: &str, record: AggregateKeyRecord) -> Result<()>;
+}
+
+/// Key store kept in memory, for tests
+#[derive(Debug, Default)]
+pub struct InMemoryKeyStore {
+    records: HashMap<String, AggregateKeyRecord>,
+}
+
+impl InMemoryKeyStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl AggregateKeyStore for InMemoryKeyStore {
+    fn get(&self, aggregate_key: &str) -> Result<Option<AggregateKeyRecord>> {
+        Ok(self.records.get(aggregate_key).cloned())
+    }
+
+    fn put(&mut self, aggregate_key: &str, record: AggregateKeyRecord) -> Result<()> {
+        self.records.insert(aggregate_key.to_string(), record);
+        Ok(())
+    }
+}
+
+/// Key store kept in a JSON file. Every change writes a new copy and renames
+/// it over the old one, so a failed write never loses the other keys.
+#[derive(Debug)]
+pub struct FileKeyStore {
+    path: PathBuf,
+    records: BTreeMap<String, AggregateKeyRecord>,
+}
+
+impl FileKeyStore {
+    /// Opens the store at `path`, starting empty when the file does not exist yet
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let records = match fs::read_to_string(&path) {
+            Ok(source) => serde_json::from_str(&source)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => {
+                return Err(EncryptionError::InvalidConfig(format!(
+                    "cannot read key store {}: {}",
+                    path.display(),
+                    e
+                )));
+            }
+        };
+        Ok(Self { path, records })
+    }
+}
+
+impl AggregateKeyStore for FileKeyStore {
+    fn get(&self, aggregate_key: &str) -> Result<Option<AggregateKeyRecord>> {
+        Ok(self.records.get(aggregate_key).cloned())
+    }
+
+    fn put(&mut self, aggregate_key: &str, record: AggregateKeyRecord) -> Result<()> {
+        let mut records = self.records.clone();
+        records.insert(aggregate_key.to_string(), record);
+        let json = serde_json::to_string_pretty(&records)?;
+        write_private(&self.path, json.as_bytes(), false)?;
+        self.records = records;
+        Ok(())
+    }
+}
+
+/// Encrypts each aggregate under its own random IKM so the aggregate can be
+/// forgotten: shredding deletes the IKM and every ciphertext written under it
+/// becomes permanently unreadable, wherever copies of it live.
+///
+/// IKMs are stored wrapped by a key management service and bound to their
+/// aggregate key. Data keys are derived exactly as for `KryptorService`, with
+/// `EncryptionContext::new(aggregate_key)` as the context.
+#[derive(Debug)]
+pub struct ShreddingKryptor<S: AggregateKeyStore> {
+    store: S,
+    kms: Arc<dyn KeyManagementService>,
+    services: HashMap<String, KryptorService>,
+}
+
+impl<S: AggregateKeyStore> ShreddingKryptor<S> {
+    pub fn new(store: S, kms: Arc<dyn KeyManagementService>) -> Self {
+        Self {
+            store,
+            kms,
+            services: HashMap::new(),
+        }
+    }
+
+    /// Wraps aggregate IKMs under the active key of `keyring`
+    pub fn with_keyring(store: S, keyring: impl Into<Arc<Keyring>>) -> Self {
+        Self::new(store, Arc::new(LocalKms::new(keyring)))
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    pub fn shred_status(&self, aggregate_key: &str) -> Result<ShredStatus> {
+        Ok(match self.store.get(aggregate_key)? {
+            None => ShredStatus::NoKey,
+            Some(AggregateKeyRecord::Active { created_at, .. }) => ShredStatus::Active { created_at },
+            Some(AggregateKeyRecord::Shredded { shredded_at }) => ShredStatus::Shredded { shredded_at },
+        })
+    }
+
+    pub fn is_shredded(&self, aggregate_key: &str) -> Result<bool> {
+        Ok(matches!(self.shred_status(aggregate_key)?, ShredStatus::Shredded { .. }))
+    }
+
+    /// Destroys the aggregate's key and leaves a tombstone. Returns whether a
+    /// key was destroyed; shredding an aggregate twice is a no-op.
+    pub fn shred(&mut self, aggregate_key: &str) -> Result<bool> {
+        let status = self.shred_status(aggregate_key)?;
+        if matches!(status, ShredStatus::Shredded { .. }) {
+            return Ok(false);
+        }
+
+        self.services.remove(aggregate_key);
+        self.store.put(
+            aggregate_key,
+            AggregateKeyRecord::Shredded {
+                shredded_at: unix_now(),
+            },
+        )?;
+        Ok(matches!(status, ShredStatus::Active { .. }))
+    }
+
+    /// Service for writing, creating the aggregate's key on first use
+    pub fn service_for(&mut self, aggregate_key: &str) -> Result<&mut KryptorService> {
+        if !self.services.contains_key(aggregate_key) {
+            let service = match self.store.get(aggregate_key)? {
+                None => self.create_key(aggregate_key)?,
+                Some(record) => self.open_key(aggregate_key, record)?,
+            };
+            self.services.insert(aggregate_key.to_string(), service);
+        }
+        Ok(self.services.get_mut(aggregate_key).expect("service was just inserted"))
+    }
+
+    /// Service for reading; fails with `KeyShredded` after `shred`
+    pub fn reader_for(&mut self, aggregate_key: &str) -> Result<&mut KryptorService> {
+        if !self.services.contains_key(aggregate_key) {
+            let record = self
+                .store
+                .get(aggregate_key)?
+                .ok_or_else(|| EncryptionError::UnknownKey(format!("no key for aggregate {}", aggregate_key)))?;
+            let service = self.open_key(aggregate_key, record)?;
+            self.services.insert(aggregate_key.to_string(), service);
+        }
+        Ok(self.services.get_mut(aggregate_key).expect("service was just inserted"))
+    }
+
+    /// Encrypts `data` under the aggregate's key, e.g. an `EventStore` payload
+    /// bound to `event.associated_data()`
+    pub fn encrypt_json_with_aad<T: Serialize>(&mut self, aggregate_key: &str, data: &T, aad: &[u8]) -> Result<String> {
+        self.service_for(aggregate_key)?.encrypt_json_with_aad(data, aad)
+    }
+
+    pub fn decrypt_json_with_aad<T: DeserializeOwned>(
+        &mut self,
+        aggregate_key: &str,
+        encrypted_base64: &str,
+        aad: &[u8],
+    ) -> Result<T> {
+        self.reader_for(aggregate_key)?.decrypt_json_with_aad(encrypted_base64, aad)
+    }
+
+    fn create_key(&mut self, aggregate_key: &str) -> Result<KryptorService> {
+        let mut ikm = SecretKey::<32>::zeroed();
+        OsRng.fill_bytes(ikm.expose_secret_mut());
+        let wrapped_key = self
+            .kms
+            .wrap(ikm.expose_secret(), &wrap_context(aggregate_key)?)?;
+
+        self.store.put(
+            aggregate_key,
+            AggregateKeyRecord::Active {
+                wrapped_key,
+                created_at: unix_now(),
+            },
+        )?;
+        aggregate_service(aggregate_key, ikm.expose_secret())
+    }
+
+    fn open_key(&self, aggregate_key: &str, record: AggregateKeyRecord) -> Result<KryptorService> {
+        match record {
+            AggregateKeyRecord::Active { wrapped_key, .. } => {
+                let ikm = self.kms.unwrap(&wrapped_key, &wrap_context(aggregate_key)?)?;
+                aggregate_service(aggregate_key, ikm.expose_secret())
+            }
+            AggregateKeyRecord::Shredded { .. } => Err(EncryptionError::KeyShredded(aggregate_key.to_string())),
+        }
+    }
+}
+
+/// Binds a wrapped IKM to its aggregate so it cannot be copied to another one
+fn wrap_context(aggregate_key: &str) -> Result<String> {
+    let context = serde_json::to_vec(&["encry:aggregate-key", aggregate_key])?;
+    Ok(general_purpose::STANDARD.encode(context))
+}
+
+fn aggregate_service(aggregate_key: &str, ikm: &[u8]) -> Result<KryptorService> {
+    let ikm_base64 = SecretString::from(general_purpose::STANDARD.encode(ikm));
+    KryptorService::with_keyring(
+        Keyring::single(AGGREGATE_KEY_ID, ikm_base64)?,
+        &EncryptionContext::new(aggregate_key.to_string()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{EventStore, Profile};
+
+    const MASTER_IKM: &str = "rph2pwTQCx+TD/lk+7o9igzQw5A7FU3+S+Z24Cf9Duk=";
+
+    fn event(aggregate_key: &str) -> EventStore {
+        let profile = Profile::new(
+            "Alice".to_string(),
+            "1990-01-01".to_string(),
+            "alice@example.com".to_string(),
+            vec![],
+        );
+        EventStore::with_profile(aggregate_key.to_string(), profile)
+    }
+
+    #[test]
+    fn test_shredding_makes_one_aggregate_unreadable() -> Result<()> {
+        let mut kryptor = ShreddingKryptor::with_keyring(InMemoryKeyStore::new(), Keyring::single("master", MASTER_IKM)?);
+        let (alice, bob) = (event("agg-alice"), event("agg-bob"));
+        assert_eq!(kryptor.shred_status("agg-alice")?, ShredStatus::NoKey);
+
+        let alice_payload =
+            kryptor.encrypt_json_with_aad(&alice.aggregated_key, &alice.payload, &alice.associated_data())?;
+        let bob_payload = kryptor.encrypt_json_with_aad(&bob.aggregated_key, &bob.payload, &bob.associated_data())?;
+        assert!(matches!(kryptor.shred_status("agg-alice")?, ShredStatus::Active { .. }));
+
+        assert!(kryptor.shred("agg-alice")?);
+        assert!(!kryptor.shred("agg-alice")?);
+        assert!(kryptor.is_shredded("agg-alice")?);
+
+        let result: Result<serde_json::Value> =
+            kryptor.decrypt_json_with_aad("agg-alice", &alice_payload, &alice.associated_data());
+        assert!(matches!(result, Err(EncryptionError::KeyShredded(id)) if id == "agg-alice"));
+        assert!(matches!(
+            kryptor.service_for("agg-alice"),
+            Err(EncryptionError::KeyShredded(_))
+        ));
+
+        let recovered: serde_json::Value =
+            kryptor.decrypt_json_with_aad("agg-bob", &bob_payload, &bob.associated_data())?;
+        assert_eq!(recovered, bob.payload);
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_store_survives_reopen_and_binds_keys_to_aggregates() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("encry-shred-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let keyring = Keyring::single("master", MASTER_IKM)?;
+
+        let mut writer = ShreddingKryptor::with_keyring(FileKeyStore::open(&path)?, keyring.duplicate());
+        let encrypted = writer.encrypt_json_with_aad("agg-1", &"secret", b"v1")?;
+        writer.service_for("agg-2")?;
+        writer.shred("agg-2")?;
+
+        let mut store = FileKeyStore::open(&path)?;
+        let mut reader = ShreddingKryptor::with_keyring(FileKeyStore::open(&path)?, keyring.duplicate());
+        assert_eq!(reader.decrypt_json_with_aad::<String>("agg-1", &encrypted, b"v1")?, "secret");
+        assert!(reader.is_shredded("agg-2")?);
+
+        // A wrapped key copied onto another aggregate does not unwrap
+        let copied = store.get("agg-1")?.expect("agg-1 has a key");
+        store.put("agg-3", copied)?;
+        let mut reader = ShreddingKryptor::with_keyring(store, keyring);
+        assert!(reader.reader_for("agg-3").is_err());
+
+        // Each put replaces the file through a private temporary copy
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o777, 0o600);
+        }
+        let prefix = format!(".{}.", path.file_name().unwrap().to_string_lossy());
+        let leftovers = fs::read_dir(path.parent().unwrap())?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .filter(|name| name.starts_with(&prefix) && name.ends_with(".tmp"))
+            .count();
+        assert_eq!(leftovers, 0);
+
+        fs::remove_file(path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,454 @@
+use std::io::{self, Read, Write};
+
+use hkdf::Hkdf;
+use rand::RngCore;
+use sha2::Sha256;
+
+use crate::kryptor::algorithm::Algorithm;
+use crate::kryptor::errors::EncryptionError;
+use crate::kryptor::keyring::KeyStatus;
+use crate::kryptor::secret::SecretKey;
+use crate::kryptor::utilities::KryptorService;
+
+pub type Result<T> = std::result::Result<T, EncryptionError>;
+
+/// Magic prefix identifying a chunked encry stream
+pub const STREAM_MAGIC: [u8; 4] = *b"ENCS";
+
+/// Stream format version written by this build
+pub const STREAM_VERSION: u8 = 2;
+
+/// Bytes of the random per-stream salt
+pub const STREAM_SALT_LEN: usize = 32;
+
+/// HKDF info for deriving a stream key from the context key and the salt
+const STREAM_KEY_INFO: &[u8] = b"encry stream key";
+
+/// Default plaintext bytes per chunk
+pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Largest chunk size a reader accepts, bounding its buffer
+pub const MAX_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+
+/// Every supported streaming AEAD appends a 16-byte tag
+const TAG_LEN: usize = 16;
+
+/// Bytes of each chunk nonce taken by the counter and the final-chunk flag
+const NONCE_SUFFIX_LEN: usize = 5;
+
+/// Header of a chunked stream, authenticated as associated data of every chunk.
+///
+/// Wire layout:
+/// `STREAM_MAGIC(4) | version(1) | algorithm(1) | key_id_len(1) | key_id | salt(32) | prefix_len(1) | nonce_prefix | chunk_size(4, BE)`
+///
+/// Chunks follow as `[Ciphertext | Tag]`. Chunk `i` is sealed with the nonce
+/// `nonce_prefix | i (4, BE) | last (1)` (the STREAM construction), so dropping,
+/// reordering or truncating chunks fails authentication. Each stream is sealed
+/// with its own key, derived by HKDF from the context key and the salt, so a
+/// repeated nonce prefix across streams never repeats a (key, nonce) pair.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamHeader {
+    pub algorithm: Algorithm,
+    pub key_id: String,
+    pub salt: [u8; STREAM_SALT_LEN],
+    pub nonce_prefix: Vec<u8>,
+    pub chunk_size: usize,
+}
+
+impl StreamHeader {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(12 + self.key_id.len() + STREAM_SALT_LEN + self.nonce_prefix.len());
+        out.extend_from_slice(&STREAM_MAGIC);
+        out.push(STREAM_VERSION);
+        out.push(self.algorithm.id());
+        out.push(self.key_id.len() as u8);
+        out.extend_from_slice(self.key_id.as_bytes());
+        out.extend_from_slice(&self.salt);
+        out.push(self.nonce_prefix.len() as u8);
+        out.extend_from_slice(&self.nonce_prefix);
+        out.extend_from_slice(&(self.chunk_size as u32).to_be_bytes());
+        out
+    }
+
+    /// Reads and validates a header from the front of a stream
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut fixed = [0u8; 7];
+        reader.read_exact(&mut fixed)?;
+        if fixed[..4] != STREAM_MAGIC {
+            return Err(EncryptionError::InvalidEnvelope("missing stream magic prefix".to_string()));
+        }
+        if fixed[4] != STREAM_VERSION {
+            return Err(EncryptionError::UnsupportedVersion(fixed[4]));
+        }
+        let algorithm = stream_algorithm(Algorithm::from_id(fixed[5])?)?;
+
+        let mut key_id = vec![0u8; fixed[6] as usize];
+        reader.read_exact(&mut key_id)?;
+        let key_id = String::from_utf8(key_id)?;
+
+        let mut salt = [0u8; STREAM_SALT_LEN];
+        reader.read_exact(&mut salt)?;
+
+        let mut prefix_len = [0u8; 1];
+        reader.read_exact(&mut prefix_len)?;
+        if prefix_len[0] as usize + NONCE_SUFFIX_LEN != algorithm.nonce_len() {
+            return Err(EncryptionError::InvalidEnvelope(format!(
+                "{} streams need a {}-byte nonce prefix, got {}",
+                algorithm.name(),
+                algorithm.nonce_len() - NONCE_SUFFIX_LEN,
+                prefix_len[0]
+            )));
+        }
+        let mut nonce_prefix = vec![0u8; prefix_len[0] as usize];
+        reader.read_exact(&mut nonce_prefix)?;
+
+        let mut chunk_size = [0u8; 4];
+        reader.read_exact(&mut chunk_size)?;
+        let chunk_size = u32::from_be_bytes(chunk_size) as usize;
+        check_chunk_size(chunk_size)?;
+
+        Ok(Self {
+            algorithm,
+            key_id,
+            salt,
+            nonce_prefix,
+            chunk_size,
+        })
+    }
+
+    /// Derives the key that seals this stream's chunks from the context key
+    pub fn stream_key(&self, context_key: SecretKey<32>) -> Result<SecretKey<32>> {
+        let hkdf = Hkdf::<Sha256>::new(Some(&self.salt), context_key.expose_secret());
+        let mut key = SecretKey::zeroed();
+        hkdf.expand(STREAM_KEY_INFO, key.expose_secret_mut())?;
+        Ok(key)
+    }
+
+    fn chunk_nonce(&self, counter: u32, last: bool) -> Vec<u8> {
+        let mut nonce = self.nonce_prefix.clone();
+        nonce.extend_from_slice(&counter.to_be_bytes());
+        nonce.push(last as u8);
+        nonce
+    }
+}
+
+/// `Write` adapter that encrypts everything written to it in fixed-size chunks.
+///
+/// Call `finish` once all data is written; it seals the final chunk. A stream
+/// that is dropped without `finish` is missing its final chunk and will be
+/// rejected by `DecryptReader` as truncated.
+pub struct EncryptWriter<W: Write> {
+    inner: W,
+    header: StreamHeader,
+    aad: Vec<u8>,
+    key: SecretKey<32>,
+    buffer: Vec<u8>,
+    counter: u32,
+}
+
+impl<W: Write> EncryptWriter<W> {
+    /// Starts a stream under the service's active key and algorithm
+    pub fn new(service: &mut KryptorService, inner: W) -> Result<Self> {
+        Self::with_chunk_size(service, inner, DEFAULT_CHUNK_SIZE)
+    }
+
+    pub fn with_chunk_size(service: &mut KryptorService, mut inner: W, chunk_size: usize) -> Result<Self> {
+        check_chunk_size(chunk_size)?;
+        let algorithm = stream_algorithm(service.algorithm())?;
+        let key_id = service.key_id().to_string();
+        let context_key = service.derive_key_for(&key_id)?;
+
+        let mut salt = [0u8; STREAM_SALT_LEN];
+        rand::rng().fill_bytes(&mut salt);
+        let mut nonce_prefix = algorithm.generate_nonce();
+        nonce_prefix.truncate(algorithm.nonce_len() - NONCE_SUFFIX_LEN);
+
+        let header = StreamHeader {
+            algorithm,
+            key_id,
+            salt,
+            nonce_prefix,
+            chunk_size,
+        };
+        let key = header.stream_key(context_key)?;
+        let aad = header.to_bytes();
+        inner.write_all(&aad)?;
+
+        Ok(Self {
+            inner,
+            header,
+            aad,
+            key,
+            buffer: Vec::with_capacity(chunk_size),
+            counter: 0,
+        })
+    }
+
+    /// Seals the final chunk, flushes, and returns the inner writer
+    pub fn finish(mut self) -> Result<W> {
+        self.seal_chunk(true)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn seal_chunk(&mut self, last: bool) -> Result<()> {
+        let nonce = self.header.chunk_nonce(self.counter, last);
+        let sealed = self.header.algorithm.seal(&self.key, &nonce, &self.buffer, &self.aad)?;
+        self.inner.write_all(&sealed)?;
+        self.buffer.clear();
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or_else(|| EncryptionError::Other("stream exceeds the maximum chunk count".to_string()))?;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for EncryptWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+        // A full buffer is only sealed once more data arrives, because the
+        // last chunk has to be flagged as such and may itself be full.
+        if self.buffer.len() == self.header.chunk_size {
+            self.seal_chunk(false).map_err(to_io_error)?;
+        }
+        let take = data.len().min(self.header.chunk_size - self.buffer.len());
+        self.buffer.extend_from_slice(&data[..take]);
+        Ok(take)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// `Read` adapter that decrypts and verifies a stream written by `EncryptWriter`.
+///
+/// Plaintext is released chunk by chunk, only after that chunk authenticated.
+/// A stream that ends without its final chunk yields an `InvalidData` error.
+pub struct DecryptReader<R: Read> {
+    inner: R,
+    header: StreamHeader,
+    aad: Vec<u8>,
+    key: SecretKey<32>,
+    pending: Vec<u8>,
+    plaintext: Vec<u8>,
+    position: usize,
+    counter: u32,
+    finished: bool,
+}
+
+impl<R: Read> DecryptReader<R> {
+    /// Reads the stream header and selects the key it names from the service's keyring
+    pub fn new(service: &mut KryptorService, mut inner: R) -> Result<Self> {
+        let header = StreamHeader::read_from(&mut inner)?;
+        let status = service
+            .keyring()
+            .get(&header.key_id)
+            .map(|entry| entry.status)
+            .ok_or_else(|| EncryptionError::UnknownKey(header.key_id.clone()))?;
+        if status == KeyStatus::Retired {
+            return Err(EncryptionError::KeyRetired(header.key_id.clone()));
+        }
+        let key = header.stream_key(service.derive_key_for(&header.key_id)?)?;
+        let aad = header.to_bytes();
+
+        Ok(Self {
+            inner,
+            pending: Vec::with_capacity(header.chunk_size + TAG_LEN + 1),
+            header,
+            aad,
+            key,
+            plaintext: Vec::new(),
+            position: 0,
+            counter: 0,
+            finished: false,
+        })
+    }
+
+    pub fn header(&self) -> &StreamHeader {
+        &self.header
+    }
+
+    /// Decrypts the next chunk into `plaintext`. One byte past a full chunk is
+    /// read ahead to tell whether this chunk is the last one.
+    fn next_chunk(&mut self) -> Result<()> {
+        let sealed_len = self.header.chunk_size + TAG_LEN;
+        while self.pending.len() <= sealed_len {
+            let start = self.pending.len();
+            self.pending.resize(sealed_len + 1, 0);
+            let read = self.inner.read(&mut self.pending[start..])?;
+            self.pending.truncate(start + read);
+            if read == 0 {
+                break;
+            }
+        }
+
+        let last = self.pending.len() <= sealed_len;
+        if last && self.pending.len() < TAG_LEN {
+            return Err(EncryptionError::InvalidEnvelope("stream truncated before its final chunk".to_string()));
+        }
+
+        let chunk_len = if last { self.pending.len() } else { sealed_len };
+        let nonce = self.header.chunk_nonce(self.counter, last);
+        self.plaintext = self
+            .header
+            .algorithm
+            .open(&self.key, &nonce, &self.pending[..chunk_len], &self.aad)?;
+        self.pending.drain(..chunk_len);
+        self.position = 0;
+        self.finished = last;
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or_else(|| EncryptionError::Other("stream exceeds the maximum chunk count".to_string()))?;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DecryptReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.plaintext.len() {
+            if self.finished || out.is_empty() {
+                return Ok(0);
+            }
+            self.next_chunk().map_err(to_io_error)?;
+        }
+
+        let take = out.len().min(self.plaintext.len() - self.position);
+        out[..take].copy_from_slice(&self.plaintext[self.position..self.position + take]);
+        self.position += take;
+        Ok(take)
+    }
+}
+
+/// Encrypts everything from `reader` into `writer`, returning the plaintext byte count
+pub fn encrypt_stream<R: Read, W: Write>(service: &mut KryptorService, reader: &mut R, writer: W) -> Result<u64> {
+    let mut encryptor = EncryptWriter::new(service, writer)?;
+    let copied = io::copy(reader, &mut encryptor).map_err(from_io_error)?;
+    encryptor.finish()?;
+    Ok(copied)
+}
+
+/// Decrypts a stream from `reader` into `writer`, returning the plaintext byte count
+pub fn decrypt_stream<R: Read, W: Write>(service: &mut KryptorService, reader: R, writer: &mut W) -> Result<u64> {
+    let mut decryptor = DecryptReader::new(service, reader)?;
+    io::copy(&mut decryptor, writer).map_err(from_io_error)
+}
+
+fn stream_algorithm(algorithm: Algorithm) -> Result<Algorithm> {
+    if algorithm.is_deterministic() || algorithm.is_public_key() {
+        return Err(EncryptionError::Other(format!(
+            "{} cannot be used for streaming encryption",
+            algorithm.name()
+        )));
+    }
+    Ok(algorithm)
+}
+
+fn check_chunk_size(chunk_size: usize) -> Result<()> {
+    if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+        return Err(EncryptionError::InvalidEnvelope(format!(
+            "chunk size must be 1-{} bytes, got {}",
+            MAX_CHUNK_SIZE, chunk_size
+        )));
+    }
+    Ok(())
+}
+
+fn to_io_error(err: EncryptionError) -> io::Error {
+    match err {
+        EncryptionError::IoError(e) => e,
+        other => io::Error::new(io::ErrorKind::InvalidData, other),
+    }
+}
+
+/// Recovers the `EncryptionError` that `to_io_error` wrapped, so a tampered
+/// stream surfaces as a decryption failure rather than an I/O error
+fn from_io_error(err: io::Error) -> EncryptionError {
+    if err.get_ref().is_some_and(|inner| inner.is::<EncryptionError>()) {
+        let inner = err.into_inner().expect("checked above");
+        return *inner.downcast::<EncryptionError>().expect("checked above");
+    }
+    EncryptionError::IoError(err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_IKM: &str = "rph2pwTQCx+TD/lk+7o9igzQw5A7FU3+S+Z24Cf9Duk=";
+
+    fn encrypt(service: &mut KryptorService, plaintext: &[u8], chunk_size: usize) -> Result<Vec<u8>> {
+        let mut writer = EncryptWriter::with_chunk_size(service, Vec::new(), chunk_size)?;
+        writer.write_all(plaintext)?;
+        writer.finish()
+    }
+
+    fn decrypt(service: &mut KryptorService, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        let mut plaintext = Vec::new();
+        decrypt_stream(service, ciphertext, &mut plaintext)?;
+        Ok(plaintext)
+    }
+
+    #[test]
+    fn test_stream_roundtrip_across_chunk_boundaries() -> Result<()> {
+        for algorithm in [Algorithm::Aes256Gcm, Algorithm::XChaCha20Poly1305] {
+            let mut service = KryptorService::with_context(TEST_IKM.to_string(), &"export")?.with_algorithm(algorithm);
+            for len in [0, 1, 15, 16, 17, 64, 100] {
+                let plaintext: Vec<u8> = (0..len).map(|i| i as u8).collect();
+                let ciphertext = encrypt(&mut service, &plaintext, 16)?;
+                assert_eq!(decrypt(&mut service, &ciphertext)?, plaintext);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncation_and_reordering_are_detected() -> Result<()> {
+        let mut service = KryptorService::with_context(TEST_IKM.to_string(), &"export")?;
+        let plaintext = vec![7u8; 40];
+        let ciphertext = encrypt(&mut service, &plaintext, 16)?;
+        let header_len = StreamHeader::read_from(&mut ciphertext.as_slice())?.to_bytes().len();
+        let sealed = 16 + TAG_LEN;
+
+        // Drop the final chunk: the previous chunk is not flagged as last
+        let truncated = &ciphertext[..header_len + 2 * sealed];
+        assert!(decrypt(&mut service, truncated).is_err());
+
+        // Swap the first two chunks
+        let mut reordered = ciphertext[..header_len].to_vec();
+        reordered.extend_from_slice(&ciphertext[header_len + sealed..header_len + 2 * sealed]);
+        reordered.extend_from_slice(&ciphertext[header_len..header_len + sealed]);
+        reordered.extend_from_slice(&ciphertext[header_len + 2 * sealed..]);
+        assert!(decrypt(&mut service, &reordered).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_streams_with_identical_plaintext_use_distinct_keys() -> Result<()> {
+        let mut service = KryptorService::with_context(TEST_IKM.to_string(), &"export")?;
+        let plaintext = vec![7u8; 40];
+        let first = encrypt(&mut service, &plaintext, 16)?;
+        let second = encrypt(&mut service, &plaintext, 16)?;
+
+        let first_header = StreamHeader::read_from(&mut first.as_slice())?;
+        let second_header = StreamHeader::read_from(&mut second.as_slice())?;
+        assert_ne!(first_header.salt, second_header.salt);
+
+        let first_key = first_header.stream_key(service.derive_key()?)?;
+        let second_key = second_header.stream_key(service.derive_key()?)?;
+        assert_ne!(first_key.expose_secret(), second_key.expose_secret());
+
+        // Even under a colliding nonce the two streams seal different ciphertexts
+        let nonce = first_header.chunk_nonce(0, false);
+        let algorithm = first_header.algorithm;
+        assert_ne!(
+            algorithm.seal(&first_key, &nonce, &plaintext, &[])?,
+            algorithm.seal(&second_key, &nonce, &plaintext, &[])?
+        );
+        Ok(())
+    }
+}
@@ -1,124 +1,1057 @@
-use aes_gcm::aead::{Aead, KeyInit, OsRng, rand_core::RngCore};
-use aes_gcm::{Aes256Gcm, Key, Nonce};
 use base64::{Engine as _, engine::general_purpose};
 use hkdf::Hkdf;
 use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use sha2::Sha256;
 
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::kryptor::algorithm::Algorithm;
+use crate::kryptor::compression::{Compression, CompressionPolicy};
+use crate::kryptor::config::AppConfig;
+use crate::kryptor::encoding::TextEncoding;
+use crate::kryptor::envelope::{ContentType, Envelope, EnvelopeHeader, LEGACY_IV_LEN};
 use crate::kryptor::errors::EncryptionError;
+use crate::kryptor::keyring::{KeyStatus, Keyring};
+use crate::kryptor::kms::KeyManagementService;
+use crate::kryptor::recipients::{self, PackageBody, Recipient, WrappedKey};
+use crate::kryptor::secret::{SecretBytes, SecretKey, SecretString};
+use crate::kryptor::serialization::SerializationFormat;
 
 pub type Result<T> = std::result::Result<T, EncryptionError>;
 
-#[derive(Debug, Clone)]
+/// Key id recorded in envelopes produced by a single-IKM service
+pub const DEFAULT_KEY_ID: &str = "default";
+
+/// Derived keys are cached in zeroizing buffers and redacted from `Debug`.
+/// The keyring is shared, so per-package services never copy the IKMs.
+#[derive(Debug)]
 pub struct KryptorService {
-    ikm_base64: String,
+    keyring: Arc<Keyring>,
     context_base64: String,
-    derived_key: Option<[u8; 32]>,
+    algorithm: Algorithm,
+    encoding: TextEncoding,
+    format: SerializationFormat,
+    compression: CompressionPolicy,
+    /// Source of package DEKs; without one they are wrapped under the keyring
+    kms: Option<Arc<dyn KeyManagementService>>,
+    derived_keys: HashMap<String, SecretKey<32>>,
 }
 
+/// Ciphertext package with its context. `data` is always standard base64,
+/// whatever `TextEncoding` the producing service uses.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EncryptedData {
     pub data: String,
     pub context: String,
+    /// Wrapped DEKs of a multi-recipient package; empty for single-key packages
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recipients: Vec<recipients::WrappedKey>,
+}
+
+/// `EncryptedData` with the ciphertext as raw envelope bytes, for BLOB columns
+/// or binary files
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawEncryptedData {
+    pub data: Vec<u8>,
+    pub context: String,
+    pub recipients: Vec<recipients::WrappedKey>,
+}
+
+impl TryFrom<&EncryptedData> for RawEncryptedData {
+    type Error = EncryptionError;
+
+    fn try_from(package: &EncryptedData) -> Result<Self> {
+        Ok(Self {
+            data: general_purpose::STANDARD.decode(&package.data)?,
+            context: package.context.clone(),
+            recipients: package.recipients.clone(),
+        })
+    }
+}
+
+impl From<RawEncryptedData> for EncryptedData {
+    fn from(raw: RawEncryptedData) -> Self {
+        Self {
+            data: general_purpose::STANDARD.encode(&raw.data),
+            context: raw.context,
+            recipients: raw.recipients,
+        }
+    }
+}
+
+/// Step-by-step construction of a `KryptorService`; errors surface from `build`
+#[derive(Debug, Default)]
+pub struct KryptorServiceBuilder {
+    keyring: Option<Arc<Keyring>>,
+    context_base64: Option<String>,
+    algorithm: Algorithm,
+    encoding: TextEncoding,
+    format: SerializationFormat,
+    compression: CompressionPolicy,
+    kms: Option<Arc<dyn KeyManagementService>>,
+    error: Option<EncryptionError>,
+}
+
+impl KryptorServiceBuilder {
+    pub fn keyring(mut self, keyring: impl Into<Arc<Keyring>>) -> Self {
+        self.keyring = Some(keyring.into());
+        self
+    }
+
+    /// Uses the keyring loaded into `config`, and its KMS if one is set
+    pub fn config(mut self, config: &AppConfig) -> Self {
+        self.kms = config.kms.clone().or(self.kms);
+        self.keyring(Arc::clone(&config.keyring))
+    }
+
+    /// Uses a single base64 IKM under `DEFAULT_KEY_ID`
+    pub fn ikm(self, ikm_base64: impl Into<SecretString>) -> Self {
+        self.keyring(Keyring::default_key(ikm_base64))
+    }
+
+    /// Serializes `context` to JSON; it becomes the HKDF info
+    pub fn context<T: Serialize>(mut self, context: &T) -> Self {
+        match serde_json::to_vec(context) {
+            Ok(json) => self.context_base64 = Some(general_purpose::STANDARD.encode(json)),
+            Err(e) => self.error = Some(e.into()),
+        }
+        self
+    }
+
+    /// Context already encoded, e.g. taken from an `EncryptedData` package
+    pub fn context_base64(mut self, context_base64: impl Into<String>) -> Self {
+        self.context_base64 = Some(context_base64.into());
+        self
+    }
+
+    pub fn algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    pub fn encoding(mut self, encoding: TextEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    pub fn format(mut self, format: SerializationFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn compression(mut self, policy: CompressionPolicy) -> Self {
+        self.compression = policy;
+        self
+    }
+
+    /// Takes package DEKs from `kms`, see `KryptorService::with_kms`
+    pub fn kms(mut self, kms: Arc<dyn KeyManagementService>) -> Self {
+        self.kms = Some(kms);
+        self
+    }
+
+    pub fn build(self) -> Result<KryptorService> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        let keyring = self.keyring.ok_or_else(|| {
+            EncryptionError::InvalidConfig("no key: set a keyring, config or ikm".to_string())
+        })?;
+        let context_base64 = self
+            .context_base64
+            .ok_or_else(|| EncryptionError::InvalidConfig("no encryption context set".to_string()))?;
+        let service = KryptorService::from_keyring(keyring, context_base64)
+            .with_algorithm(self.algorithm)
+            .with_encoding(self.encoding)
+            .with_format(self.format)
+            .with_compression(self.compression);
+        Ok(match self.kms {
+            Some(kms) => service.with_kms(kms),
+            None => service,
+        })
+    }
 }
 
 impl KryptorService {
+    pub fn builder() -> KryptorServiceBuilder {
+        KryptorServiceBuilder::default()
+    }
+
     pub fn new(ikm_base64: String, context_base64: String) -> Self {
+        Self::from_keyring(Keyring::default_key(ikm_base64), context_base64)
+    }
+
+    /// Creates a service that encrypts under the keyring's active key and
+    /// decrypts with whichever key id the ciphertext records
+    pub fn from_keyring(keyring: impl Into<Arc<Keyring>>, context_base64: String) -> Self {
         Self {
-            ikm_base64,
+            keyring: keyring.into(),
             context_base64,
-            derived_key: None,
+            algorithm: Algorithm::default(),
+            encoding: TextEncoding::default(),
+            format: SerializationFormat::default(),
+            compression: CompressionPolicy::default(),
+            kms: None,
+            derived_keys: HashMap::new(),
         }
     }
 
+    /// Selects the AEAD used for new encryptions. Decryption always follows
+    /// the algorithm recorded in the ciphertext's envelope.
+    pub fn with_algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// Selects the text encoding of the ciphertexts taken and returned as
+    /// `String`. Legacy blobs and `EncryptedData` stay standard base64.
+    pub fn with_encoding(mut self, encoding: TextEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    pub fn encoding(&self) -> TextEncoding {
+        self.encoding
+    }
+
+    /// Selects the serialization used by `encrypt_value`; `encrypt_json`
+    /// always writes JSON
+    pub fn with_format(mut self, format: SerializationFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn format(&self) -> SerializationFormat {
+        self.format
+    }
+
+    /// Compresses new JSON, value and byte payloads before encryption as
+    /// `policy` allows. Decryption always follows the envelope flags.
+    pub fn with_compression(mut self, policy: CompressionPolicy) -> Self {
+        self.compression = policy;
+        self
+    }
+
+    pub fn compression(&self) -> &CompressionPolicy {
+        &self.compression
+    }
+
+    /// Envelope encryption for packages: each one gets a fresh DEK from
+    /// `generate_data_key`, stored wrapped under the KMS key instead of the
+    /// keyring. Packages are unwrapped through the KMS first, then the
+    /// keyring; bare ciphertexts still use the keyring.
+    pub fn with_kms(mut self, kms: Arc<dyn KeyManagementService>) -> Self {
+        self.kms = Some(kms);
+        self
+    }
+
+    pub fn kms(&self) -> Option<&Arc<dyn KeyManagementService>> {
+        self.kms.as_ref()
+    }
+
     pub fn with_context<T: Serialize>(ikm_base64: String, context: &T) -> Result<Self> {
+        Self::with_keyring(Keyring::default_key(ikm_base64), context)
+    }
+
+    pub fn with_keyring<T: Serialize>(keyring: impl Into<Arc<Keyring>>, context: &T) -> Result<Self> {
         let context_json = serde_json::to_string(context)?;
         let context_base64 = general_purpose::STANDARD.encode(&context_json);
-        Ok(Self::new(ikm_base64, context_base64))
+        Ok(Self::from_keyring(keyring, context_base64))
+    }
+
+    /// Id of the key used for new encryptions
+    pub fn key_id(&self) -> &str {
+        &self.keyring.active().key_id
+    }
+
+    pub fn keyring(&self) -> &Keyring {
+        &self.keyring
+    }
+
+    /// The keyring as shared with the services this one spawns
+    pub fn shared_keyring(&self) -> &Arc<Keyring> {
+        &self.keyring
+    }
+
+    pub fn context_base64(&self) -> &str {
+        &self.context_base64
     }
 
-    /// Derives a 256-bit (32-byte) AES key using HKDF-SHA256
-    pub fn derive_key(&mut self) -> Result<[u8; 32]> {
-        if let Some(key) = self.derived_key {
-            return Ok(key);
+    /// Derives a 256-bit (32-byte) AES key from the active IKM using HKDF-SHA256
+    pub fn derive_key(&mut self) -> Result<SecretKey<32>> {
+        let key_id = self.key_id().to_string();
+        self.derive_key_for(&key_id)
+    }
+
+    /// Derives the 256-bit key for a specific keyring entry using HKDF-SHA256.
+    /// The returned key is an explicit copy of the cached one.
+    pub fn derive_key_for(&mut self, key_id: &str) -> Result<SecretKey<32>> {
+        if let Some(key) = self.derived_keys.get(key_id) {
+            return Ok(key.duplicate());
         }
 
-        let ikm = general_purpose::STANDARD.decode(&self.ikm_base64)?;
+        let entry = self
+            .keyring
+            .get(key_id)
+            .ok_or_else(|| EncryptionError::UnknownKey(key_id.to_string()))?;
+
+        let ikm = entry.decode_ikm()?;
         let info = general_purpose::STANDARD.decode(&self.context_base64)?;
 
-        let hkdf = Hkdf::<Sha256>::new(None, &ikm);
-        let mut key = [0u8; 32];
-        hkdf.expand(&info, &mut key)?;
+        let hkdf = Hkdf::<Sha256>::new(None, ikm.expose_secret());
+        let mut key = SecretKey::zeroed();
+        hkdf.expand(&info, key.expose_secret_mut())?;
 
-        self.derived_key = Some(key);
+        self.derived_keys.insert(key_id.to_string(), key.duplicate());
         Ok(key)
     }
 
     /// Generic method to encrypt any serializable type
     pub fn encrypt_json<T: Serialize>(&mut self, data: &T) -> Result<String> {
-        let json_string = serde_json::to_string(data)?;
-        let json_base64 = general_purpose::STANDARD.encode(&json_string);
-        self.encrypt_bytes(json_base64.as_bytes())
+        self.encrypt_json_with_aad(data, &[])
+    }
+
+    /// Encrypts any serializable type, binding the ciphertext to `aad`.
+    /// The JSON bytes are encrypted directly in a v2 envelope.
+    pub fn encrypt_json_with_aad<T: Serialize>(&mut self, data: &T, aad: &[u8]) -> Result<String> {
+        let envelope = self.encrypt_json_to_vec_with_aad(data, aad)?;
+        Ok(self.encoding.encode(&envelope))
+    }
+
+    /// Like `encrypt_json`, returning the raw envelope bytes
+    pub fn encrypt_json_to_vec<T: Serialize>(&mut self, data: &T) -> Result<Vec<u8>> {
+        self.encrypt_json_to_vec_with_aad(data, &[])
+    }
+
+    pub fn encrypt_json_to_vec_with_aad<T: Serialize>(&mut self, data: &T, aad: &[u8]) -> Result<Vec<u8>> {
+        self.encrypt_with_format(SerializationFormat::Json, data, aad)
     }
 
     /// Generic method to decrypt and deserialize to any type
-    pub fn decrypt_json<T: DeserializeOwned>(&mut self, encrypted_base64: &str) -> Result<T> {
-        let decrypted_bytes = self.decrypt_bytes(encrypted_base64)?;
-        let json_base64 = String::from_utf8(decrypted_bytes)?;
-        let json_bytes = general_purpose::STANDARD.decode(&json_base64)?;
-        let json_string = String::from_utf8(json_bytes)?;
-        let data: T = serde_json::from_str(&json_string)?;
-        Ok(data)
+    pub fn decrypt_json<T: DeserializeOwned>(&mut self, encrypted: &str) -> Result<T> {
+        self.decrypt_json_with_aad(encrypted, &[])
+    }
+
+    /// Decrypts a ciphertext bound to `aad`; fails if the associated data differs.
+    /// v1 envelopes written by older builds hold base64 text of the JSON and
+    /// are detected from the header. Like `decrypt_value`, it reads any format.
+    pub fn decrypt_json_with_aad<T: DeserializeOwned>(&mut self, encrypted: &str, aad: &[u8]) -> Result<T> {
+        let data = self.encoding.decode(encrypted)?;
+        self.decrypt_json_from_slice_with_aad(&data, aad)
+    }
+
+    /// Like `decrypt_json`, taking the raw envelope bytes
+    pub fn decrypt_json_from_slice<T: DeserializeOwned>(&mut self, data: &[u8]) -> Result<T> {
+        self.decrypt_json_from_slice_with_aad(data, &[])
+    }
+
+    pub fn decrypt_json_from_slice_with_aad<T: DeserializeOwned>(&mut self, data: &[u8], aad: &[u8]) -> Result<T> {
+        self.decrypt_value_from_slice_with_aad(data, aad)
+    }
+
+    /// Serializes `data` with the service's `SerializationFormat` and encrypts it
+    pub fn encrypt_value<T: Serialize>(&mut self, data: &T) -> Result<String> {
+        self.encrypt_value_with_aad(data, &[])
+    }
+
+    pub fn encrypt_value_with_aad<T: Serialize>(&mut self, data: &T, aad: &[u8]) -> Result<String> {
+        let envelope = self.encrypt_value_to_vec_with_aad(data, aad)?;
+        Ok(self.encoding.encode(&envelope))
+    }
+
+    pub fn encrypt_value_to_vec<T: Serialize>(&mut self, data: &T) -> Result<Vec<u8>> {
+        self.encrypt_value_to_vec_with_aad(data, &[])
+    }
+
+    pub fn encrypt_value_to_vec_with_aad<T: Serialize>(&mut self, data: &T, aad: &[u8]) -> Result<Vec<u8>> {
+        self.encrypt_with_format(self.format, data, aad)
+    }
+
+    /// Decrypts and deserializes with the format recorded in the envelope,
+    /// whatever this service's own format is
+    pub fn decrypt_value<T: DeserializeOwned>(&mut self, encrypted: &str) -> Result<T> {
+        self.decrypt_value_with_aad(encrypted, &[])
+    }
+
+    pub fn decrypt_value_with_aad<T: DeserializeOwned>(&mut self, encrypted: &str, aad: &[u8]) -> Result<T> {
+        let data = self.encoding.decode(encrypted)?;
+        self.decrypt_value_from_slice_with_aad(&data, aad)
+    }
+
+    pub fn decrypt_value_from_slice<T: DeserializeOwned>(&mut self, data: &[u8]) -> Result<T> {
+        self.decrypt_value_from_slice_with_aad(data, &[])
+    }
+
+    pub fn decrypt_value_from_slice_with_aad<T: DeserializeOwned>(&mut self, data: &[u8], aad: &[u8]) -> Result<T> {
+        let (header, plaintext) = self.open(data, aad, false)?;
+        match header.content_type {
+            None => from_base64_json(plaintext),
+            // Opaque bytes are read as JSON, matching `encrypt_bytes` of a JSON document
+            Some(content_type) => SerializationFormat::from_content_type(content_type)
+                .unwrap_or_default()
+                .deserialize(&plaintext),
+        }
     }
 
-    /// Encrypts raw bytes using AES-GCM with a random 12-byte IV.
-    /// Returns base64-encoded string of [IV | Ciphertext | Tag]
+    fn encrypt_with_format<T: Serialize>(
+        &mut self,
+        format: SerializationFormat,
+        data: &T,
+        aad: &[u8],
+    ) -> Result<Vec<u8>> {
+        let payload = format.serialize(data)?;
+        self.seal(&payload, aad, Some(format.content_type()))
+    }
+
+    /// Decrypts a bare legacy ciphertext produced before the envelope format
+    /// was introduced, then deserializes it
+    pub fn decrypt_legacy_json<T: DeserializeOwned>(&mut self, encrypted_base64: &str) -> Result<T> {
+        from_base64_json(self.decrypt_legacy_bytes(encrypted_base64)?)
+    }
+
+    /// Encrypts raw bytes with the service's AEAD (AES-256-GCM by default) and a random nonce.
+    /// Returns the text-encoded envelope of [Header | Ciphertext | Tag],
+    /// where the header records the format version, algorithm, key id and nonce.
     pub fn encrypt_bytes(&mut self, plaintext: &[u8]) -> Result<String> {
-        let key = self.derive_key()?;
-        let mut iv = [0u8; 12];
-        OsRng.fill_bytes(&mut iv);
+        self.encrypt_bytes_with_aad(plaintext, &[])
+    }
 
-        let key_arr = Key::<Aes256Gcm>::from_slice(&key);
-        let cipher = Aes256Gcm::new(key_arr);
-        let nonce = Nonce::from_slice(&iv);
+    /// Encrypts raw bytes and authenticates `aad` alongside the envelope header.
+    /// The associated data is not stored; the same bytes must be supplied to decrypt.
+    pub fn encrypt_bytes_with_aad(&mut self, plaintext: &[u8], aad: &[u8]) -> Result<String> {
+        let envelope = self.encrypt_bytes_to_vec_with_aad(plaintext, aad)?;
+        Ok(self.encoding.encode(&envelope))
+    }
 
-        let ciphertext = cipher.encrypt(nonce, plaintext)?;
+    /// Like `encrypt_bytes`, returning the raw envelope bytes
+    pub fn encrypt_bytes_to_vec(&mut self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        self.encrypt_bytes_to_vec_with_aad(plaintext, &[])
+    }
 
-        let mut result = Vec::new();
-        result.extend_from_slice(&iv);
-        result.extend_from_slice(&ciphertext);
+    pub fn encrypt_bytes_to_vec_with_aad(&mut self, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        self.seal(plaintext, aad, Some(ContentType::Bytes))
+    }
 
-        Ok(general_purpose::STANDARD.encode(&result))
+    /// Appends the raw envelope to `out` and returns the number of bytes written
+    pub fn encrypt_bytes_into(&mut self, plaintext: &[u8], aad: &[u8], out: &mut Vec<u8>) -> Result<usize> {
+        let envelope = self.encrypt_bytes_to_vec_with_aad(plaintext, aad)?;
+        out.extend_from_slice(&envelope);
+        Ok(envelope.len())
     }
 
-    /// Decrypts AES-GCM-encrypted data from a base64 input containing [IV | Ciphertext | Tag]
-    pub fn decrypt_bytes(&mut self, encoded_b64: &str) -> Result<Vec<u8>> {
-        let key = self.derive_key()?;
-        let data = general_purpose::STANDARD.decode(encoded_b64)?;
-        let (iv, ciphertext_and_tag) = data.split_at(12); // 12-byte IV
+    /// Decrypts a text-encoded envelope produced by `encrypt_bytes`, using
+    /// the keyring entry named in its header.
+    /// Bare legacy blobs are rejected; use `decrypt_legacy_bytes` for those.
+    pub fn decrypt_bytes(&mut self, encoded: &str) -> Result<Vec<u8>> {
+        self.decrypt_bytes_with_aad(encoded, &[])
+    }
 
-        let key_arr = Key::<Aes256Gcm>::from_slice(&key);
-        let cipher = Aes256Gcm::new(key_arr);
-        let nonce = Nonce::from_slice(iv);
+    /// Decrypts an envelope bound to `aad`; fails if the associated data differs
+    pub fn decrypt_bytes_with_aad(&mut self, encoded: &str, aad: &[u8]) -> Result<Vec<u8>> {
+        let data = self.encoding.decode(encoded)?;
+        self.decrypt_bytes_from_slice_with_aad(&data, aad)
+    }
 
-        let plaintext = cipher.decrypt(nonce, ciphertext_and_tag)?;
+    /// Like `decrypt_bytes`, taking the raw envelope bytes
+    pub fn decrypt_bytes_from_slice(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        self.decrypt_bytes_from_slice_with_aad(data, &[])
+    }
 
+    pub fn decrypt_bytes_from_slice_with_aad(&mut self, data: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        let (_, plaintext) = self.open(data, aad, false)?;
         Ok(plaintext)
     }
 
+    /// Appends the plaintext of a raw envelope to `out` and returns its length
+    pub fn decrypt_bytes_into(&mut self, data: &[u8], aad: &[u8], out: &mut Vec<u8>) -> Result<usize> {
+        let plaintext = self.decrypt_bytes_from_slice_with_aad(data, aad)?;
+        out.extend_from_slice(&plaintext);
+        Ok(plaintext.len())
+    }
+
+    /// Decrypts a bare legacy AES-GCM blob from a base64 input containing [IV | Ciphertext | Tag].
+    /// Legacy blobs carry no key id, so every non-retired key is tried, active key first.
+    /// They only ever existed as standard base64, so the service's encoding is ignored.
+    pub fn decrypt_legacy_bytes(&mut self, encoded_b64: &str) -> Result<Vec<u8>> {
+        let data = general_purpose::STANDARD.decode(encoded_b64)?;
+        self.decrypt_legacy_bytes_from_slice(&data)
+    }
+
+    /// Like `decrypt_legacy_bytes`, taking the raw [IV | Ciphertext | Tag] bytes
+    pub fn decrypt_legacy_bytes_from_slice(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        self.open_legacy(data, false)
+    }
+
+    /// Encrypts under the active key and returns the raw envelope bytes.
+    /// The AEAD associated data is the encoded header followed by `aad`.
+    /// A `content_type` selects a v2 envelope; `None` writes v1, which is
+    /// never compressed.
+    pub(crate) fn seal(&mut self, plaintext: &[u8], aad: &[u8], content_type: Option<ContentType>) -> Result<Vec<u8>> {
+        let compression = match content_type {
+            Some(_) => self.compression.select(&self.context_base64, plaintext.len()),
+            None => Compression::None,
+        };
+        self.seal_with(plaintext, aad, self.algorithm, content_type, compression)
+    }
+
+    /// Like `seal` with an explicit algorithm and codec instead of the
+    /// service's. The plaintext stays uncompressed when compressing does not
+    /// shrink it.
+    pub(crate) fn seal_with(
+        &mut self,
+        plaintext: &[u8],
+        aad: &[u8],
+        algorithm: Algorithm,
+        content_type: Option<ContentType>,
+        compression: Compression,
+    ) -> Result<Vec<u8>> {
+        let key_id = self.key_id().to_string();
+        let key = self.derive_key_for(&key_id)?;
+        seal_envelope(&key, key_id, plaintext, aad, algorithm, content_type, compression)
+    }
+
+    /// Parses and decrypts raw envelope bytes. Retired keys are only accepted
+    /// when `allow_retired` is set, which is reserved for rewrap jobs.
+    pub(crate) fn open(&mut self, data: &[u8], aad: &[u8], allow_retired: bool) -> Result<(EnvelopeHeader, Vec<u8>)> {
+        if !Envelope::is_envelope(data) {
+            return Err(EncryptionError::InvalidEnvelope(
+                "missing magic prefix; use decrypt_legacy_bytes for pre-envelope ciphertexts".to_string(),
+            ));
+        }
+
+        let envelope = Envelope::parse(data)?;
+        let status = self
+            .keyring
+            .get(&envelope.header.key_id)
+            .map(|entry| entry.status)
+            .ok_or_else(|| EncryptionError::UnknownKey(envelope.header.key_id.clone()))?;
+        if status == KeyStatus::Retired && !allow_retired {
+            return Err(EncryptionError::KeyRetired(envelope.header.key_id.clone()));
+        }
+
+        let key = self.derive_key_for(&envelope.header.key_id)?;
+        let plaintext = open_envelope(&key, &envelope, aad, self.compression.max_decompressed_len())?;
+        Ok((envelope.header, plaintext))
+    }
+
+    /// Decrypts raw legacy AES-GCM [IV | Ciphertext | Tag] bytes by trial over the keyring
+    pub(crate) fn open_legacy(&mut self, data: &[u8], allow_retired: bool) -> Result<Vec<u8>> {
+        if data.len() < LEGACY_IV_LEN {
+            return Err(EncryptionError::InvalidEnvelope("legacy ciphertext is shorter than its IV".to_string()));
+        }
+        let (iv, ciphertext_and_tag) = data.split_at(LEGACY_IV_LEN);
+
+        let mut key_ids = vec![self.key_id().to_string()];
+        key_ids.extend(
+            self.keyring
+                .entries()
+                .iter()
+                .filter(|entry| {
+                    entry.status == KeyStatus::DecryptOnly || (allow_retired && entry.status == KeyStatus::Retired)
+                })
+                .map(|entry| entry.key_id.clone()),
+        );
+
+        let mut last_error = None;
+        for key_id in key_ids {
+            let key = self.derive_key_for(&key_id)?;
+            match Algorithm::Aes256Gcm.open(&key, iv, ciphertext_and_tag, &[]) {
+                Ok(plaintext) => return Ok(plaintext),
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        Err(last_error.expect("keyring always holds an active key"))
+    }
+
     /// Creates an EncryptedData structure with both encrypted data and context
     pub fn create_encrypted_package<T: Serialize>(&mut self, data: &T) -> Result<EncryptedData> {
-        let encrypted_data = self.encrypt_json(data)?;
-        Ok(EncryptedData {
-            data: encrypted_data,
+        self.create_encrypted_package_with_aad(data, &[])
+    }
+
+    /// Creates an EncryptedData package bound to `aad`
+    pub fn create_encrypted_package_with_aad<T: Serialize>(&mut self, data: &T, aad: &[u8]) -> Result<EncryptedData> {
+        if self.kms.is_some() {
+            return self.create_encrypted_package_for_with_aad(data, &[], aad);
+        }
+        Ok(self.create_encrypted_package_raw_with_aad(data, aad)?.into())
+    }
+
+    /// Like `create_encrypted_package_with_aad`, keeping the ciphertext as raw bytes
+    pub fn create_encrypted_package_raw_with_aad<T: Serialize>(
+        &mut self,
+        data: &T,
+        aad: &[u8],
+    ) -> Result<RawEncryptedData> {
+        if self.kms.is_some() {
+            return RawEncryptedData::try_from(&self.create_encrypted_package_for_with_aad(data, &[], aad)?);
+        }
+        Ok(RawEncryptedData {
+            data: self.encrypt_json_to_vec_with_aad(data, aad)?,
             context: self.context_base64.clone(),
+            recipients: Vec::new(),
         })
     }
 
+    /// Creates a package readable by this service and every one of `recipients`.
+    /// The body is encrypted once under a random DEK that is wrapped per recipient,
+    /// and for this service under the KMS key when one is set.
+    pub fn create_encrypted_package_for<T: Serialize>(
+        &mut self,
+        data: &T,
+        recipients: &[Recipient],
+    ) -> Result<EncryptedData> {
+        self.create_encrypted_package_for_with_aad(data, recipients, &[])
+    }
+
+    pub fn create_encrypted_package_for_with_aad<T: Serialize>(
+        &mut self,
+        data: &T,
+        recipients: &[Recipient],
+        aad: &[u8],
+    ) -> Result<EncryptedData> {
+        if let Some(kms) = &self.kms {
+            let data_key = kms.generate_data_key(&self.context_base64)?;
+            return recipients::create_package_with_dek(
+                data,
+                &self.context_base64,
+                &data_key.plaintext,
+                vec![data_key.wrapped],
+                recipients,
+                aad,
+                &self.package_body(),
+            );
+        }
+        let mut all = vec![Recipient::Symmetric(Arc::clone(&self.keyring))];
+        all.extend_from_slice(recipients);
+        recipients::create_package(data, &self.context_base64, &all, aad, &self.package_body())
+    }
+
+    /// Wraps the DEK of a multi-recipient package for one more recipient;
+    /// the body is left untouched. This service must be able to read the package.
+    pub fn add_recipient(&mut self, package: &mut EncryptedData, recipient: &Recipient) -> Result<()> {
+        let mut service = self.package_service(package.context.clone());
+        recipients::add_recipient(package, recipient, |wrapped| service.unwrap_package_key(wrapped))
+    }
+
     /// Decrypts an EncryptedData package
     pub fn decrypt_package<T: DeserializeOwned>(&mut self, package: &EncryptedData) -> Result<T> {
+        self.decrypt_package_with_aad(package, &[])
+    }
+
+    /// Decrypts an EncryptedData package bound to `aad`
+    pub fn decrypt_package_with_aad<T: DeserializeOwned>(&mut self, package: &EncryptedData, aad: &[u8]) -> Result<T> {
         // Create a new service with the package's context
-        let mut service = Self::new(self.ikm_base64.clone(), package.context.clone());
-        service.decrypt_json(&package.data)
+        let mut service = self.package_service(package.context.clone());
+        if package.is_multi_recipient() {
+            let max_decompressed_len = self.compression.max_decompressed_len();
+            return recipients::decrypt_package(package, aad, max_decompressed_len, |wrapped| {
+                service.unwrap_package_key(wrapped)
+            });
+        }
+        service.decrypt_json_with_aad(&package.data, aad)
+    }
+
+    /// Decrypts a package whose ciphertext is held as raw bytes
+    pub fn decrypt_package_raw_with_aad<T: DeserializeOwned>(
+        &mut self,
+        package: &RawEncryptedData,
+        aad: &[u8],
+    ) -> Result<T> {
+        if !package.recipients.is_empty() {
+            return self.decrypt_package_with_aad(&EncryptedData::from(package.clone()), aad);
+        }
+        let mut service = self.package_service(package.context.clone());
+        service.decrypt_json_from_slice_with_aad(&package.data, aad)
+    }
+
+    /// Algorithm, format and compression of multi-recipient package bodies,
+    /// the same as `encrypt_value` uses
+    fn package_body(&self) -> PackageBody {
+        PackageBody {
+            algorithm: self.algorithm,
+            format: self.format,
+            compression: self.compression.clone(),
+        }
+    }
+
+    /// Service sharing this one's keys and decompression limit under a package's context
+    fn package_service(&self, context_base64: String) -> Self {
+        Self {
+            kms: self.kms.clone(),
+            compression: self.compression.clone(),
+            ..Self::from_keyring(Arc::clone(&self.keyring), context_base64)
+        }
+    }
+
+    /// Unwraps a package DEK through the KMS, falling back to the keyring
+    fn unwrap_package_key(&mut self, wrapped: &WrappedKey) -> Result<SecretBytes> {
+        if let Some(kms) = &self.kms {
+            match kms.unwrap(wrapped, &self.context_base64) {
+                Err(EncryptionError::UnknownKey(_)) => {}
+                unwrapped => return unwrapped,
+            }
+        }
+        recipients::unwrap_symmetric(self, wrapped)
+    }
+}
+
+/// Seals `plaintext` under `key` into raw envelope bytes. The AEAD associated
+/// data is the encoded header followed by `aad`. The plaintext stays
+/// uncompressed when compressing does not shrink it.
+pub(crate) fn seal_envelope(
+    key: &SecretKey<32>,
+    key_id: String,
+    plaintext: &[u8],
+    aad: &[u8],
+    algorithm: Algorithm,
+    content_type: Option<ContentType>,
+    compression: Compression,
+) -> Result<Vec<u8>> {
+    let mut header = EnvelopeHeader::new(algorithm, key_id, algorithm.generate_nonce())?;
+    let mut compressed = None;
+    if let Some(content_type) = content_type {
+        header = header.with_content_type(content_type);
+        if compression != Compression::None {
+            let packed = compression.compress(plaintext)?;
+            if packed.len() < plaintext.len() {
+                header = header.with_compression(compression);
+                compressed = Some(packed);
+            }
+        }
+    }
+    let mut full_aad = header.to_bytes();
+    full_aad.extend_from_slice(aad);
+
+    let body = compressed.as_deref().unwrap_or(plaintext);
+    let ciphertext = algorithm.seal(key, &header.nonce, body, &full_aad)?;
+
+    Ok(Envelope { header, ciphertext }.to_bytes())
+}
+
+/// Decrypts a parsed envelope under `key` and inflates a compressed
+/// plaintext up to `max_decompressed_len` bytes
+pub(crate) fn open_envelope(
+    key: &SecretKey<32>,
+    envelope: &Envelope,
+    aad: &[u8],
+    max_decompressed_len: usize,
+) -> Result<Vec<u8>> {
+    let header = &envelope.header;
+    let mut full_aad = header.to_bytes();
+    full_aad.extend_from_slice(aad);
+
+    let plaintext = header
+        .algorithm
+        .open(key, &header.nonce, &envelope.ciphertext, &full_aad)?;
+    // Only decompressed once authenticated, so only key holders can feed the codec
+    match header.compression()? {
+        Compression::None => Ok(plaintext),
+        compression => compression.decompress(&plaintext, max_decompressed_len),
+    }
+}
+
+/// Reads the pre-v2 JSON encoding: base64 text of the serialized JSON
+fn from_base64_json<T: DeserializeOwned>(plaintext: Vec<u8>) -> Result<T> {
+    let json_base64 = String::from_utf8(plaintext)?;
+    let json_bytes = general_purpose::STANDARD.decode(&json_base64)?;
+    let json_string = String::from_utf8(json_bytes)?;
+    Ok(serde_json::from_str(&json_string)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::kryptor::envelope::FORMAT_V2;
+    use crate::kryptor::keyring::KeyEntry;
+    use crate::models::EventStore;
+
+    const TEST_IKM: &str = "rph2pwTQCx+TD/lk+7o9igzQw5A7FU3+S+Z24Cf9Duk=";
+    const OTHER_IKM: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+
+    #[test]
+    fn test_envelope_records_key_and_algorithm() -> Result<()> {
+        let mut service = KryptorService::with_keyring(Keyring::single("k1", TEST_IKM)?, &"ctx")?;
+        let encrypted = service.encrypt_bytes(b"hello")?;
+
+        let raw = general_purpose::STANDARD.decode(&encrypted)?;
+        let envelope = Envelope::parse(&raw)?;
+        assert_eq!(envelope.header.algorithm, Algorithm::Aes256Gcm);
+        assert_eq!(envelope.header.key_id, "k1");
+
+        assert_eq!(service.decrypt_bytes(&encrypted)?, b"hello");
+        Ok(())
+    }
+
+    #[test]
+    fn test_tampered_header_fails_authentication() -> Result<()> {
+        let mut service = KryptorService::with_context(TEST_IKM.to_string(), &"ctx")?;
+        let encrypted = service.encrypt_bytes(b"hello")?;
+
+        let mut raw = general_purpose::STANDARD.decode(&encrypted)?;
+        let last_nonce_byte = raw.len() - 5 - 16 - 1;
+        raw[last_nonce_byte] ^= 0x01;
+        let tampered = general_purpose::STANDARD.encode(&raw);
+
+        assert!(matches!(
+            service.decrypt_bytes(&tampered),
+            Err(EncryptionError::DecryptionError(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_legacy_blob_requires_explicit_path() -> Result<()> {
+        let mut service = KryptorService::with_context(TEST_IKM.to_string(), &"ctx")?;
+        let key = service.derive_key()?;
+
+        // Produce a pre-envelope blob: base64([IV | Ciphertext | Tag]) of base64(JSON)
+        let iv = [3u8; LEGACY_IV_LEN];
+        let inner = general_purpose::STANDARD.encode("\"legacy\"");
+        let mut blob = iv.to_vec();
+        blob.extend(Algorithm::Aes256Gcm.seal(&key, &iv, inner.as_bytes(), &[])?);
+        let legacy = general_purpose::STANDARD.encode(&blob);
+
+        assert!(service.decrypt_json::<String>(&legacy).is_err());
+        let value: String = service.decrypt_legacy_json(&legacy)?;
+        assert_eq!(value, "legacy");
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_is_encrypted_once_and_v1_stays_readable() -> Result<()> {
+        let mut service = KryptorService::with_context(TEST_IKM.to_string(), &"ctx")?;
+        let value = serde_json::json!({ "name": "Alice", "tags": ["a", "b"] });
+
+        let encrypted = service.encrypt_json_with_aad(&value, b"aad")?;
+        let header = Envelope::parse(&general_purpose::STANDARD.decode(&encrypted)?)?.header;
+        assert_eq!(header.version, FORMAT_V2);
+        assert_eq!(header.content_type, Some(ContentType::Json));
+        let decrypted: serde_json::Value = service.decrypt_json_with_aad(&encrypted, b"aad")?;
+        assert_eq!(decrypted, value);
+
+        // A v1 envelope as older builds wrote it: base64 of the JSON text inside
+        let inner = general_purpose::STANDARD.encode(serde_json::to_string(&value)?);
+        let v1 = general_purpose::STANDARD.encode(service.seal(inner.as_bytes(), b"aad", None)?);
+        assert!(encrypted.len() < v1.len());
+        let decrypted: serde_json::Value = service.decrypt_json_with_aad(&v1, b"aad")?;
+        assert_eq!(decrypted, value);
+        Ok(())
+    }
+
+    #[test]
+    fn test_binary_and_text_encodings_share_one_envelope() -> Result<()> {
+        let mut service = KryptorService::with_context(TEST_IKM.to_string(), &"ctx")?;
+        let raw = service.encrypt_bytes_to_vec_with_aad(b"blob", b"aad")?;
+        assert!(Envelope::is_envelope(&raw));
+        assert_eq!(service.decrypt_bytes_from_slice_with_aad(&raw, b"aad")?, b"blob");
+
+        let mut buffer = b"prefix".to_vec();
+        let written = service.encrypt_bytes_into(b"blob", b"aad", &mut buffer)?;
+        assert_eq!(buffer.len(), 6 + written);
+        let mut plaintext = Vec::new();
+        service.decrypt_bytes_into(&buffer[6..], b"aad", &mut plaintext)?;
+        assert_eq!(plaintext, b"blob");
+
+        for encoding in [TextEncoding::Base64UrlNoPad, TextEncoding::Hex] {
+            let mut service = KryptorService::builder()
+                .ikm(TEST_IKM)
+                .context(&"ctx")
+                .encoding(encoding)
+                .build()?;
+            let encrypted = service.encrypt_json(&"value")?;
+            assert_eq!(encoding.decode(&encrypted)?.len(), service.encrypt_json_to_vec(&"value")?.len());
+            assert_eq!(service.decrypt_json::<String>(&encrypted)?, "value");
+        }
+
+        // Packages stay standard base64 and convert to and from raw bytes
+        let package = service.create_encrypted_package_raw_with_aad(&"pkg", b"aad")?;
+        let text = EncryptedData::from(package.clone());
+        assert_eq!(RawEncryptedData::try_from(&text)?, package);
+        assert_eq!(service.decrypt_package_with_aad::<String>(&text, b"aad")?, "pkg");
+        assert_eq!(service.decrypt_package_raw_with_aad::<String>(&package, b"aad")?, "pkg");
+        Ok(())
+    }
+
+    #[test]
+    fn test_value_format_is_read_from_the_envelope() -> Result<()> {
+        let value = serde_json::json!({ "name": "Alice", "phones": ["+254712345678"] });
+        let mut reader = KryptorService::with_context(TEST_IKM.to_string(), &"ctx")?;
+
+        for format in [
+            SerializationFormat::Json,
+            SerializationFormat::Cbor,
+            SerializationFormat::MessagePack,
+        ] {
+            let mut writer = KryptorService::with_context(TEST_IKM.to_string(), &"ctx")?.with_format(format);
+            let encrypted = writer.encrypt_value_with_aad(&value, b"aad")?;
+            let header = Envelope::parse(&general_purpose::STANDARD.decode(&encrypted)?)?.header;
+            assert_eq!(header.content_type, Some(format.content_type()));
+
+            let decrypted: serde_json::Value = reader.decrypt_value_with_aad(&encrypted, b"aad")?;
+            assert_eq!(decrypted, value);
+            let decrypted: serde_json::Value = reader.decrypt_json_with_aad(&encrypted, b"aad")?;
+            assert_eq!(decrypted, value);
+        }
+
+        let mut writer = KryptorService::builder()
+            .ikm(TEST_IKM)
+            .context(&"ctx")
+            .format(SerializationFormat::Bincode)
+            .build()?;
+        let encrypted = writer.encrypt_value_to_vec(&(7u32, "seven".to_string()))?;
+        assert_eq!(reader.decrypt_value_from_slice::<(u32, String)>(&encrypted)?, (7, "seven".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_compression_is_opt_in_and_recorded_in_the_envelope() -> Result<()> {
+        let metadata = serde_json::json!({ "history": vec!["login from 10.0.0.1"; 200] });
+        let policy = CompressionPolicy::new(Compression::Zstd).exclude_context(&"secrets")?;
+        let mut plain = KryptorService::with_context(TEST_IKM.to_string(), &"ctx")?;
+        let mut compressing = KryptorService::with_context(TEST_IKM.to_string(), &"ctx")?.with_compression(policy.clone());
+
+        let large = compressing.encrypt_json_to_vec(&metadata)?;
+        assert_eq!(Envelope::parse(&large)?.header.compression()?, Compression::Zstd);
+        assert!(large.len() < plain.encrypt_json_to_vec(&metadata)?.len() / 10);
+        assert_eq!(plain.decrypt_json_from_slice::<serde_json::Value>(&large)?, metadata);
+
+        // Below the threshold, and under an excluded context, nothing is compressed
+        let small = compressing.encrypt_json_to_vec(&"short")?;
+        assert_eq!(Envelope::parse(&small)?.header.compression()?, Compression::None);
+        let mut excluded = KryptorService::with_context(TEST_IKM.to_string(), &"secrets")?.with_compression(policy);
+        let secret = excluded.encrypt_json_to_vec(&metadata)?;
+        assert_eq!(Envelope::parse(&secret)?.header.compression()?, Compression::None);
+
+        // The flag is authenticated: clearing it fails decryption
+        let mut stripped = large.clone();
+        stripped[6] = 0;
+        assert!(plain.decrypt_json_from_slice::<serde_json::Value>(&stripped).is_err());
+
+        // Readers refuse payloads that inflate past their limit
+        let mut capped = KryptorService::with_context(TEST_IKM.to_string(), &"ctx")?
+            .with_compression(CompressionPolicy::disabled().with_max_decompressed_len(1024));
+        assert!(matches!(
+            capped.decrypt_json_from_slice::<serde_json::Value>(&large),
+            Err(EncryptionError::CodecError(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_package_decryption_keeps_the_decompression_limit() -> Result<()> {
+        let metadata = serde_json::json!({ "history": vec!["login from 10.0.0.1"; 200] });
+        let mut writer = KryptorService::with_context(TEST_IKM.to_string(), &"ctx")?
+            .with_compression(CompressionPolicy::new(Compression::Zstd));
+        let package = writer.create_encrypted_package(&metadata)?;
+        let raw = writer.create_encrypted_package_raw_with_aad(&metadata, &[])?;
+
+        let mut capped = KryptorService::with_context(TEST_IKM.to_string(), &"other")?
+            .with_compression(CompressionPolicy::disabled().with_max_decompressed_len(1024));
+        assert!(matches!(
+            capped.decrypt_package::<serde_json::Value>(&package),
+            Err(EncryptionError::CodecError(_))
+        ));
+        assert!(matches!(
+            capped.decrypt_package_raw_with_aad::<serde_json::Value>(&raw, &[]),
+            Err(EncryptionError::CodecError(_))
+        ));
+
+        let mut uncapped = KryptorService::with_context(TEST_IKM.to_string(), &"other")?;
+        assert_eq!(uncapped.decrypt_package::<serde_json::Value>(&package)?, metadata);
+        assert_eq!(uncapped.decrypt_package_raw_with_aad::<serde_json::Value>(&raw, &[])?, metadata);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rotation_keeps_old_ciphertexts_readable() -> Result<()> {
+        let mut keyring = Keyring::single("k1", TEST_IKM)?;
+        let mut service = KryptorService::with_keyring(keyring.duplicate(), &"ctx")?;
+        let before_rotation = service.encrypt_json(&"payload")?;
+
+        keyring.rotate(KeyEntry::new("k2", OTHER_IKM, KeyStatus::Active))?;
+        let mut service = KryptorService::with_keyring(keyring.duplicate(), &"ctx")?;
+        let after_rotation = service.encrypt_json(&"payload")?;
+
+        let raw = general_purpose::STANDARD.decode(&after_rotation)?;
+        assert_eq!(Envelope::parse(&raw)?.header.key_id, "k2");
+        assert_eq!(service.decrypt_json::<String>(&before_rotation)?, "payload");
+        assert_eq!(service.decrypt_json::<String>(&after_rotation)?, "payload");
+
+        keyring.set_status("k1", KeyStatus::Retired)?;
+        let mut service = KryptorService::with_keyring(keyring, &"ctx")?;
+        assert!(matches!(
+            service.decrypt_json::<String>(&before_rotation),
+            Err(EncryptionError::KeyRetired(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_aad_binds_ciphertext_to_event_metadata() -> Result<()> {
+        let mut service = KryptorService::with_context(TEST_IKM.to_string(), &"aggregate-1")?;
+        let event = EventStore::new("aggregate-1".to_string(), "Profile".to_string(), 3, serde_json::Value::Null);
+        let encrypted = service.encrypt_json_with_aad(&"payload", &event.associated_data())?;
+
+        let value: String = service.decrypt_json_with_aad(&encrypted, &event.associated_data())?;
+        assert_eq!(value, "payload");
+
+        let moved = EventStore::new("aggregate-1".to_string(), "Profile".to_string(), 4, serde_json::Value::Null);
+        assert!(service.decrypt_json_with_aad::<String>(&encrypted, &moved.associated_data()).is_err());
+        assert!(service.decrypt_json::<String>(&encrypted).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_algorithm_is_recorded_and_used_for_decryption() -> Result<()> {
+        let mut aes = KryptorService::with_context(TEST_IKM.to_string(), &"ctx")?;
+        let mut xchacha =
+            KryptorService::with_context(TEST_IKM.to_string(), &"ctx")?.with_algorithm(Algorithm::XChaCha20Poly1305);
+
+        let encrypted = xchacha.encrypt_json(&"payload")?;
+        let raw = general_purpose::STANDARD.decode(&encrypted)?;
+        let header = Envelope::parse(&raw)?.header;
+        assert_eq!(header.algorithm, Algorithm::XChaCha20Poly1305);
+        assert_eq!(header.nonce.len(), 24);
+
+        // A service configured for another algorithm still reads it
+        assert_eq!(aes.decrypt_json::<String>(&encrypted)?, "payload");
+        Ok(())
+    }
+
+    #[test]
+    fn test_debug_redacts_key_material() -> Result<()> {
+        let mut service = KryptorService::with_context(TEST_IKM.to_string(), &"ctx")?;
+        service.encrypt_bytes(b"warm the key cache")?;
+
+        let debug = format!("{:?}", service);
+        assert!(!debug.contains(TEST_IKM));
+        assert!(debug.contains("[REDACTED]"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_builder_matches_constructors() -> Result<()> {
+        let mut built = KryptorService::builder()
+            .ikm(TEST_IKM)
+            .context(&"ctx")
+            .algorithm(Algorithm::ChaCha20Poly1305)
+            .build()?;
+        let mut service = KryptorService::with_context(TEST_IKM.to_string(), &"ctx")?;
+        assert_eq!(built.context_base64(), service.context_base64());
+        assert_eq!(service.decrypt_json::<i32>(&built.encrypt_json(&7)?)?, 7);
+
+        assert!(matches!(
+            KryptorService::builder().context(&"ctx").build(),
+            Err(EncryptionError::InvalidConfig(_))
+        ));
+        assert!(KryptorService::builder().ikm(TEST_IKM).build().is_err());
+        Ok(())
     }
 }
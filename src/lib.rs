@@ -0,0 +1,32 @@
+//! Context-bound encryption for serializable data.
+//!
+//! `KryptorService` derives a key per context with HKDF and seals data in a
+//! versioned envelope. The `kryptor` module holds the building blocks (key
+//! rotation, field-level and public-key encryption, streaming), and
+//! `event_store` persists encrypted `EventStore` records.
+//!
+//! ```no_run
+//! use encry::{AppConfig, EncryptionContext, KryptorService};
+//!
+//! let config = AppConfig::load()?;
+//! let mut service = KryptorService::builder()
+//!     .config(&config)
+//!     .context(&EncryptionContext::new("user:42".to_string()))
+//!     .build()?;
+//! let encrypted = service.encrypt_json(&"secret")?;
+//! let decrypted: String = service.decrypt_json(&encrypted)?;
+//! # Ok::<(), encry::EncryptionError>(())
+//! ```
+
+// Lets `#[derive(EncryptFields)]` name this crate as `::encry` from inside it
+extern crate self as encry;
+
+pub mod event_store;
+pub mod examples;
+pub mod kryptor;
+pub mod models;
+
+pub use kryptor::config::AppConfig;
+pub use kryptor::errors::EncryptionError;
+pub use kryptor::utilities::{EncryptedData, KryptorService, KryptorServiceBuilder};
+pub use models::EncryptionContext;
@@ -1,13 +1,19 @@
-mod examples;
-mod kryptor;
-mod models;
-
-use examples::demonstrate_advanced_encryption;
-use models::{EncryptionContext, EventStore, Profile};
+mod cli;
+
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use clap::Parser;
+use cli::{Cli, Command};
+use encry::examples::demonstrate_advanced_encryption;
+use encry::kryptor::{
+    fields::{EncryptFields, FieldEncryptor},
+    shredding::{InMemoryKeyStore, ShreddingKryptor},
+};
+use encry::models::{EventStore, Profile};
+use encry::{AppConfig, EncryptionContext, EncryptionError, KryptorService};
 use uuid::{NoContext, Timestamp, Uuid};
 
-use crate::kryptor::{config::AppConfig, errors::EncryptionError, utilities::KryptorService};
-
 fn create_sample_profile() -> Profile {
     Profile::new(
         "Mwaura S W".to_string(),
@@ -22,9 +28,7 @@ fn generate_aggregate_key() -> String {
     Uuid::new_v7(ts).to_string()
 }
 
-fn demonstrate_encryption() -> Result<(), EncryptionError> {
-    // Configuration
-    let config = AppConfig::new();
+fn demonstrate_encryption(config: AppConfig) -> Result<(), EncryptionError> {
     let aggregate_key = generate_aggregate_key();
 
     // Create sample data
@@ -35,7 +39,10 @@ fn demonstrate_encryption() -> Result<(), EncryptionError> {
     let context = EncryptionContext::new(aggregate_key);
 
     // Create encryption service
-    let mut kryptor_service = KryptorService::with_context(config.ikm_base64, &context)?;
+    let mut kryptor_service = KryptorService::builder()
+        .config(&config)
+        .context(&context)
+        .build()?;
 
     println!("=== Original Data ===");
     let json_string = serde_json::to_string_pretty(&event_store)?;
@@ -71,14 +78,45 @@ fn demonstrate_encryption() -> Result<(), EncryptionError> {
     let _recovered_data: EventStore = kryptor_service.decrypt_package(&package)?;
     println!("Successfully recovered data from package");
 
+    // Bind the payload ciphertext to its row metadata
+    println!("\n=== Associated Data Demo ===");
+    let aad = event_store.associated_data();
+    let encrypted_payload = kryptor_service.encrypt_json_with_aad(&event_store.payload, &aad)?;
+    let _payload: serde_json::Value = kryptor_service.decrypt_json_with_aad(&encrypted_payload, &aad)?;
+    println!("Payload bound to aggregate {} version {}", event_store.aggregated_key, event_store.version);
+
+    // Encrypt individual fields, keeping row metadata queryable
+    println!("\n=== Field-Level Encryption Demo ===");
+    let mut field_encryptor = FieldEncryptor::with_keyring(Arc::clone(&config.keyring), &context)?;
+    let encrypted_profile = create_sample_profile().encrypt_fields(&mut field_encryptor)?;
+    println!("{}", serde_json::to_string_pretty(&encrypted_profile)?);
+    let _profile = Profile::decrypt_fields(&encrypted_profile, &mut field_encryptor)?;
+
+    let encrypted_event = event_store.encrypt_fields(&mut field_encryptor)?;
+    println!(
+        "Event {} v{} stored with encrypted payload",
+        encrypted_event.aggregate_type, encrypted_event.version
+    );
+    let _event = EventStore::decrypt_fields(&encrypted_event, &mut field_encryptor)?;
+
+    // Forget the aggregate by deleting its own key
+    println!("\n=== Crypto-Shredding Demo ===");
+    let mut shredder = ShreddingKryptor::with_keyring(InMemoryKeyStore::new(), config.keyring);
+    let erasable_payload = shredder.encrypt_json_with_aad(&event_store.aggregated_key, &event_store.payload, &aad)?;
+    shredder.shred(&event_store.aggregated_key)?;
+    match shredder.decrypt_json_with_aad::<serde_json::Value>(&event_store.aggregated_key, &erasable_payload, &aad) {
+        Err(e @ EncryptionError::KeyShredded(_)) => println!("{}", e),
+        _ => println!("❌ Shredded payload is still readable!"),
+    }
+
     Ok(())
 }
 
-fn main() {
+fn run_demos(config: AppConfig) {
     println!("🔐 Encryption Service Demo\n");
 
     // Run basic encryption demo
-    match demonstrate_encryption() {
+    match demonstrate_encryption(config.clone()) {
         Ok(()) => println!("✅ Basic encryption demo completed successfully!"),
         Err(e) => eprintln!("❌ Basic encryption error: {}", e),
     }
@@ -86,8 +124,29 @@ fn main() {
     println!("\n{}", "=".repeat(60));
 
     // Run advanced encryption examples
-    match demonstrate_advanced_encryption() {
+    match demonstrate_advanced_encryption(config) {
         Ok(()) => println!("\n🎉 All advanced encryption operations completed successfully!"),
         Err(e) => eprintln!("❌ Advanced encryption error: {}", e),
     }
 }
+
+fn main() -> ExitCode {
+    match Cli::parse().command {
+        Command::Demo(args) => {
+            let config = if args.dev {
+                AppConfig::development()
+            } else {
+                match AppConfig::load() {
+                    Ok(config) => config,
+                    Err(e) => {
+                        eprintln!("encry: {}", e);
+                        return ExitCode::from(cli::exit_code(&e));
+                    }
+                }
+            };
+            run_demos(config);
+            ExitCode::SUCCESS
+        }
+        command => cli::execute(command),
+    }
+}
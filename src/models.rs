@@ -1,18 +1,27 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::kryptor::fields::EncryptFields;
+
+#[derive(Debug, Clone, Serialize, Deserialize, EncryptFields)]
 pub struct EventStore {
     pub aggregated_key: String,
     pub aggregate_type: String,
     pub version: i32,
+    #[encrypt]
     pub payload: serde_json::Value,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, EncryptFields)]
 pub struct Profile {
+    #[encrypt]
     pub name: String,
+    #[encrypt]
     pub dob: String,
+    #[encrypt(deterministic)]
+    #[blind_index(normalize = "email")]
     pub email: String,
+    #[encrypt]
+    #[blind_index(name = "phone", normalize = "e164")]
     pub phones: Vec<String>,
 }
 
@@ -37,6 +46,14 @@ impl EventStore {
         }
     }
 
+    /// Row metadata to bind a payload ciphertext to as associated data, so an
+    /// encrypted payload cannot be replayed under another aggregate or version
+    pub fn associated_data(&self) -> Vec<u8> {
+        serde_json::json!([self.aggregated_key, self.aggregate_type, self.version])
+            .to_string()
+            .into_bytes()
+    }
+
     pub fn with_profile(aggregated_key: String, profile: Profile) -> Self {
         let payload = serde_json::to_value(profile).unwrap_or(serde_json::Value::Null);
         Self::new(aggregated_key, "Profile".to_string(), 0, payload)